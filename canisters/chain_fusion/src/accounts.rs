@@ -0,0 +1,247 @@
+//! Manages the EVM accounts of the canister. Every account is derived from the canister's
+//! t-ECDSA key with its own derivation path, so that users or purposes can be given their
+//! own address. The nonce and last known balance are tracked per account.
+use std::collections::BTreeSet;
+
+use candid::{CandidType, Deserialize, Principal};
+use ethers_core::types::U256;
+use evm_rpc_canister_types::{BlockTag, GetTransactionCountArgs};
+use ic_evm_utils::{
+    conversions::{nat_to_u256, u256_to_nat},
+    eth_call::EthCallError,
+    eth_get_balance::get_balance,
+    eth_get_transaction_count::get_transaction_count,
+    evm_rpc_client::EvmRpcClient,
+    evm_signer::{pubkey_bytes_to_address, Signer},
+};
+use serde_bytes::ByteBuf;
+
+use crate::state::{mutate_state, read_state, State};
+
+pub type DerivationPath = Vec<Vec<u8>>;
+
+/// The derivation path of the canister's default account, used for submitting job results.
+pub const DEFAULT_DERIVATION_PATH: DerivationPath = Vec::new();
+
+/// The maximum number of accounts principals can derive for themselves, which bounds the
+/// memory and cycles spent on accounts. Controllers can derive accounts beyond it.
+pub const MAX_ACCOUNTS: usize = 10_000;
+
+/// State of a single derived EVM account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub address: String,
+    /// The nonce of the next transaction sent from the account.
+    pub nonce: U256,
+    /// The nonces below `nonce` that were reserved for transactions that were not signed,
+    /// see `State::release_nonce`.
+    pub unused_nonces: BTreeSet<U256>,
+    /// The balance of the account in wei, as of the last refresh.
+    pub balance: Option<U256>,
+}
+
+/// Who or what an account is derived for.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccountOwner {
+    /// An account per principal, e.g. a user of the canister.
+    Principal(Principal),
+    /// An account per arbitrary tag, e.g. a job type or purpose.
+    Tag(String),
+}
+
+impl AccountOwner {
+    /// Returns the derivation path of the owner's account. The first element separates
+    /// the namespaces, so that a tag can never collide with a principal.
+    pub fn derivation_path(&self) -> DerivationPath {
+        match self {
+            AccountOwner::Principal(principal) => {
+                vec![b"principal".to_vec(), principal.as_slice().to_vec()]
+            }
+            AccountOwner::Tag(tag) => vec![b"tag".to_vec(), tag.as_bytes().to_vec()],
+        }
+    }
+}
+
+/// Candid representation of an account, returned by the account endpoints.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AccountView {
+    pub derivation_path: Vec<ByteBuf>,
+    pub address: String,
+    pub nonce: candid::Nat,
+    pub balance: Option<candid::Nat>,
+}
+
+impl AccountView {
+    fn new(derivation_path: &[Vec<u8>], account: &Account) -> Self {
+        Self {
+            derivation_path: derivation_path.iter().cloned().map(ByteBuf::from).collect(),
+            address: account.address.clone(),
+            nonce: u256_to_nat(account.nonce),
+            balance: account.balance.map(u256_to_nat),
+        }
+    }
+}
+
+/// Returns the account of `derivation_path`, deriving its address from the canister's
+/// public key if the account is not known yet.
+pub async fn get_or_create_account(derivation_path: DerivationPath) -> Account {
+    if let Some(account) = read_state(|s| s.accounts.get(&derivation_path).cloned()) {
+        return account;
+    }
+    let signer = read_state(State::signer);
+    let public_key = signer.public_key(&derivation_path).await;
+    let address = pubkey_bytes_to_address(&public_key);
+    mutate_state(|s| {
+        s.accounts
+            .entry(derivation_path)
+            .or_insert(Account {
+                address,
+                nonce: U256::zero(),
+                unused_nonces: BTreeSet::new(),
+                balance: None,
+            })
+            .clone()
+    })
+}
+
+/// Whether a principal may derive the account of `derivation_path`, which is the case if
+/// it exists already or the limit of `MAX_ACCOUNTS` was not reached yet.
+pub fn can_create_account(derivation_path: &[Vec<u8>]) -> bool {
+    read_state(|s| s.accounts.contains_key(derivation_path) || s.accounts.len() < MAX_ACCOUNTS)
+}
+
+/// Raises the nonce of the account of `derivation_path` to its transaction count, as the
+/// nonces tracked on the heap start over when the canister is upgraded. Nonces reserved by
+/// transactions sent in the meantime are kept.
+pub async fn sync_nonce(derivation_path: DerivationPath, evm_rpc: impl EvmRpcClient) {
+    let account = get_or_create_account(derivation_path.clone()).await;
+    let transaction_count = get_transaction_count(
+        read_state(State::rpc_services),
+        GetTransactionCountArgs {
+            address: account.address,
+            block: BlockTag::Latest,
+        },
+        evm_rpc,
+    )
+    .await;
+    let transaction_count = nat_to_u256(&transaction_count);
+    mutate_state(|s| {
+        if let Some(account) = s.accounts.get_mut(&derivation_path) {
+            account.nonce = account.nonce.max(transaction_count);
+            account
+                .unused_nonces
+                .retain(|nonce| *nonce >= transaction_count);
+        }
+    });
+}
+
+/// Reads the current balance of the account of `derivation_path` and records it.
+pub async fn refresh_balance(
+    derivation_path: DerivationPath,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<U256, EthCallError> {
+    let account = get_or_create_account(derivation_path.clone()).await;
+    let balance = get_balance(
+        account.address,
+        "latest",
+        read_state(State::rpc_service),
+        evm_rpc,
+    )
+    .await?;
+    mutate_state(|s| {
+        if let Some(account) = s.accounts.get_mut(&derivation_path) {
+            account.balance = Some(balance);
+        }
+    });
+    Ok(balance)
+}
+
+/// Returns the account of `derivation_path`, if it was derived already.
+pub fn account_view(derivation_path: &[Vec<u8>]) -> Option<AccountView> {
+    read_state(|s| {
+        s.accounts
+            .get(derivation_path)
+            .map(|account| AccountView::new(derivation_path, account))
+    })
+}
+
+/// Returns all derived accounts.
+pub fn accounts() -> Vec<AccountView> {
+    read_state(|s| {
+        s.accounts
+            .iter()
+            .map(|(derivation_path, account)| AccountView::new(derivation_path, account))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::initialize_state, test_fixtures::init_arg};
+
+    fn init_account(nonce: u64) {
+        initialize_state(State::try_from(init_arg()).expect("init arg should be valid"));
+        mutate_state(|s| {
+            s.accounts.insert(
+                DEFAULT_DERIVATION_PATH,
+                Account {
+                    address: "0x0000000000000000000000000000000000000001".to_string(),
+                    nonce: U256::from(nonce),
+                    unused_nonces: BTreeSet::new(),
+                    balance: None,
+                },
+            )
+        });
+    }
+
+    fn reserve() -> U256 {
+        mutate_state(|s| s.reserve_nonce(&DEFAULT_DERIVATION_PATH))
+    }
+
+    fn release(nonce: u64) {
+        mutate_state(|s| s.release_nonce(&DEFAULT_DERIVATION_PATH, U256::from(nonce)));
+    }
+
+    fn account() -> Account {
+        read_state(|s| s.accounts[&DEFAULT_DERIVATION_PATH].clone())
+    }
+
+    #[test]
+    fn should_reserve_consecutive_nonces() {
+        init_account(5);
+
+        assert_eq!(reserve(), U256::from(5));
+        assert_eq!(reserve(), U256::from(6));
+        assert_eq!(account().nonce, U256::from(7));
+    }
+
+    #[test]
+    fn should_reuse_released_nonce_first() {
+        init_account(5);
+        for _ in 0..3 {
+            reserve();
+        }
+
+        release(6);
+
+        assert_eq!(reserve(), U256::from(6));
+        assert_eq!(reserve(), U256::from(8));
+    }
+
+    #[test]
+    fn should_lower_nonce_when_last_reserved_nonces_are_released() {
+        init_account(5);
+        for _ in 0..3 {
+            reserve();
+        }
+
+        release(6);
+        assert_eq!(account().nonce, U256::from(8));
+        release(7);
+        assert_eq!(account().nonce, U256::from(6));
+        assert!(account().unused_nonces.is_empty());
+        release(5);
+        assert_eq!(account().nonce, U256::from(5));
+    }
+}
@@ -1,53 +1,311 @@
+mod attest_result;
 mod calculate_result;
+mod callback;
+mod context;
+mod eth_call;
+mod handler;
+mod ledger;
+mod store;
+mod submit_batch;
 mod submit_result;
 
 use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
 
+use attest_result::attest_result;
+use calculate_result::FibonacciHandler;
 use ethers_core::types::U256;
 use evm_rpc_canister_types::LogEntry;
 use ic_cdk::println;
+use ic_evm_utils::evm_rpc_client::EvmRpcClient;
+use ledger::{admit_job, settle_jobs, Admission};
+use store::JobStatus;
+use submit_batch::{batch_result, queue_result};
 use submit_result::submit_result;
 
+pub use callback::Callback;
+pub use context::JobContext;
+pub use handler::{
+    result_to_string, ErasedJobHandler, JobError, JobEvent, JobHandler, JobHandlers, JobStep,
+};
+pub use ledger::{ledger_view, restore_ledger, resume_deferred_jobs, JobFee, Ledger, LedgerView};
+pub use store::{
+    get_attestation, get_job, list_jobs, Attestation, JobKey, JobKeyArg, JobPage, JobView,
+    ListJobsArg, Progress,
+};
+pub use submit_batch::BatchedResult;
+
 use crate::{
-    job::calculate_result::fibonacci,
-    state::{mutate_state, LogSource},
+    lifecycle::SubmissionMode,
+    state::{mutate_state, read_state, LogSource, State},
 };
 
-pub async fn job(event_source: LogSource, event: LogEntry) {
+thread_local! {
+    /// The handlers of the events that trigger jobs. Register a handler for every event
+    /// the canister subscribes to with `get_logs_topics`.
+    static JOB_HANDLERS: JobHandlers = JobHandlers::default().register(FibonacciHandler);
+}
+
+pub async fn job(event_source: LogSource, event: LogEntry, evm_rpc: impl EvmRpcClient + 'static) {
     mutate_state(|s| s.record_processed_log(event_source.clone()));
-    // because we deploy the canister with topics only matching
-    // NewJob events we can safely assume that the event is a NewJob.
-    let new_job_event = NewJobEvent::from(event);
-    // this calculation would likely exceed an ethereum blocks gas limit
-    // but can easily be calculated on the IC
-    let result = fibonacci(20);
-    // we write the result back to the evm smart contract, creating a signature
-    // on the transaction with chain key ecdsa and sending it to the evm via the
-    // evm rpc canister
-    submit_result(result.to_string(), new_job_event.job_id).await;
-    println!("Successfully ran job #{:?}", &new_job_event.job_id);
+    let Some(handler) = JOB_HANDLERS.with(|handlers| handlers.get(&event)) else {
+        println!("Skipping {event_source:?}, no handler is registered for its event");
+        return;
+    };
+    let key = match handler
+        .job_id(&event)
+        .map_err(|e| e.to_string())
+        .and_then(|job_id| JobKey::new(&event, job_id))
+    {
+        Ok(key) => key,
+        Err(e) => {
+            println!("Skipping {event_source:?}: {e}");
+            return;
+        }
+    };
+    if !store::record_job(key, &event_source, &event) {
+        println!("Skipping job {key} of {event_source:?}, it was already run");
+        return;
+    }
+
+    match admit_job(key, handler.payment(&event)) {
+        Admission::Run => {}
+        Admission::Refuse(reason) => {
+            println!("Refusing job {key}: {reason}");
+            store::record_job_outcome(key, JobStatus::Failed(reason), None, None);
+            settle_jobs(vec![key], None, evm_rpc);
+            return;
+        }
+        Admission::Defer => {
+            println!("Deferring underpaid job {key}");
+            store::record_job_outcome(key, JobStatus::Deferred, None, None);
+            return;
+        }
+    }
+
+    let context = JobContext::new(event_source, &event);
+    run_job(key, handler, &event, &context, None, evm_rpc).await;
+}
+
+/// Runs a deferred job once the surplus of other jobs covers its shortfall.
+async fn resume_job(key: JobKey, evm_rpc: impl EvmRpcClient + 'static) {
+    let Some((event_source, event)) = store::load_deferred(key) else {
+        println!("Not resuming job {key}, it is not deferred");
+        return;
+    };
+    let handler = JOB_HANDLERS
+        .with(|handlers| handlers.get(&event))
+        .expect("BUG: the handler of a deferred job should be registered");
+    store::record_job_outcome(key, JobStatus::Pending, None, None);
+    let context = JobContext::new(event_source, &event);
+    run_job(key, handler, &event, &context, None, evm_rpc).await;
+}
+
+/// Continues a chunked job from the checkpoint of its previous step.
+async fn continue_job(key: JobKey, evm_rpc: impl EvmRpcClient + 'static) {
+    let Some((event_source, event, checkpoint)) = store::load_checkpoint(key) else {
+        println!("Not continuing job {key}, it is not running");
+        return;
+    };
+    let handler = JOB_HANDLERS
+        .with(|handlers| handlers.get(&event))
+        .expect("BUG: the handler of a running job should be registered");
+    let context = JobContext::new(event_source, &event);
+    run_job(key, handler, &event, &context, Some(checkpoint), evm_rpc).await;
+}
+
+/// Schedules the next step of every running chunked job, as their timers don't survive
+/// upgrades.
+pub fn continue_running_jobs(evm_rpc: impl EvmRpcClient + 'static) {
+    for key in store::running_jobs() {
+        println!("Continuing job {key}");
+        schedule_next_step(key, evm_rpc.clone());
+    }
+}
+
+/// Queues the results of the batched jobs again, as the pending batch doesn't survive
+/// upgrades. If batch mode was turned off by the upgrade, they are sent right away.
+pub fn requeue_batched_jobs(evm_rpc: impl EvmRpcClient + 'static) {
+    let (window, max_batch_size) = match read_state(State::submission_mode) {
+        SubmissionMode::Batch {
+            window_seconds,
+            max_batch_size,
+        } => (Duration::from_secs(window_seconds), max_batch_size),
+        SubmissionMode::Callback | SubmissionMode::Attestation => (Duration::ZERO, u64::MAX),
+    };
+    for (key, event, result, encoded_result) in store::load_batched() {
+        println!("Queueing the result of job {key} again");
+        let callback = read_state(|s| s.callback(&event));
+        let result = BatchedResult {
+            key,
+            contract_address: callback.contract_address(&event).to_string(),
+            result,
+            encoded_result,
+        };
+        queue_result(result, window, max_batch_size, evm_rpc.clone());
+    }
+}
+
+/// Continues a chunked job in a new message with a fresh instruction limit.
+fn schedule_next_step(key: JobKey, evm_rpc: impl EvmRpcClient + 'static) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(continue_job(key, evm_rpc))
+    });
 }
 
+/// Runs a job, or its next step, and delivers its result once it is done. If a chunked job
+/// yields, its checkpoint is stored and the next step is run by a timer, i.e. in a new
+/// message with a fresh instruction limit.
+async fn run_job(
+    key: JobKey,
+    handler: Rc<dyn ErasedJobHandler>,
+    event: &LogEntry,
+    context: &JobContext,
+    checkpoint: Option<Vec<u8>>,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    let result = match handler.run(event, context, checkpoint).await {
+        Ok(JobStep::Done(result)) => result,
+        Ok(JobStep::Yield {
+            checkpoint,
+            progress,
+        }) => {
+            println!(
+                "Job {key} yielded at {}/{}",
+                progress.completed, progress.total
+            );
+            store::record_job_progress(key, progress, checkpoint);
+            schedule_next_step(key, evm_rpc);
+            return;
+        }
+        Err(e) => {
+            println!("Job {key} failed: {e}");
+            store::record_job_outcome(key, JobStatus::Failed(e.to_string()), None, None);
+            settle_jobs(vec![key], None, evm_rpc);
+            return;
+        }
+    };
+    let result_string = result_to_string(&result);
+    let (status, transaction_hash) = match read_state(State::submission_mode) {
+        // we write the result back to the evm smart contract, creating a signature
+        // on the transaction with chain key ecdsa and sending it to the evm via the
+        // evm rpc canister
+        SubmissionMode::Callback => {
+            match submit_result(result, key.job_id, event, evm_rpc.clone()).await {
+                Ok(transaction_hash) => (JobStatus::Submitted, Some(transaction_hash)),
+                Err(e) => {
+                    println!("Error {e}");
+                    (JobStatus::Failed(e.to_string()), None)
+                }
+            }
+        }
+        // we only sign the result, users or relayers submit it to the contract themselves
+        SubmissionMode::Attestation => {
+            attest_result(result_string.clone(), key).await;
+            (JobStatus::Attested, None)
+        }
+        // we queue the result and send it together with the results of the other jobs
+        // done within the batch window, to the contract the callback of the event is
+        // configured for
+        SubmissionMode::Batch {
+            window_seconds,
+            max_batch_size,
+        } => {
+            let callback = read_state(|s| s.callback(event));
+            batch_result(
+                key,
+                callback.contract_address(event).to_string(),
+                &result,
+                result_string.clone(),
+                Duration::from_secs(window_seconds),
+                max_batch_size,
+                evm_rpc.clone(),
+            );
+            (JobStatus::Batched, None)
+        }
+    };
+    // batched jobs are settled once their batch was sent
+    if status != JobStatus::Batched {
+        settle_jobs(vec![key], transaction_hash.clone(), evm_rpc);
+    }
+    store::record_job_outcome(key, status, Some(result_string), transaction_hash);
+    println!("Successfully ran job {key}");
+}
+
+/// The `NewJob(uint256 indexed job_id, uint256 payment)` event emitted by the
+/// `Coprocessor` contract.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct NewJobEvent {
     pub job_id: U256,
+    /// The amount of wei sent with the `newJob` call.
+    pub payment: U256,
 }
 
 impl fmt::Debug for NewJobEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("NewJobEvent")
             .field("job_id", &self.job_id)
+            .field("payment", &self.payment)
             .finish()
     }
 }
 
-impl From<LogEntry> for NewJobEvent {
-    fn from(entry: LogEntry) -> NewJobEvent {
+impl JobEvent for NewJobEvent {
+    const SIGNATURE: &'static str = "NewJob(uint256,uint256)";
+
+    fn decode(entry: &LogEntry) -> Result<Self, String> {
         // we expect exactly 2 topics from the NewJob event.
         // you can read more about event signatures [here](https://docs.alchemy.com/docs/deep-dive-into-eth_getlogs#what-are-event-signatures)
-        let job_id =
-            U256::from_str_radix(&entry.topics[1], 16).expect("the token id should be valid");
+        let topic = entry
+            .topics
+            .get(1)
+            .ok_or("the NewJob event should have the job ID as second topic")?;
+        let job_id = U256::from_str_radix(topic, 16).map_err(|e| format!("invalid job ID: {e}"))?;
+        // the payment is the only non-indexed argument, i.e. the data is a single word
+        let data = hex::decode(entry.data.trim_start_matches("0x"))
+            .map_err(|e| format!("invalid data: {e}"))?;
+        if data.len() != 32 {
+            return Err("the NewJob event should have the payment as data".to_string());
+        }
+        Ok(NewJobEvent {
+            job_id,
+            payment: U256::from_big_endian(&data),
+        })
+    }
+
+    fn job_id(&self) -> U256 {
+        self.job_id
+    }
+
+    fn payment(&self) -> Option<U256> {
+        Some(self.payment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{new_job_log, JOB_PAYMENT};
+
+    #[test]
+    fn should_decode_job_id_and_payment_of_new_job_event() {
+        let event = new_job_log(7, 16);
+        let handler = JOB_HANDLERS
+            .with(|handlers| handlers.get(&event))
+            .expect("the NewJob handler should be registered");
+
+        assert_eq!(handler.job_id(&event).unwrap(), U256::from(7));
+        assert_eq!(handler.payment(&event), Some(U256::from(JOB_PAYMENT)));
+    }
+
+    #[test]
+    fn should_reject_new_job_event_without_payment() {
+        let event = LogEntry {
+            data: "0x".to_string(),
+            ..new_job_log(7, 16)
+        };
 
-        NewJobEvent { job_id }
+        assert!(NewJobEvent::decode(&event).is_err());
     }
 }
@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+
+use ethers_core::{
+    abi::{encode, Token},
+    types::{transaction::eip712::EIP712Domain, transaction::eip712::Eip712, U256},
+    utils::keccak256,
+};
+use ic_evm_utils::{eth_send_raw_transaction::IntoChainId, evm_signer::Signer};
+
+use crate::{
+    accounts::DEFAULT_DERIVATION_PATH,
+    job::store::{self, JobKey},
+    state::{read_state, State},
+};
+
+/// The EIP-712 typed result of a job, matching `JOB_RESULT_TYPEHASH` in `Coprocessor.sol`.
+struct JobResult {
+    domain: EIP712Domain,
+    job_id: U256,
+    result: String,
+}
+
+impl Eip712 for JobResult {
+    type Error = Infallible;
+
+    fn domain(&self) -> Result<EIP712Domain, Self::Error> {
+        Ok(self.domain.clone())
+    }
+
+    fn type_hash() -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256("JobResult(uint256 jobId,string result)"))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(encode(&[
+            Token::FixedBytes(Self::type_hash()?.to_vec()),
+            Token::Uint(self.job_id),
+            Token::FixedBytes(keccak256(&self.result).to_vec()),
+        ])))
+    }
+}
+
+/// Signs the result of a job with EIP-712 for the contract that emitted its event and
+/// stores the signature with the job, so that it can be served by the `get_attestation`
+/// query and submitted to the contract by anyone.
+pub async fn attest_result(result: String, key: JobKey) {
+    // get necessary global state
+    let chain_id = read_state(State::rpc_services).chain_id();
+    let signer = read_state(State::signer);
+
+    // the domain of the `Coprocessor` contract, see its `EIP712` constructor
+    let domain = EIP712Domain {
+        name: Some("Coprocessor".to_string()),
+        version: Some("1".to_string()),
+        chain_id: Some(U256::from(chain_id.as_u64())),
+        verifying_contract: Some(key.contract_address),
+        salt: None,
+    };
+
+    let job_result = JobResult {
+        domain,
+        job_id: key.job_id,
+        result,
+    };
+
+    let signature = match signer
+        .sign_typed_data(&job_result, DEFAULT_DERIVATION_PATH)
+        .await
+    {
+        Ok(signature) => signature,
+        Err(e) => match e {},
+    };
+
+    store::record_job_signature(key, signature.to_vec());
+}
@@ -1,3 +1,19 @@
+use crate::job::{JobContext, JobError, JobHandler, NewJobEvent};
+
+/// Handles `NewJob` events by computing the 20th fibonacci number.
+pub struct FibonacciHandler;
+
+impl JobHandler for FibonacciHandler {
+    type Event = NewJobEvent;
+    type Output = String;
+
+    async fn handle(&self, _event: NewJobEvent, _context: &JobContext) -> Result<String, JobError> {
+        // this calculation would likely exceed an ethereum blocks gas limit
+        // but can easily be calculated on the IC
+        Ok(fibonacci(20).to_string())
+    }
+}
+
 pub fn fibonacci(n: u64) -> u64 {
     if n == 0 {
         0
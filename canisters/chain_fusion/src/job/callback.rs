@@ -0,0 +1,212 @@
+//! Resolves the function a job result is delivered to in `Callback` mode and maps the
+//! result onto the function's arguments.
+use std::str::FromStr;
+
+use ethers_core::{
+    abi::{encode, parse_abi, Contract, Function, Token},
+    types::{H256, U256},
+};
+use evm_rpc_canister_types::LogEntry;
+
+use crate::lifecycle::CallbackArg;
+
+/// The function of the `Coprocessor` contract that receives job results by default.
+const COPROCESSOR_CALLBACK: &str = "callback(string,uint256)";
+
+/// A validated `CallbackConfig`.
+#[derive(Clone, Debug)]
+pub struct Callback {
+    /// The called contract, `None` for the contract that emitted the event.
+    pub contract_address: Option<String>,
+    /// An ABI containing only the called function.
+    pub abi: Contract,
+    pub function_name: String,
+    pub args: Vec<CallbackArg>,
+}
+
+impl Callback {
+    /// Parses a callback from the signature of its function, e.g. `callback(bytes,uint256)`.
+    /// The arguments default to the tokens of the result followed by the job ID.
+    pub fn parse(
+        function_signature: &str,
+        contract_address: Option<String>,
+        args: Option<Vec<CallbackArg>>,
+    ) -> Result<Self, String> {
+        let declaration = format!("function {}", function_signature.trim());
+        let abi = parse_abi(&[declaration.as_str()])
+            .map_err(|e| format!("invalid function signature {function_signature}: {e}"))?;
+        let function_name = abi
+            .functions()
+            .next()
+            .map(|function| function.name.clone())
+            .ok_or_else(|| format!("invalid function signature {function_signature}"))?;
+        Ok(Self {
+            contract_address,
+            abi,
+            function_name,
+            args: args.unwrap_or_else(|| vec![CallbackArg::Result, CallbackArg::JobId]),
+        })
+    }
+
+    /// The `callback(string,uint256)` function of the contract that emitted the event.
+    pub fn coprocessor() -> Self {
+        Self::parse(COPROCESSOR_CALLBACK, None, None)
+            .expect("BUG: the coprocessor callback should be valid")
+    }
+
+    /// The address of the contract the result of a job triggered by `event` is delivered to.
+    pub fn contract_address<'a>(&'a self, event: &'a LogEntry) -> &'a str {
+        self.contract_address.as_deref().unwrap_or(&event.address)
+    }
+
+    pub fn function(&self) -> &Function {
+        self.abi
+            .functions()
+            .next()
+            .expect("BUG: the callback ABI should contain the callback function")
+    }
+
+    /// Returns the arguments the function is called with for the result of a job, or an
+    /// error if they don't match the function's parameters.
+    pub fn args(
+        &self,
+        result: &[Token],
+        job_id: U256,
+        event: &LogEntry,
+    ) -> Result<Vec<Token>, String> {
+        let mut args = vec![];
+        for arg in &self.args {
+            match arg {
+                CallbackArg::Result => args.extend_from_slice(result),
+                CallbackArg::ResultField(index) => args.push(
+                    result
+                        .get(*index as usize)
+                        .cloned()
+                        .ok_or_else(|| format!("the result has no field {index}"))?,
+                ),
+                CallbackArg::EncodedResult => args.push(Token::Bytes(encode(result))),
+                CallbackArg::JobId => args.push(Token::Uint(job_id)),
+                CallbackArg::EventTopic(index) => {
+                    let topic = event
+                        .topics
+                        .get(*index as usize)
+                        .ok_or_else(|| format!("the event has no topic {index}"))?;
+                    let topic =
+                        H256::from_str(topic).map_err(|e| format!("invalid topic {topic}: {e}"))?;
+                    args.push(Token::FixedBytes(topic.as_bytes().to_vec()))
+                }
+            }
+        }
+        // check the arguments against the parameters, so that a misconfigured callback
+        // fails the job instead of trapping when the calldata is encoded
+        self.function().encode_input(&args).map_err(|e| {
+            format!(
+                "the arguments {args:?} do not match {}: {e}",
+                self.function().signature()
+            )
+        })?;
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{new_job_log, CONTRACT_ADDRESS};
+
+    #[test]
+    fn should_pass_result_and_job_id_to_coprocessor_callback() {
+        let callback = Callback::coprocessor();
+        assert_eq!(callback.function_name, "callback");
+        assert_eq!(callback.contract_address, None);
+        assert_eq!(
+            callback.contract_address(&new_job_log(7, 16)),
+            CONTRACT_ADDRESS
+        );
+
+        let args = callback
+            .args(
+                &[Token::String("6765".to_string())],
+                U256::from(7),
+                &new_job_log(7, 16),
+            )
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                Token::String("6765".to_string()),
+                Token::Uint(U256::from(7))
+            ]
+        );
+    }
+
+    #[test]
+    fn should_map_typed_result_onto_arguments() {
+        let callback = Callback::parse(
+            "onResult(bytes32,(uint256,address),bytes)",
+            Some("0x70997970c51812dc3a010c7d01b50e0d17dc79c8".to_string()),
+            Some(vec![
+                CallbackArg::EventTopic(1),
+                CallbackArg::ResultField(1),
+                CallbackArg::EncodedResult,
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            callback.contract_address(&new_job_log(7, 16)),
+            "0x70997970c51812dc3a010c7d01b50e0d17dc79c8"
+        );
+        let result = vec![
+            Token::Uint(U256::from(1)),
+            Token::Tuple(vec![
+                Token::Uint(U256::from(2)),
+                Token::Address(Default::default()),
+            ]),
+        ];
+
+        let args = callback
+            .args(&result, U256::from(7), &new_job_log(7, 16))
+            .unwrap();
+
+        let mut topic = [0; 32];
+        topic[31] = 7;
+        assert_eq!(
+            args,
+            vec![
+                Token::FixedBytes(topic.to_vec()),
+                result[1].clone(),
+                Token::Bytes(encode(&result)),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_arguments_not_matching_function() {
+        let callback = Callback::parse("callback(uint256,uint256)", None, None).unwrap();
+        let result = callback.args(
+            &[Token::String("6765".to_string())],
+            U256::from(7),
+            &new_job_log(7, 16),
+        );
+        assert!(result.unwrap_err().contains("do not match"));
+
+        let callback = Callback::parse(
+            "callback(uint256)",
+            None,
+            Some(vec![CallbackArg::ResultField(1)]),
+        )
+        .unwrap();
+        let result = callback.args(
+            &[Token::Uint(U256::one())],
+            U256::from(7),
+            &new_job_log(7, 16),
+        );
+        assert_eq!(result, Err("the result has no field 1".to_string()));
+    }
+
+    #[test]
+    fn should_reject_invalid_function_signature() {
+        assert!(Callback::parse("callback(string", None, None).is_err());
+        assert!(Callback::parse("callback(strin)", None, None).is_err());
+    }
+}
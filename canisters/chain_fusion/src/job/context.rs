@@ -0,0 +1,133 @@
+use candid::Nat;
+use ethers_core::abi::{Detokenize, Token};
+use evm_rpc_canister_types::LogEntry;
+use ic_evm_utils::{
+    conversions::nat_to_u256,
+    eth_call::EthCallError,
+    eth_send_raw_transaction::{ContractDetails, IntoChainId},
+    evm_rpc_client::EvmRpcClient,
+};
+
+use crate::{
+    job::eth_call::eth_call,
+    state::{read_state, LogSource, State},
+};
+
+/// The context a job runs in.
+///
+/// Reads made through the context are pinned to the block of the event that triggered the
+/// job, so that the state a job observes is consistent with the event it processes.
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    /// The ID of the chain the event was emitted on.
+    pub chain_id: u64,
+    pub event_source: LogSource,
+    pub block_number: Option<Nat>,
+}
+
+impl JobContext {
+    pub fn new(event_source: LogSource, event: &LogEntry) -> Self {
+        Self {
+            chain_id: read_state(State::rpc_services).chain_id().as_u64(),
+            event_source,
+            block_number: event.blockNumber.clone(),
+        }
+    }
+
+    /// Returns the block reads are pinned to, falling back to `latest` for events that
+    /// are not included in a block yet.
+    pub fn block(&self) -> String {
+        match &self.block_number {
+            Some(block_number) => format!("0x{:x}", nat_to_u256(block_number)),
+            None => "latest".to_string(),
+        }
+    }
+
+    /// Calls a view function of an EVM smart contract at the context's block through
+    /// `evm_rpc`, e.g. `EVM_RPC`, and returns the raw output tokens.
+    pub async fn eth_call(
+        &self,
+        contract_details: ContractDetails<'_>,
+        evm_rpc: impl EvmRpcClient,
+    ) -> Result<Vec<Token>, EthCallError> {
+        eth_call(contract_details, &self.block(), evm_rpc).await
+    }
+
+    /// Calls a view function of an EVM smart contract at the context's block and decodes the
+    /// output into `T`, e.g. `U256`, `String` or a tuple for functions with multiple outputs.
+    /// Returns `EthCallError::AbiMismatch` if the output of the function does not match `T`.
+    pub async fn read<T: Detokenize>(
+        &self,
+        contract_details: ContractDetails<'_>,
+        evm_rpc: impl EvmRpcClient,
+    ) -> Result<T, EthCallError> {
+        let function_name = contract_details.function_name.to_string();
+        let tokens = self.eth_call(contract_details, evm_rpc).await?;
+        T::from_tokens(tokens).map_err(|e| {
+            EthCallError::AbiMismatch(format!(
+                "failed to decode the output of `{function_name}`: {e}"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::{parse_abi, Address};
+    use ethers_core::types::U256;
+    use evm_rpc_canister_types::RequestResult;
+    use ic_evm_utils::evm_rpc_client::MockEvmRpcClient;
+
+    use crate::{
+        state::initialize_state,
+        test_fixtures::{block_on, init_arg, CONTRACT_ADDRESS},
+    };
+
+    fn context(block_number: Option<Nat>) -> JobContext {
+        JobContext {
+            chain_id: 11155111,
+            event_source: LogSource {
+                transaction_hash: format!("0x{:064x}", 1),
+                log_index: Nat::from(0u32),
+            },
+            block_number,
+        }
+    }
+
+    #[test]
+    fn should_pin_reads_to_block_of_event() {
+        assert_eq!(context(Some(Nat::from(16u32))).block(), "0x10");
+        assert_eq!(context(Some(Nat::from(6_000_000u32))).block(), "0x5b8d80");
+    }
+
+    #[test]
+    fn should_read_latest_block_for_pending_event() {
+        assert_eq!(context(None).block(), "latest");
+    }
+
+    #[test]
+    fn should_read_through_given_evm_rpc_client_at_block_of_event() {
+        initialize_state(State::try_from(init_arg()).expect("init arg should be valid"));
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(format!(
+            r#"{{"jsonrpc":"2.0","id":1,"result":"0x{:064x}"}}"#,
+            42
+        )));
+        let abi = parse_abi(&["function balanceOf(address) view returns (uint256)"]).unwrap();
+
+        let balance: U256 = block_on(context(Some(Nat::from(16u32))).read(
+            ContractDetails {
+                contract_address: CONTRACT_ADDRESS.to_string(),
+                abi: &abi,
+                function_name: "balanceOf",
+                args: &[Token::Address(Address::zero())],
+            },
+            evm_rpc.clone(),
+        ))
+        .unwrap();
+
+        assert_eq!(balance, U256::from(42));
+        assert!(evm_rpc.requests()[0].contains(r#""0x10"]"#));
+    }
+}
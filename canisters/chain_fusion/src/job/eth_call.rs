@@ -0,0 +1,28 @@
+use ethers_core::abi::Token;
+use ic_evm_utils::{
+    eth_call::EthCallError, eth_send_raw_transaction::ContractDetails, evm_rpc_client::EvmRpcClient,
+};
+
+use crate::state::{read_state, State};
+
+/// The maximum number of response bytes accepted for an `eth_call` made by a job.
+const MAX_RESPONSE_BYTES: u64 = 4096;
+
+/// Reads from an EVM smart contract by calling `eth_call` on the canister's configured
+/// `rpc_service`. `block_number` is either a hex encoded block number or a block tag
+/// such as `"latest"`.
+pub async fn eth_call(
+    contract_details: ContractDetails<'_>,
+    block_number: &str,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<Vec<Token>, EthCallError> {
+    let rpc_service = read_state(State::rpc_service);
+    ic_evm_utils::eth_call::eth_call(
+        contract_details,
+        block_number,
+        rpc_service,
+        MAX_RESPONSE_BYTES,
+        evm_rpc,
+    )
+    .await
+}
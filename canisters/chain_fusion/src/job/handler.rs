@@ -0,0 +1,467 @@
+//! Dispatches the events that trigger jobs to the handlers computing their results.
+//!
+//! Every event type is identified by the hash of its signature, i.e. the first topic of its
+//! logs, and has at most one handler. Handlers receive the decoded event and a `JobContext`
+//! and return a typed result, which is ABI-encoded for the callback.
+//!
+//! A `JobHandler` computes the result within a single message. Computations that would
+//! exceed the instruction limit of a message implement `ChunkedJobHandler` instead, which
+//! runs in steps. The state between two steps is stored in stable memory and every step
+//! is run by a timer, i.e. in its own message.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use ethers_core::{
+    abi::{encode, Token, Tokenize},
+    types::{H256, U256},
+    utils::keccak256,
+};
+use evm_rpc_canister_types::LogEntry;
+use ic_evm_utils::eth_call::EthCallError;
+
+use crate::job::{JobContext, Progress};
+
+/// An EVM event that triggers jobs.
+pub trait JobEvent: Sized {
+    /// The signature of the event, e.g. `NewJob(uint256,uint256)`.
+    const SIGNATURE: &'static str;
+
+    /// Decodes the event from a log entry whose first topic is the hash of `SIGNATURE`.
+    fn decode(event: &LogEntry) -> Result<Self, String>;
+
+    /// Returns the ID of the job triggered by the event.
+    fn job_id(&self) -> U256;
+
+    /// Returns the amount of wei paid for the job, if the event reports it. If job fees are
+    /// enabled, jobs of events without a payment are underpaid.
+    fn payment(&self) -> Option<U256> {
+        None
+    }
+}
+
+/// Computes the results of the jobs triggered by one type of event.
+// the IC executes futures on a single thread, so the futures of handlers need not be `Send`
+#[allow(async_fn_in_trait)]
+pub trait JobHandler {
+    type Event: JobEvent;
+    /// The result of a job, its tokens are the leading arguments of the callback, e.g.
+    /// `String`, `U256` or a tuple for multiple values.
+    type Output: Tokenize;
+
+    async fn handle(
+        &self,
+        event: Self::Event,
+        context: &JobContext,
+    ) -> Result<Self::Output, JobError>;
+}
+
+/// Computes the results of long-running jobs in steps, each of which runs in its own
+/// message and thus has its own instruction limit. Every `JobHandler` is a chunked handler
+/// that is done after its first step.
+// the IC executes futures on a single thread, so the futures of handlers need not be `Send`
+#[allow(async_fn_in_trait)]
+pub trait ChunkedJobHandler {
+    type Event: JobEvent;
+    /// The result of a job, see `JobHandler::Output`.
+    type Output: Tokenize;
+    /// The partial state of a job between two steps, stored in stable memory.
+    type State: minicbor::Encode<()> + for<'b> minicbor::Decode<'b, ()>;
+
+    /// Returns the state the first step of a job starts from.
+    fn start(&self, event: &Self::Event) -> Self::State;
+
+    /// Runs the next step of a job. A step should stay well below the instruction limit
+    /// of a message, e.g. by processing a fixed number of items.
+    async fn step(
+        &self,
+        event: Self::Event,
+        state: Self::State,
+        context: &JobContext,
+    ) -> Result<Step<Self::State, Self::Output>, JobError>;
+}
+
+impl<H: JobHandler> ChunkedJobHandler for H {
+    type Event = H::Event;
+    type Output = H::Output;
+    type State = ();
+
+    fn start(&self, _event: &Self::Event) {}
+
+    async fn step(
+        &self,
+        event: Self::Event,
+        _state: (),
+        context: &JobContext,
+    ) -> Result<Step<(), Self::Output>, JobError> {
+        self.handle(event, context).await.map(Step::Done)
+    }
+}
+
+/// The outcome of a step of a `ChunkedJobHandler`.
+pub enum Step<S, O> {
+    /// The job continues with `state` in the next step.
+    Yield { state: S, progress: Progress },
+    /// The job is done.
+    Done(O),
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    /// The event could not be decoded.
+    InvalidEvent(String),
+    /// A read of the job failed.
+    EthCall(EthCallError),
+    /// The stored state of a chunked job could not be decoded.
+    InvalidCheckpoint(String),
+    /// The job failed for any other reason.
+    Failed(String),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::InvalidEvent(e) => write!(f, "invalid event: {e}"),
+            JobError::EthCall(e) => write!(f, "eth_call failed: {e}"),
+            JobError::InvalidCheckpoint(e) => write!(f, "invalid checkpoint: {e}"),
+            JobError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<EthCallError> for JobError {
+    fn from(e: EthCallError) -> Self {
+        JobError::EthCall(e)
+    }
+}
+
+type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// The outcome of running a job or one of its steps, with the types of the handler erased.
+pub enum JobStep {
+    /// The job continues from the encoded `checkpoint` in the next step.
+    Yield {
+        checkpoint: Vec<u8>,
+        progress: Progress,
+    },
+    /// The job is done with the result's ABI tokens.
+    Done(Vec<Token>),
+}
+
+/// A handler with its event, output and state types erased, so that handlers of different
+/// events can be registered together.
+pub trait ErasedJobHandler {
+    fn job_id(&self, event: &LogEntry) -> Result<U256, JobError>;
+
+    /// Returns the payment reported by the event, `None` if it reports none or can't be
+    /// decoded.
+    fn payment(&self, event: &LogEntry) -> Option<U256>;
+
+    /// Runs a job, or its next step if the job yielded a `checkpoint` before.
+    fn run<'a>(
+        &'a self,
+        event: &'a LogEntry,
+        context: &'a JobContext,
+        checkpoint: Option<Vec<u8>>,
+    ) -> LocalBoxFuture<'a, Result<JobStep, JobError>>;
+}
+
+impl<H: ChunkedJobHandler> ErasedJobHandler for H {
+    fn job_id(&self, event: &LogEntry) -> Result<U256, JobError> {
+        H::Event::decode(event)
+            .map(|event| event.job_id())
+            .map_err(JobError::InvalidEvent)
+    }
+
+    fn payment(&self, event: &LogEntry) -> Option<U256> {
+        H::Event::decode(event).ok()?.payment()
+    }
+
+    fn run<'a>(
+        &'a self,
+        event: &'a LogEntry,
+        context: &'a JobContext,
+        checkpoint: Option<Vec<u8>>,
+    ) -> LocalBoxFuture<'a, Result<JobStep, JobError>> {
+        Box::pin(async move {
+            let event = H::Event::decode(event).map_err(JobError::InvalidEvent)?;
+            let state = match checkpoint {
+                Some(checkpoint) => minicbor::decode(&checkpoint)
+                    .map_err(|e| JobError::InvalidCheckpoint(e.to_string()))?,
+                None => self.start(&event),
+            };
+            match self.step(event, state, context).await? {
+                Step::Yield { state, progress } => Ok(JobStep::Yield {
+                    checkpoint: minicbor::to_vec(&state)
+                        .expect("state encoding should always succeed"),
+                    progress,
+                }),
+                Step::Done(output) => Ok(JobStep::Done(output.into_tokens())),
+            }
+        })
+    }
+}
+
+/// The handlers of all event types, keyed by the hash of the event signature.
+#[derive(Default, Clone)]
+pub struct JobHandlers(BTreeMap<H256, Rc<dyn ErasedJobHandler>>);
+
+impl JobHandlers {
+    /// Registers `handler` for the events with the signature of `H::Event`.
+    ///
+    /// # Panics
+    ///
+    /// If a handler for the same event type was registered already.
+    pub fn register<H: ChunkedJobHandler + 'static>(mut self, handler: H) -> Self {
+        let topic = H256::from(keccak256(H::Event::SIGNATURE));
+        assert!(
+            self.0.insert(topic, Rc::new(handler)).is_none(),
+            "a handler for {} was registered twice",
+            H::Event::SIGNATURE
+        );
+        self
+    }
+
+    /// Returns the handler of the event's type, if any.
+    pub fn get(&self, event: &LogEntry) -> Option<Rc<dyn ErasedJobHandler>> {
+        let topic = H256::from_str(event.topics.first()?).ok()?;
+        self.0.get(&topic).cloned()
+    }
+}
+
+/// Renders the result of a job for the job store and attestations: a single string is
+/// kept as is, any other result is ABI-encoded and hex encoded.
+pub fn result_to_string(result: &[Token]) -> String {
+    match result {
+        [Token::String(result)] => result.clone(),
+        result => format!("0x{}", hex::encode(encode(result))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use minicbor_derive::{Decode, Encode};
+
+    use crate::{
+        state::LogSource,
+        test_fixtures::{block_on, new_job_log},
+    };
+
+    struct Deposit {
+        id: U256,
+        amount: U256,
+    }
+
+    impl JobEvent for Deposit {
+        const SIGNATURE: &'static str = "Deposit(uint256,uint256)";
+
+        fn decode(event: &LogEntry) -> Result<Self, String> {
+            let topic = |i: usize| {
+                event
+                    .topics
+                    .get(i)
+                    .and_then(|topic| U256::from_str_radix(topic, 16).ok())
+                    .ok_or_else(|| format!("missing topic {i}"))
+            };
+            Ok(Deposit {
+                id: topic(1)?,
+                amount: topic(2)?,
+            })
+        }
+
+        fn job_id(&self) -> U256 {
+            self.id
+        }
+    }
+
+    /// Doubles the deposited amount and returns it with the block of the deposit.
+    struct DoubleHandler;
+
+    impl JobHandler for DoubleHandler {
+        type Event = Deposit;
+        type Output = (U256, String);
+
+        async fn handle(
+            &self,
+            event: Deposit,
+            context: &JobContext,
+        ) -> Result<Self::Output, JobError> {
+            let doubled = event
+                .amount
+                .checked_mul(U256::from(2))
+                .ok_or_else(|| JobError::Failed("overflow".to_string()))?;
+            Ok((doubled, context.block()))
+        }
+    }
+
+    /// Sums the numbers up to the deposited amount, ten numbers per step.
+    struct SumHandler;
+
+    #[derive(Encode, Decode)]
+    struct Sum {
+        #[n(0)]
+        next: u64,
+        #[n(1)]
+        sum: u64,
+    }
+
+    impl ChunkedJobHandler for SumHandler {
+        type Event = Deposit;
+        type Output = U256;
+        type State = Sum;
+
+        fn start(&self, _event: &Deposit) -> Sum {
+            Sum { next: 1, sum: 0 }
+        }
+
+        async fn step(
+            &self,
+            event: Deposit,
+            mut state: Sum,
+            _context: &JobContext,
+        ) -> Result<Step<Sum, U256>, JobError> {
+            let last = event.amount.as_u64();
+            let end = (state.next + 10).min(last + 1);
+            state.sum += (state.next..end).sum::<u64>();
+            state.next = end;
+            if state.next > last {
+                return Ok(Step::Done(U256::from(state.sum)));
+            }
+            let progress = Progress {
+                completed: state.next - 1,
+                total: last,
+            };
+            Ok(Step::Yield { state, progress })
+        }
+    }
+
+    /// A `Deposit` event in block 16, i.e. a `NewJob` event with other topics.
+    fn deposit(id: u64, amount: U256) -> LogEntry {
+        let mut amount_topic = [0; 32];
+        amount.to_big_endian(&mut amount_topic);
+        LogEntry {
+            topics: vec![
+                format!("0x{}", hex::encode(keccak256(Deposit::SIGNATURE))),
+                format!("0x{id:064x}"),
+                format!("0x{}", hex::encode(amount_topic)),
+            ],
+            ..new_job_log(id, 16)
+        }
+    }
+
+    fn context(event: &LogEntry) -> JobContext {
+        JobContext {
+            chain_id: 31_337,
+            event_source: LogSource {
+                transaction_hash: event.transactionHash.clone().unwrap(),
+                log_index: Nat::from(0u32),
+            },
+            block_number: event.blockNumber.clone(),
+        }
+    }
+
+    #[test]
+    fn should_dispatch_event_to_its_handler() {
+        let handlers = JobHandlers::default().register(DoubleHandler);
+        let event = deposit(7, U256::from(21));
+
+        let handler = handlers.get(&event).expect("handler should be registered");
+        assert_eq!(handler.job_id(&event).unwrap(), U256::from(7));
+        // deposits don't report a payment
+        assert_eq!(handler.payment(&event), None);
+        let result = block_on(handler.run(&event, &context(&event), None)).unwrap();
+        assert!(matches!(
+            result,
+            JobStep::Done(tokens) if tokens == vec![
+                Token::Uint(U256::from(42)),
+                Token::String("0x10".to_string())
+            ]
+        ));
+    }
+
+    #[test]
+    fn should_run_chunked_job_in_steps() {
+        let handlers = JobHandlers::default().register(SumHandler);
+        let event = deposit(7, U256::from(25));
+        let handler = handlers.get(&event).expect("handler should be registered");
+
+        let mut checkpoint = None;
+        let mut progress = vec![];
+        let result = loop {
+            match block_on(handler.run(&event, &context(&event), checkpoint.take())).unwrap() {
+                JobStep::Yield {
+                    checkpoint: next,
+                    progress: step,
+                } => {
+                    progress.push((step.completed, step.total));
+                    checkpoint = Some(next);
+                }
+                JobStep::Done(result) => break result,
+            }
+        };
+
+        assert_eq!(progress, vec![(10, 25), (20, 25)]);
+        assert_eq!(result, vec![Token::Uint(U256::from(325))]);
+    }
+
+    #[test]
+    fn should_reject_invalid_checkpoint() {
+        let handlers = JobHandlers::default().register(SumHandler);
+        let event = deposit(7, U256::from(25));
+        let handler = handlers.get(&event).unwrap();
+
+        let result = block_on(handler.run(&event, &context(&event), Some(vec![0xff])));
+        assert!(matches!(result, Err(JobError::InvalidCheckpoint(_))));
+    }
+
+    #[test]
+    fn should_not_dispatch_unknown_event() {
+        let handlers = JobHandlers::default().register(DoubleHandler);
+        let mut event = deposit(7, U256::from(21));
+        event.topics[0] = format!("0x{}", hex::encode(keccak256("Withdrawal(uint256)")));
+
+        assert!(handlers.get(&event).is_none());
+        event.topics.clear();
+        assert!(handlers.get(&event).is_none());
+    }
+
+    #[test]
+    fn should_return_handler_errors() {
+        let handlers = JobHandlers::default().register(DoubleHandler);
+        let event = deposit(7, U256::MAX);
+        let handler = handlers.get(&event).unwrap();
+
+        let result = block_on(handler.run(&event, &context(&event), None));
+        assert!(matches!(result, Err(JobError::Failed(_))));
+
+        let mut invalid = event.clone();
+        invalid.topics.truncate(2);
+        let result = block_on(handler.run(&invalid, &context(&invalid), None));
+        assert!(matches!(result, Err(JobError::InvalidEvent(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn should_not_register_two_handlers_for_same_event() {
+        let _ = JobHandlers::default()
+            .register(DoubleHandler)
+            .register(SumHandler);
+    }
+
+    #[test]
+    fn should_render_result() {
+        assert_eq!(
+            result_to_string(&[Token::String("6765".to_string())]),
+            "6765"
+        );
+        assert_eq!(
+            result_to_string(&[Token::Uint(U256::from(1))]),
+            format!("0x{:064x}", 1)
+        );
+    }
+}
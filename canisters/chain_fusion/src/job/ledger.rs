@@ -0,0 +1,450 @@
+//! Accounts for the payments of jobs against the gas spent on delivering their results.
+//!
+//! `Coprocessor.newJob` forwards the ETH sent with it to the canister's address and reports
+//! it in the `NewJob` event. If job fees are enabled, the canister books that payment before
+//! running a job, and once the result was delivered it reads the gas cost from the receipt
+//! of the callback transaction. Jobs paying less than the minimum payment are
+//! refused, or deferred until the surplus of other jobs covers their shortfall.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize, Nat};
+use ethers_core::types::U256;
+use evm_rpc_canister_types::{GetTransactionReceiptResult, MultiGetTransactionReceiptResult};
+#[cfg(target_arch = "wasm32")]
+use ic_cdk::println;
+use ic_evm_utils::{
+    conversions::{nat_to_u256, u256_to_nat},
+    evm_rpc_client::EvmRpcClient,
+};
+
+use crate::{
+    job::store::{self, JobKey, JobKeyArg, PaidJob},
+    lifecycle::UnderpaidJobs,
+    state::{mutate_state, read_state, State},
+};
+
+/// How long to wait for a callback transaction to be included before reading its receipt.
+const RECEIPT_DELAY: Duration = Duration::from_secs(60);
+/// How often to try reading the receipt of a callback transaction before giving up.
+const MAX_RECEIPT_ATTEMPTS: u32 = 5;
+/// The cycles attached to `eth_getTransactionReceipt` calls.
+const RECEIPT_CYCLES: u128 = 10_000_000_000;
+
+/// The payment required for jobs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JobFee {
+    /// The minimum amount of wei the transaction that triggers a job has to pay.
+    pub min_payment: U256,
+    pub underpaid_jobs: UnderpaidJobs,
+}
+
+/// The running totals of the payments and gas costs of all jobs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ledger {
+    /// The payments of all jobs, in wei.
+    pub revenue: U256,
+    /// The gas spent on delivering the results of all jobs, in wei.
+    pub gas_spent: U256,
+    /// The funds set aside for the jobs whose gas cost is not known yet. That is their
+    /// payment, plus the shortfall covered by the surplus for deferred jobs.
+    pub unsettled: BTreeMap<JobKey, U256>,
+    /// The shortfall of the deferred jobs.
+    pub deferred: BTreeMap<JobKey, U256>,
+}
+
+impl Ledger {
+    /// Rebuilds the ledger from the payments and gas costs recorded with the jobs. Jobs that
+    /// are not settled yet set aside their payment, or `min_payment` if a shortfall was
+    /// covered for them, and deferred jobs wait for their shortfall again.
+    pub fn rebuild(paid_jobs: Vec<PaidJob>, min_payment: U256) -> Self {
+        let mut ledger = Ledger::default();
+        for job in paid_jobs {
+            ledger.revenue += job.payment;
+            match job.gas_cost {
+                Some(gas_cost) => ledger.gas_spent += gas_cost,
+                None if job.deferred => {
+                    ledger.unsettled.insert(job.key, job.payment);
+                    ledger.defer(job.key, min_payment.saturating_sub(job.payment));
+                }
+                None => {
+                    ledger
+                        .unsettled
+                        .insert(job.key, job.payment.max(min_payment));
+                }
+            }
+        }
+        ledger
+    }
+
+    pub fn record_payment(&mut self, key: JobKey, payment: U256) {
+        self.revenue += payment;
+        self.unsettled.insert(key, payment);
+    }
+
+    pub fn defer(&mut self, key: JobKey, shortfall: U256) {
+        self.deferred.insert(key, shortfall);
+    }
+
+    /// Releases the funds set aside for a job and books its gas cost.
+    pub fn settle(&mut self, key: JobKey, gas_cost: U256) {
+        self.unsettled.remove(&key);
+        self.gas_spent += gas_cost;
+    }
+
+    /// The funds that are not set aside for any job.
+    pub fn surplus(&self) -> U256 {
+        let committed = self
+            .unsettled
+            .values()
+            .fold(self.gas_spent, |committed, funds| committed + funds);
+        self.revenue.saturating_sub(committed)
+    }
+
+    /// Removes the deferred jobs whose shortfall is covered by the surplus, in the order of
+    /// their keys, and sets their shortfall aside.
+    pub fn resume_funded(&mut self) -> Vec<JobKey> {
+        let mut resumed = vec![];
+        while let Some((&key, &shortfall)) = self.deferred.first_key_value() {
+            if shortfall > self.surplus() {
+                break;
+            }
+            self.deferred.remove(&key);
+            *self.unsettled.entry(key).or_default() += shortfall;
+            resumed.push(key);
+        }
+        resumed
+    }
+}
+
+/// Candid representation of the ledger, returned by `get_ledger`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LedgerView {
+    pub revenue: Nat,
+    pub gas_spent: Nat,
+    pub surplus: Nat,
+    pub deferred_jobs: Vec<JobKeyArg>,
+}
+
+pub fn ledger_view() -> LedgerView {
+    read_state(|s| LedgerView {
+        revenue: u256_to_nat(s.ledger.revenue),
+        gas_spent: u256_to_nat(s.ledger.gas_spent),
+        surplus: u256_to_nat(s.ledger.surplus()),
+        deferred_jobs: s
+            .ledger
+            .deferred
+            .keys()
+            .copied()
+            .map(JobKeyArg::from)
+            .collect(),
+    })
+}
+
+/// Rebuilds the ledger on the heap from the job store, as it starts over when the canister
+/// is upgraded.
+pub fn restore_ledger() {
+    let min_payment = read_state(|s| s.job_fee.map(|job_fee| job_fee.min_payment));
+    let ledger = Ledger::rebuild(store::paid_jobs(), min_payment.unwrap_or_default());
+    mutate_state(|s| s.ledger = ledger);
+}
+
+/// Whether a job is run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Run,
+    Refuse(String),
+    Defer,
+}
+
+/// Records the payment reported by the event of a job and decides whether the job is run.
+/// Jobs of events without a payment paid nothing. Every job is run if job fees are
+/// disabled.
+pub fn admit_job(key: JobKey, payment: Option<U256>) -> Admission {
+    let Some(job_fee) = read_state(|s| s.job_fee) else {
+        return Admission::Run;
+    };
+    let payment = payment.unwrap_or_default();
+    store::record_job_payment(key, payment);
+    mutate_state(|s| s.ledger.record_payment(key, payment));
+
+    if payment >= job_fee.min_payment {
+        return Admission::Run;
+    }
+    match job_fee.underpaid_jobs {
+        UnderpaidJobs::Refuse => Admission::Refuse(format!(
+            "underpaid: paid {payment} wei, the minimum is {} wei",
+            job_fee.min_payment
+        )),
+        UnderpaidJobs::Defer => {
+            mutate_state(|s| s.ledger.defer(key, job_fee.min_payment - payment));
+            Admission::Defer
+        }
+    }
+}
+
+/// Books the gas cost of the jobs whose results were delivered by `transaction_hash`, or no
+/// gas cost if no transaction was sent for them. The cost of a transaction is read from its
+/// receipt once it was included.
+pub fn settle_jobs(
+    keys: Vec<JobKey>,
+    transaction_hash: Option<String>,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    if read_state(|s| s.job_fee.is_none()) {
+        return;
+    }
+    match transaction_hash {
+        Some(transaction_hash) => schedule_settlement(keys, transaction_hash, 1, evm_rpc),
+        None => {
+            book_gas_cost(&keys, U256::zero());
+            resume_deferred_jobs(evm_rpc);
+        }
+    }
+}
+
+fn schedule_settlement(
+    keys: Vec<JobKey>,
+    transaction_hash: String,
+    attempt: u32,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    ic_cdk_timers::set_timer(RECEIPT_DELAY, move || {
+        ic_cdk::spawn(settle_transaction(keys, transaction_hash, attempt, evm_rpc))
+    });
+}
+
+async fn settle_transaction(
+    keys: Vec<JobKey>,
+    transaction_hash: String,
+    attempt: u32,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    match read_gas_cost(&transaction_hash, evm_rpc.clone()).await {
+        Some(gas_cost) => {
+            book_gas_cost(&keys, gas_cost);
+            resume_deferred_jobs(evm_rpc);
+        }
+        None if attempt < MAX_RECEIPT_ATTEMPTS => {
+            schedule_settlement(keys, transaction_hash, attempt + 1, evm_rpc)
+        }
+        // the payments of the jobs stay set aside, as their cost is unknown
+        None => println!("Failed to read the receipt of {transaction_hash}, giving up"),
+    }
+}
+
+/// Reads the gas cost of an included transaction from its receipt.
+async fn read_gas_cost(transaction_hash: &str, evm_rpc: impl EvmRpcClient) -> Option<U256> {
+    let rpc_services = read_state(State::rpc_services);
+    let response = evm_rpc
+        .eth_get_transaction_receipt(
+            rpc_services,
+            None,
+            transaction_hash.to_string(),
+            RECEIPT_CYCLES,
+        )
+        .await;
+    match response {
+        Ok((MultiGetTransactionReceiptResult::Consistent(GetTransactionReceiptResult::Ok(
+            Some(receipt),
+        )),)) => Some(nat_to_u256(&receipt.gasUsed) * nat_to_u256(&receipt.effectiveGasPrice)),
+        _ => None,
+    }
+}
+
+fn book_gas_cost(keys: &[JobKey], gas_cost: U256) {
+    for (key, share) in keys.iter().zip(split_gas_cost(gas_cost, keys.len())) {
+        store::record_job_gas_cost(*key, share);
+        mutate_state(|s| s.ledger.settle(*key, share));
+    }
+}
+
+/// Splits the gas cost of a transaction evenly between the jobs it delivered, the first
+/// job bears the remainder.
+fn split_gas_cost(gas_cost: U256, jobs: usize) -> Vec<U256> {
+    if jobs == 0 {
+        return vec![];
+    }
+    let (share, remainder) = gas_cost.div_mod(U256::from(jobs));
+    let mut shares = vec![share; jobs];
+    shares[0] += remainder;
+    shares
+}
+
+/// Runs the deferred jobs the surplus can pay for.
+pub fn resume_deferred_jobs(evm_rpc: impl EvmRpcClient + 'static) {
+    for key in mutate_state(|s| s.ledger.resume_funded()) {
+        println!("Resuming deferred job {key}");
+        let evm_rpc = evm_rpc.clone();
+        ic_cdk_timers::set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(super::resume_job(key, evm_rpc))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lifecycle::{InitArg, JobFeeArg},
+        state::{initialize_state, LogSource},
+        test_fixtures::{init_arg, new_job_log, CONTRACT_ADDRESS},
+    };
+
+    fn wei(amount: u64) -> U256 {
+        U256::from(amount)
+    }
+
+    fn key(job_id: u64) -> JobKey {
+        JobKey {
+            contract_address: CONTRACT_ADDRESS.parse().unwrap(),
+            job_id: U256::from(job_id),
+        }
+    }
+
+    #[test]
+    fn should_set_aside_payments_until_settled() {
+        let mut ledger = Ledger::default();
+        ledger.record_payment(key(1), wei(100));
+        ledger.record_payment(key(2), wei(100));
+        assert_eq!(ledger.surplus(), wei(0));
+
+        ledger.settle(key(1), wei(60));
+        assert_eq!(ledger.revenue, wei(200));
+        assert_eq!(ledger.gas_spent, wei(60));
+        assert_eq!(ledger.surplus(), wei(40));
+
+        // a job may cost more than it paid
+        ledger.settle(key(2), wei(150));
+        assert_eq!(ledger.surplus(), wei(0));
+    }
+
+    #[test]
+    fn should_resume_deferred_jobs_covered_by_surplus_in_order() {
+        let mut ledger = Ledger::default();
+        ledger.record_payment(key(1), wei(100));
+        ledger.settle(key(1), wei(40));
+        ledger.record_payment(key(2), wei(70));
+        ledger.defer(key(2), wei(30));
+        ledger.record_payment(key(3), wei(50));
+        ledger.defer(key(3), wei(50));
+        ledger.record_payment(key(4), wei(90));
+        ledger.defer(key(4), wei(10));
+        assert_eq!(ledger.surplus(), wei(60));
+
+        // job 3 blocks job 4, so that jobs are resumed in order
+        assert_eq!(ledger.resume_funded(), vec![key(2)]);
+        assert_eq!(ledger.surplus(), wei(30));
+        assert_eq!(ledger.unsettled.get(&key(2)), Some(&wei(100)));
+
+        ledger.settle(key(2), wei(20));
+        assert_eq!(ledger.surplus(), wei(110));
+        assert_eq!(ledger.resume_funded(), vec![key(3), key(4)]);
+        assert!(ledger.deferred.is_empty());
+    }
+
+    fn init_state(underpaid_jobs: UnderpaidJobs) {
+        initialize_state(
+            State::try_from(InitArg {
+                job_fee: Some(JobFeeArg {
+                    min_payment: Nat::from(100u32),
+                    underpaid_jobs: Some(underpaid_jobs),
+                }),
+                ..init_arg()
+            })
+            .expect("init arg should be valid"),
+        );
+    }
+
+    fn record_job(job_id: u64) {
+        let event = new_job_log(job_id, 16);
+        let source = LogSource {
+            transaction_hash: event.transactionHash.clone().unwrap(),
+            log_index: event.logIndex.clone().unwrap(),
+        };
+        assert!(store::record_job(key(job_id), &source, &event));
+    }
+
+    #[test]
+    fn should_admit_jobs_paying_minimum() {
+        init_state(UnderpaidJobs::Refuse);
+        record_job(1);
+        record_job(2);
+        record_job(3);
+
+        assert_eq!(admit_job(key(1), Some(wei(100))), Admission::Run);
+        assert!(matches!(
+            admit_job(key(2), Some(wei(99))),
+            Admission::Refuse(reason) if reason.contains("paid 99 wei")
+        ));
+        assert!(matches!(
+            admit_job(key(3), None),
+            Admission::Refuse(reason) if reason.contains("paid 0 wei")
+        ));
+        assert_eq!(
+            store::get_job(key(2)).unwrap().payment,
+            Some(Nat::from(99u32))
+        );
+        read_state(|s| {
+            assert_eq!(s.ledger.revenue, wei(199));
+            assert!(s.ledger.deferred.is_empty());
+        });
+    }
+
+    #[test]
+    fn should_defer_underpaid_jobs() {
+        init_state(UnderpaidJobs::Defer);
+        record_job(1);
+
+        assert_eq!(admit_job(key(1), Some(wei(60))), Admission::Defer);
+        read_state(|s| assert_eq!(s.ledger.deferred.get(&key(1)), Some(&wei(40))));
+    }
+
+    #[test]
+    fn should_admit_every_job_without_job_fee() {
+        initialize_state(State::try_from(init_arg()).expect("init arg should be valid"));
+        record_job(1);
+
+        assert_eq!(admit_job(key(1), None), Admission::Run);
+        assert_eq!(store::get_job(key(1)).unwrap().payment, None);
+    }
+
+    #[test]
+    fn should_rebuild_ledger_from_paid_jobs() {
+        let paid_job = |job_id, payment, gas_cost, deferred| PaidJob {
+            key: key(job_id),
+            payment: wei(payment),
+            gas_cost: gas_cost.map(wei),
+            deferred,
+        };
+        let mut ledger = Ledger::default();
+        ledger.record_payment(key(1), wei(100));
+        ledger.settle(key(1), wei(40));
+        ledger.record_payment(key(2), wei(70));
+        ledger.defer(key(2), wei(30));
+        ledger.record_payment(key(3), wei(50));
+        ledger.defer(key(3), wei(50));
+        ledger.record_payment(key(4), wei(120));
+        assert_eq!(ledger.resume_funded(), vec![key(2)]);
+
+        let rebuilt = Ledger::rebuild(
+            vec![
+                paid_job(1, 100, Some(40), false),
+                paid_job(2, 70, None, false),
+                paid_job(3, 50, None, true),
+                paid_job(4, 120, None, false),
+            ],
+            wei(100),
+        );
+
+        assert_eq!(rebuilt, ledger);
+        assert_eq!(rebuilt.surplus(), wei(30));
+    }
+
+    #[test]
+    fn should_split_gas_cost_between_jobs() {
+        assert_eq!(split_gas_cost(wei(10), 3), vec![wei(4), wei(3), wei(3)]);
+        assert_eq!(split_gas_cost(wei(10), 1), vec![wei(10)]);
+        assert_eq!(split_gas_cost(wei(10), 0), vec![]);
+    }
+}
@@ -0,0 +1,867 @@
+//! Persists the jobs run by the canister in stable memory, so that their inputs, results
+//! and status survive upgrades and can be served to frontends by `get_job` and `list_jobs`.
+//! Chunked jobs also keep the checkpoint they continue from here between their steps.
+//!
+//! Job IDs are only unique per contract, e.g. they start over at 0 when the `Coprocessor`
+//! is redeployed, so jobs are keyed by the address of the contract that emitted their
+//! event and their ID.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+
+use candid::{CandidType, Deserialize, Nat};
+use ethers_core::types::{Address, U256};
+use evm_rpc_canister_types::LogEntry;
+use ic_evm_utils::conversions::{nat_to_u256, u256_to_nat};
+use ic_stable_structures::{storable::Bound, storable::Storable, StableBTreeMap};
+use minicbor::bytes::ByteVec;
+use minicbor_derive::{Decode, Encode};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    memory::{get_memory, VMem, JOBS_MEMORY_ID},
+    state::LogSource,
+    time::now,
+};
+
+/// The maximum number of jobs returned by a single `list_jobs` call.
+pub const MAX_JOBS_PER_PAGE: u64 = 100;
+
+/// Identifies a job by the contract that emitted its event and its ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JobKey {
+    pub contract_address: Address,
+    pub job_id: U256,
+}
+
+impl JobKey {
+    /// Returns the key of the job with `job_id` triggered by `event`.
+    pub fn new(event: &LogEntry, job_id: U256) -> Result<Self, String> {
+        let contract_address = event
+            .address
+            .parse()
+            .map_err(|e| format!("invalid contract address {}: {e}", event.address))?;
+        Ok(JobKey {
+            contract_address,
+            job_id,
+        })
+    }
+}
+
+impl fmt::Display for JobKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} of {:?}", self.job_id, self.contract_address)
+    }
+}
+
+/// The address followed by the big-endian job ID, so that jobs are ordered by contract and
+/// then by ID.
+impl Storable for JobKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = self.contract_address.as_bytes().to_vec();
+        bytes.extend([0; 32]);
+        self.job_id.to_big_endian(&mut bytes[20..]);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        assert_eq!(bytes.len(), 52, "job key should be 52 bytes");
+        JobKey {
+            contract_address: Address::from_slice(&bytes[..20]),
+            job_id: U256::from_big_endian(&bytes[20..]),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 52,
+        is_fixed_size: true,
+    };
+}
+
+/// Candid representation of a job key.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobKeyArg {
+    pub contract_address: String,
+    pub job_id: Nat,
+}
+
+impl From<JobKey> for JobKeyArg {
+    fn from(key: JobKey) -> Self {
+        JobKeyArg {
+            contract_address: format!("{:?}", key.contract_address),
+            job_id: u256_to_nat(key.job_id),
+        }
+    }
+}
+
+impl TryFrom<&JobKeyArg> for JobKey {
+    type Error = String;
+
+    fn try_from(arg: &JobKeyArg) -> Result<Self, Self::Error> {
+        let contract_address = arg
+            .contract_address
+            .parse()
+            .map_err(|e| format!("invalid contract address {}: {e}", arg.contract_address))?;
+        Ok(JobKey {
+            contract_address,
+            job_id: nat_to_u256(&arg.job_id),
+        })
+    }
+}
+
+/// The status of a job.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum JobStatus {
+    /// The job was picked up and its result is being computed or delivered.
+    #[n(0)]
+    Pending,
+    /// The job runs in steps and continues in the next message.
+    #[n(4)]
+    Running(#[n(0)] Progress),
+    /// The `callback` transaction with the result was sent.
+    #[n(1)]
+    Submitted,
+    /// The result was signed and can be fetched with `get_attestation`.
+    #[n(2)]
+    Attested,
+    /// The result could not be delivered.
+    #[n(3)]
+    Failed(#[n(0)] String),
+    /// The result waits for the next `batchCallback` transaction.
+    #[n(5)]
+    Batched,
+    /// The job was underpaid and waits until the surplus of other jobs covers its
+    /// shortfall, see `ledger.rs`.
+    #[n(6)]
+    Deferred,
+}
+
+/// How far a chunked job got, in units chosen by its handler, e.g. processed items.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Progress {
+    #[n(0)]
+    pub completed: u64,
+    #[n(1)]
+    pub total: u64,
+}
+
+/// A job as stored in stable memory.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+struct Job {
+    /// The transaction hash of the event that triggered the job.
+    #[n(0)]
+    source_transaction_hash: String,
+    #[n(1)]
+    source_log_index: u64,
+    #[n(2)]
+    block_number: Option<u64>,
+    /// The topics of the event.
+    #[n(3)]
+    topics: Vec<String>,
+    /// The ABI-encoded non-indexed arguments of the event.
+    #[n(4)]
+    data: String,
+    #[n(5)]
+    status: JobStatus,
+    #[n(6)]
+    result: Option<String>,
+    /// The hash of the transaction that delivered the result.
+    #[n(7)]
+    transaction_hash: Option<String>,
+    /// When the job was picked up, in nanoseconds since the epoch.
+    #[n(8)]
+    created_at: u64,
+    /// When the job last changed, in nanoseconds since the epoch.
+    #[n(9)]
+    updated_at: u64,
+    /// The address of the contract that emitted the event, as reported by the RPC provider.
+    #[n(10)]
+    address: String,
+    /// The encoded state a chunked job continues from in its next step.
+    #[n(11)]
+    checkpoint: Option<ByteVec>,
+    /// The big-endian amount of wei paid by the transaction that triggered the job.
+    #[n(12)]
+    payment: Option<ByteVec>,
+    /// The big-endian amount of wei spent on gas to deliver the result.
+    #[n(13)]
+    gas_cost: Option<ByteVec>,
+    /// The EIP-712 signature of the result in attestation mode.
+    #[n(14)]
+    signature: Option<ByteVec>,
+    /// The ABI-encoded result of a batched job, until its batch was sent.
+    #[n(15)]
+    batched_result: Option<ByteVec>,
+}
+
+impl Job {
+    /// Restores the event that triggered the job.
+    fn event(&self) -> LogEntry {
+        LogEntry {
+            transactionHash: Some(self.source_transaction_hash.clone()),
+            blockNumber: self.block_number.map(Nat::from),
+            data: self.data.clone(),
+            blockHash: None,
+            transactionIndex: None,
+            topics: self.topics.clone(),
+            address: self.address.clone(),
+            logIndex: Some(Nat::from(self.source_log_index)),
+            removed: false,
+        }
+    }
+
+    fn source(&self) -> LogSource {
+        LogSource {
+            transaction_hash: self.source_transaction_hash.clone(),
+            log_index: Nat::from(self.source_log_index),
+        }
+    }
+}
+
+impl Storable for Job {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut buf = vec![];
+        minicbor::encode(self, &mut buf).expect("job encoding should always succeed");
+        Cow::Owned(buf)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        minicbor::decode(bytes.as_ref())
+            .unwrap_or_else(|e| panic!("failed to decode job bytes {}: {e}", hex::encode(bytes)))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// The input of a job, i.e. the event that triggered it.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobInput {
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+/// Candid representation of a job, returned by the job endpoints.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobView {
+    /// The address of the contract that emitted the event of the job.
+    pub contract_address: String,
+    pub job_id: Nat,
+    pub source: LogSource,
+    pub block_number: Option<Nat>,
+    pub input: JobInput,
+    pub status: JobStatus,
+    pub result: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// The amount of wei paid by the transaction that triggered the job, if job fees are
+    /// enabled.
+    pub payment: Option<Nat>,
+    /// The amount of wei spent on gas to deliver the result, once it is known.
+    pub gas_cost: Option<Nat>,
+}
+
+/// A job result signed with EIP-712 by the canister's default account, which can be
+/// submitted to the contract with `submitAttestation`.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    pub job_id: Nat,
+    pub result: String,
+    /// The 65 byte recoverable signature `r || s || v`.
+    pub signature: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ListJobsArg {
+    /// The key of the first job to return, defaults to the lowest key.
+    pub start: Option<JobKeyArg>,
+    /// The maximum number of jobs to return, defaults to and is capped at
+    /// `MAX_JOBS_PER_PAGE`.
+    pub limit: Option<u64>,
+}
+
+/// A page of jobs ordered by contract address and ID.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobPage {
+    pub jobs: Vec<JobView>,
+    /// The `start` of the next page, `None` if this is the last one.
+    pub next: Option<JobKeyArg>,
+}
+
+thread_local! {
+    static JOBS: RefCell<StableBTreeMap<JobKey, Job, VMem>> = RefCell::new(
+        StableBTreeMap::init(get_memory(JOBS_MEMORY_ID))
+    );
+}
+
+/// Records a new pending job for the event. Returns `false` without recording anything if
+/// the contract already emitted a job with the same ID.
+pub fn record_job(key: JobKey, source: &LogSource, event: &LogEntry) -> bool {
+    if JOBS.with_borrow(|jobs| jobs.contains_key(&key)) {
+        return false;
+    }
+    let now = now();
+    let job = Job {
+        source_transaction_hash: source.transaction_hash.clone(),
+        source_log_index: to_u64(&source.log_index),
+        block_number: event.blockNumber.as_ref().map(to_u64),
+        topics: event.topics.clone(),
+        data: event.data.clone(),
+        status: JobStatus::Pending,
+        result: None,
+        transaction_hash: None,
+        created_at: now,
+        updated_at: now,
+        address: event.address.clone(),
+        checkpoint: None,
+        payment: None,
+        gas_cost: None,
+        signature: None,
+        batched_result: None,
+    };
+    JOBS.with_borrow_mut(|jobs| jobs.insert(key, job));
+    true
+}
+
+/// Records the progress of a chunked job and the checkpoint its next step continues from.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_job_progress(key: JobKey, progress: Progress, checkpoint: Vec<u8>) {
+    update_job(key, |job| {
+        job.status = JobStatus::Running(progress);
+        job.checkpoint = Some(ByteVec::from(checkpoint));
+    });
+}
+
+/// Records the outcome of a job.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_job_outcome(
+    key: JobKey,
+    status: JobStatus,
+    result: Option<String>,
+    transaction_hash: Option<String>,
+) {
+    update_job(key, |job| {
+        if status != JobStatus::Batched {
+            job.batched_result = None;
+        }
+        job.status = status;
+        job.result = result;
+        job.transaction_hash = transaction_hash;
+        job.checkpoint = None;
+    });
+}
+
+/// Records the ABI-encoded result of a job waiting for the next `batchCallback`
+/// transaction.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_batched_result(key: JobKey, encoded_result: Vec<u8>) {
+    update_job(key, |job| {
+        job.batched_result = Some(ByteVec::from(encoded_result))
+    });
+}
+
+/// Records the amount of wei paid by the transaction that triggered a job.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_job_payment(key: JobKey, payment: U256) {
+    update_job(key, |job| job.payment = Some(to_wei_bytes(payment)));
+}
+
+/// Records the amount of wei spent on gas to deliver the result of a job.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_job_gas_cost(key: JobKey, gas_cost: U256) {
+    update_job(key, |job| job.gas_cost = Some(to_wei_bytes(gas_cost)));
+}
+
+/// Records the EIP-712 signature of the result of a job run in attestation mode.
+///
+/// # Panics
+///
+/// If the job was not recorded.
+pub fn record_job_signature(key: JobKey, signature: Vec<u8>) {
+    update_job(key, |job| job.signature = Some(ByteVec::from(signature)));
+}
+
+/// Returns the signed result of an attested job, or `None` if the job is not attested.
+pub fn get_attestation(key: JobKey) -> Option<Attestation> {
+    let job = JOBS.with_borrow(|jobs| jobs.get(&key))?;
+    match (job.status, job.result, job.signature) {
+        (JobStatus::Attested, Some(result), Some(signature)) => Some(Attestation {
+            job_id: u256_to_nat(key.job_id),
+            result,
+            signature: ByteBuf::from(signature.to_vec()),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the source and event of a deferred job, or `None` if the job is not deferred.
+pub fn load_deferred(key: JobKey) -> Option<(LogSource, LogEntry)> {
+    let job = JOBS.with_borrow(|jobs| jobs.get(&key))?;
+    (job.status == JobStatus::Deferred).then(|| (job.source(), job.event()))
+}
+
+/// Returns the source and event of a running chunked job together with the checkpoint its
+/// next step continues from, or `None` if the job is not running.
+pub fn load_checkpoint(key: JobKey) -> Option<(LogSource, LogEntry, Vec<u8>)> {
+    let job = JOBS.with_borrow(|jobs| jobs.get(&key))?;
+    match (&job.status, &job.checkpoint) {
+        (JobStatus::Running(_), Some(checkpoint)) => {
+            Some((job.source(), job.event(), checkpoint.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the batched jobs with their event, result and ABI-encoded result, e.g. to queue
+/// them again after an upgrade dropped the pending batch.
+pub fn load_batched() -> Vec<(JobKey, LogEntry, String, Vec<u8>)> {
+    JOBS.with_borrow(|jobs| {
+        jobs.iter()
+            .filter(|(_, job)| job.status == JobStatus::Batched)
+            .filter_map(|(key, job)| {
+                let event = job.event();
+                Some((key, event, job.result?, job.batched_result?.to_vec()))
+            })
+            .collect()
+    })
+}
+
+/// Returns the keys of the chunked jobs that continue in a next step, e.g. to schedule
+/// their steps again after an upgrade dropped the timers.
+pub fn running_jobs() -> Vec<JobKey> {
+    JOBS.with_borrow(|jobs| {
+        jobs.iter()
+            .filter(|(_, job)| matches!(job.status, JobStatus::Running(_)))
+            .map(|(key, _)| key)
+            .collect()
+    })
+}
+
+/// The payment and gas cost of a job as recorded by the ledger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaidJob {
+    pub key: JobKey,
+    pub payment: U256,
+    /// `None` until the job was settled.
+    pub gas_cost: Option<U256>,
+    pub deferred: bool,
+}
+
+/// Returns the jobs whose payment was recorded, e.g. to rebuild the ledger after an
+/// upgrade.
+pub fn paid_jobs() -> Vec<PaidJob> {
+    JOBS.with_borrow(|jobs| {
+        jobs.iter()
+            .filter_map(|(key, job)| {
+                Some(PaidJob {
+                    key,
+                    payment: U256::from_big_endian(job.payment.as_ref()?),
+                    gas_cost: job
+                        .gas_cost
+                        .as_ref()
+                        .map(|gas_cost| U256::from_big_endian(gas_cost)),
+                    deferred: job.status == JobStatus::Deferred,
+                })
+            })
+            .collect()
+    })
+}
+
+fn update_job(key: JobKey, f: impl FnOnce(&mut Job)) {
+    JOBS.with_borrow_mut(|jobs| {
+        let mut job = jobs
+            .get(&key)
+            .unwrap_or_else(|| panic!("BUG: unknown job {key} updated"));
+        f(&mut job);
+        job.updated_at = now();
+        jobs.insert(key, job);
+    });
+}
+
+pub fn get_job(key: JobKey) -> Option<JobView> {
+    JOBS.with_borrow(|jobs| jobs.get(&key).map(|job| job_view(key, job)))
+}
+
+/// Returns a page of jobs, or an error if the `start` of `arg` is not a valid key.
+pub fn list_jobs(arg: ListJobsArg) -> Result<JobPage, String> {
+    let start = match &arg.start {
+        Some(start) => JobKey::try_from(start)?,
+        None => JobKey {
+            contract_address: Address::zero(),
+            job_id: U256::zero(),
+        },
+    };
+    let limit = arg
+        .limit
+        .unwrap_or(MAX_JOBS_PER_PAGE)
+        .min(MAX_JOBS_PER_PAGE) as usize;
+    JOBS.with_borrow(|jobs| {
+        // fetch one more job than requested to learn where the next page starts
+        let mut page: Vec<_> = jobs.range(start..).take(limit + 1).collect();
+        let next = if page.len() > limit {
+            page.pop().map(|(key, _)| JobKeyArg::from(key))
+        } else {
+            None
+        };
+        Ok(JobPage {
+            jobs: page
+                .into_iter()
+                .map(|(key, job)| job_view(key, job))
+                .collect(),
+            next,
+        })
+    })
+}
+
+fn job_view(key: JobKey, job: Job) -> JobView {
+    let JobKeyArg {
+        contract_address,
+        job_id,
+    } = JobKeyArg::from(key);
+    JobView {
+        contract_address,
+        job_id,
+        source: job.source(),
+        block_number: job.block_number.map(Nat::from),
+        input: JobInput {
+            topics: job.topics,
+            data: job.data,
+        },
+        status: job.status,
+        result: job.result,
+        transaction_hash: job.transaction_hash,
+        created_at: job.created_at,
+        updated_at: job.updated_at,
+        payment: job.payment.as_ref().map(from_wei_bytes),
+        gas_cost: job.gas_cost.as_ref().map(from_wei_bytes),
+    }
+}
+
+fn to_wei_bytes(amount: U256) -> ByteVec {
+    let mut bytes = [0; 32];
+    amount.to_big_endian(&mut bytes);
+    ByteVec::from(bytes.to_vec())
+}
+
+fn from_wei_bytes(bytes: &ByteVec) -> Nat {
+    u256_to_nat(U256::from_big_endian(bytes))
+}
+
+fn to_u64(n: &Nat) -> u64 {
+    u64::try_from(n.0.clone()).expect("block numbers and log indices should fit into a u64")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{new_job_log, CONTRACT_ADDRESS as CONTRACT};
+
+    const OTHER_CONTRACT: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+
+    fn event(job_id: u64, block_number: u64) -> (LogSource, LogEntry) {
+        let event = new_job_log(job_id, block_number);
+        let source = LogSource {
+            transaction_hash: event.transactionHash.clone().unwrap(),
+            log_index: event.logIndex.clone().unwrap(),
+        };
+        (source, event)
+    }
+
+    fn key(job_id: u64) -> JobKey {
+        JobKey {
+            contract_address: CONTRACT.parse().unwrap(),
+            job_id: U256::from(job_id),
+        }
+    }
+
+    fn record(job_id: u64) -> bool {
+        let (source, event) = event(job_id, 10 + job_id);
+        record_job(key(job_id), &source, &event)
+    }
+
+    fn job_ids(page: &JobPage) -> Vec<Nat> {
+        page.jobs.iter().map(|job| job.job_id.clone()).collect()
+    }
+
+    #[test]
+    fn should_record_job_and_its_outcome() {
+        let (source, event) = event(7, 42);
+        assert!(record_job(key(7), &source, &event));
+
+        let job = get_job(key(7)).expect("job should be recorded");
+        assert_eq!(job.contract_address, CONTRACT);
+        assert_eq!(job.source, source);
+        assert_eq!(job.block_number, Some(Nat::from(42u32)));
+        assert_eq!(job.input.topics, event.topics);
+        assert_eq!(job.status, JobStatus::Pending);
+        assert_eq!(job.result, None);
+
+        record_job_outcome(
+            key(7),
+            JobStatus::Submitted,
+            Some("6765".to_string()),
+            Some("0xabc".to_string()),
+        );
+
+        let updated = get_job(key(7)).unwrap();
+        assert_eq!(updated.status, JobStatus::Submitted);
+        assert_eq!(updated.result, Some("6765".to_string()));
+        assert_eq!(updated.transaction_hash, Some("0xabc".to_string()));
+        assert_eq!(updated.created_at, job.created_at);
+        assert!(updated.updated_at >= job.updated_at);
+        assert_eq!(get_job(key(8)), None);
+    }
+
+    #[test]
+    fn should_keep_checkpoint_of_running_job() {
+        let (source, event) = event(3, 42);
+        assert!(record_job(key(3), &source, &event));
+        assert_eq!(load_checkpoint(key(3)), None);
+        assert_eq!(running_jobs(), vec![]);
+
+        let progress = Progress {
+            completed: 10,
+            total: 25,
+        };
+        record_job_progress(key(3), progress, vec![1, 2, 3]);
+
+        assert_eq!(
+            get_job(key(3)).unwrap().status,
+            JobStatus::Running(progress)
+        );
+        let (loaded_source, loaded_event, checkpoint) =
+            load_checkpoint(key(3)).expect("job should be running");
+        assert_eq!(loaded_source, source);
+        assert_eq!(loaded_event.topics, event.topics);
+        assert_eq!(loaded_event.address, event.address);
+        assert_eq!(loaded_event.blockNumber, event.blockNumber);
+        assert_eq!(checkpoint, vec![1, 2, 3]);
+        assert_eq!(running_jobs(), vec![key(3)]);
+
+        record_job_outcome(key(3), JobStatus::Submitted, None, None);
+        assert_eq!(load_checkpoint(key(3)), None);
+    }
+
+    #[test]
+    fn should_record_payment_and_gas_cost() {
+        assert!(record(5));
+        assert!(record(6));
+        assert_eq!(get_job(key(5)).unwrap().payment, None);
+
+        record_job_payment(key(5), U256::exp10(16));
+        record_job_gas_cost(key(5), U256::from(21_000u64 * 30_000_000_000));
+        record_job_payment(key(6), U256::exp10(15));
+        record_job_outcome(key(6), JobStatus::Deferred, None, None);
+
+        let job = get_job(key(5)).unwrap();
+        assert_eq!(job.payment, Some(Nat::from(10_000_000_000_000_000u64)));
+        assert_eq!(job.gas_cost, Some(Nat::from(630_000_000_000_000u64)));
+        assert_eq!(
+            paid_jobs(),
+            vec![
+                PaidJob {
+                    key: key(5),
+                    payment: U256::exp10(16),
+                    gas_cost: Some(U256::from(630_000_000_000_000u64)),
+                    deferred: false,
+                },
+                PaidJob {
+                    key: key(6),
+                    payment: U256::exp10(15),
+                    gas_cost: None,
+                    deferred: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_serve_attestations_of_jobs_with_same_id_from_different_contracts() {
+        assert!(record(1));
+        let (source, mut event) = event(1, 99);
+        event.address = OTHER_CONTRACT.to_string();
+        let other_key = JobKey::new(&event, U256::one()).unwrap();
+        assert!(record_job(other_key, &source, &event));
+
+        for (key, signature) in [(key(1), vec![1; 65]), (other_key, vec![2; 65])] {
+            record_job_signature(key, signature);
+            assert_eq!(get_attestation(key), None);
+            record_job_outcome(key, JobStatus::Attested, Some("6765".to_string()), None);
+        }
+
+        assert_eq!(
+            get_attestation(key(1)),
+            Some(Attestation {
+                job_id: Nat::from(1u32),
+                result: "6765".to_string(),
+                signature: ByteBuf::from(vec![1; 65]),
+            })
+        );
+        assert_eq!(
+            get_attestation(other_key).unwrap().signature,
+            ByteBuf::from(vec![2; 65])
+        );
+    }
+
+    #[test]
+    fn should_keep_encoded_result_of_batched_job_until_sent() {
+        assert!(record(2));
+        assert!(record(3));
+        record_batched_result(key(2), vec![1, 2, 3]);
+        record_job_outcome(key(2), JobStatus::Batched, Some("6765".to_string()), None);
+        record_job_outcome(key(3), JobStatus::Submitted, Some("6765".to_string()), None);
+
+        let batched = load_batched();
+        assert_eq!(batched.len(), 1);
+        let (batched_key, event, result, encoded_result) = &batched[0];
+        assert_eq!(*batched_key, key(2));
+        assert_eq!(event.topics, new_job_log(2, 12).topics);
+        assert_eq!(result, "6765");
+        assert_eq!(encoded_result, &vec![1, 2, 3]);
+
+        record_job_outcome(key(2), JobStatus::Submitted, Some("6765".to_string()), None);
+        assert_eq!(load_batched(), vec![]);
+        assert_eq!(
+            JOBS.with_borrow(|jobs| jobs.get(&key(2)).unwrap().batched_result),
+            None
+        );
+    }
+
+    #[test]
+    fn should_load_event_of_deferred_job_only() {
+        let (source, event) = event(6, 42);
+        assert!(record_job(key(6), &source, &event));
+        assert_eq!(load_deferred(key(6)), None);
+
+        record_job_outcome(key(6), JobStatus::Deferred, None, None);
+
+        let (loaded_source, loaded_event) = load_deferred(key(6)).expect("job should be deferred");
+        assert_eq!(loaded_source, source);
+        assert_eq!(loaded_event.topics, event.topics);
+    }
+
+    #[test]
+    fn should_not_overwrite_job_with_same_key() {
+        assert!(record(1));
+        let (other_source, other_event) = event(2, 99);
+        assert!(!record_job(key(1), &other_source, &other_event));
+        assert_eq!(
+            get_job(key(1)).unwrap().block_number,
+            Some(Nat::from(11u32))
+        );
+    }
+
+    #[test]
+    fn should_record_jobs_with_same_id_from_different_contracts() {
+        assert!(record(1));
+        let (source, mut event) = event(1, 99);
+        event.address = OTHER_CONTRACT.to_string();
+        let other_key = JobKey::new(&event, U256::one()).unwrap();
+        assert!(record_job(other_key, &source, &event));
+
+        assert_eq!(
+            get_job(key(1)).unwrap().block_number,
+            Some(Nat::from(11u32))
+        );
+        let other = get_job(other_key).unwrap();
+        assert_eq!(other.contract_address, OTHER_CONTRACT);
+        assert_eq!(other.block_number, Some(Nat::from(99u32)));
+    }
+
+    #[test]
+    fn should_list_jobs_in_pages_ordered_by_contract_and_id() {
+        for job_id in [300, 2, 1, 256] {
+            assert!(record(job_id));
+        }
+        // the other contract's address is greater, so its jobs come last
+        let (source, mut event) = event(0, 99);
+        event.address = OTHER_CONTRACT.to_string();
+        assert!(record_job(
+            JobKey::new(&event, U256::zero()).unwrap(),
+            &source,
+            &event
+        ));
+
+        let first = list_jobs(ListJobsArg {
+            start: None,
+            limit: Some(3),
+        })
+        .unwrap();
+        assert_eq!(
+            job_ids(&first),
+            vec![Nat::from(1u32), Nat::from(2u32), Nat::from(256u32)]
+        );
+        assert_eq!(first.next, Some(JobKeyArg::from(key(300))));
+
+        let second = list_jobs(ListJobsArg {
+            start: first.next,
+            limit: Some(3),
+        })
+        .unwrap();
+        assert_eq!(job_ids(&second), vec![Nat::from(300u32), Nat::from(0u32)]);
+        assert_eq!(second.jobs[1].contract_address, OTHER_CONTRACT);
+        assert_eq!(second.next, None);
+    }
+
+    #[test]
+    fn should_cap_page_size() {
+        for job_id in 0..MAX_JOBS_PER_PAGE + 1 {
+            assert!(record(job_id));
+        }
+
+        let page = list_jobs(ListJobsArg {
+            start: None,
+            limit: Some(u64::MAX),
+        })
+        .unwrap();
+        assert_eq!(page.jobs.len() as u64, MAX_JOBS_PER_PAGE);
+        assert_eq!(page.next, Some(JobKeyArg::from(key(MAX_JOBS_PER_PAGE))));
+    }
+
+    #[test]
+    fn should_reject_invalid_start() {
+        let result = list_jobs(ListJobsArg {
+            start: Some(JobKeyArg {
+                contract_address: "0xinvalid".to_string(),
+                job_id: Nat::from(0u32),
+            }),
+            limit: None,
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_order_encoded_keys_by_contract_and_id() {
+        let keys = [
+            key(1),
+            key(256),
+            JobKey {
+                contract_address: OTHER_CONTRACT.parse().unwrap(),
+                job_id: U256::zero(),
+            },
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].to_bytes() < pair[1].to_bytes());
+        }
+        for key in keys {
+            assert_eq!(JobKey::from_bytes(key.to_bytes()), key);
+            assert_eq!(JobKey::try_from(&JobKeyArg::from(key)), Ok(key));
+        }
+    }
+}
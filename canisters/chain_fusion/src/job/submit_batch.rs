@@ -0,0 +1,220 @@
+//! Collects job results in batch mode and delivers them with one `batchCallback`
+//! transaction per contract, so that a burst of jobs costs a single signature and
+//! transaction instead of one per job.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use ethers_core::abi::{encode, parse_abi, Token};
+use ic_cdk::println;
+use ic_evm_utils::{eth_send_raw_transaction::ContractDetails, evm_rpc_client::EvmRpcClient};
+
+use crate::{
+    job::{
+        ledger::settle_jobs,
+        store::{self, JobKey, JobStatus},
+        submit_result::send_transaction,
+    },
+    state::mutate_state,
+};
+
+/// The function of the `Coprocessor` contract that receives batched results.
+const BATCH_CALLBACK: &str = "function batchCallback(uint256[],bytes[])";
+
+/// A job result waiting for the next `batchCallback` transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchedResult {
+    pub key: JobKey,
+    /// The contract the result is delivered to.
+    pub contract_address: String,
+    /// The result as reported by `get_job`.
+    pub result: String,
+    /// The ABI-encoded tokens of the result.
+    pub encoded_result: Vec<u8>,
+}
+
+/// Adds the result of a job to the pending batch and keeps its encoding in the job store,
+/// so that it can be queued again after an upgrade, see `queue_result`.
+pub fn batch_result(
+    key: JobKey,
+    contract_address: String,
+    result: &[Token],
+    result_string: String,
+    window: Duration,
+    max_batch_size: u64,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    let encoded_result = encode(result);
+    store::record_batched_result(key, encoded_result.clone());
+    let result = BatchedResult {
+        key,
+        contract_address,
+        result: result_string,
+        encoded_result,
+    };
+    queue_result(result, window, max_batch_size, evm_rpc);
+}
+
+/// Adds a result to the pending batch and schedules sending it, either at the end of the
+/// batch window or right away if the batch is full. A full batch may be sent while the
+/// previous one is still being sent, both use their own nonce as `send_transaction`
+/// reserves it before it awaits anything.
+pub fn queue_result(
+    result: BatchedResult,
+    window: Duration,
+    max_batch_size: u64,
+    evm_rpc: impl EvmRpcClient + 'static,
+) {
+    let (batch_size, timer) = mutate_state(|s| {
+        s.pending_batch.push(result);
+        (s.pending_batch.len() as u64, s.batch_timer)
+    });
+    let delay = if batch_size >= max_batch_size {
+        Duration::ZERO
+    } else if timer.is_none() {
+        window
+    } else {
+        // the batch is sent by the already scheduled timer
+        return;
+    };
+    if let Some(timer) = timer {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    let timer = ic_cdk_timers::set_timer(delay, move || ic_cdk::spawn(submit_batch(evm_rpc)));
+    mutate_state(|s| s.batch_timer = Some(timer));
+}
+
+/// Sends the pending batch and records the outcome of its jobs.
+async fn submit_batch(evm_rpc: impl EvmRpcClient + 'static) {
+    let batch = mutate_state(|s| {
+        s.batch_timer = None;
+        std::mem::take(&mut s.pending_batch)
+    });
+    let abi = parse_abi(&[BATCH_CALLBACK]).expect("BUG: the batch callback should be valid");
+    for (contract_address, results) in group_by_contract(batch) {
+        let args = batch_args(&results);
+        let contract_details = ContractDetails {
+            contract_address,
+            abi: &abi,
+            function_name: "batchCallback",
+            args: &args,
+        };
+        let (status, transaction_hash) =
+            match send_transaction(contract_details, evm_rpc.clone()).await {
+                Ok(transaction_hash) => {
+                    println!(
+                        "Submitted a batch of {} results in {transaction_hash}",
+                        results.len()
+                    );
+                    (JobStatus::Submitted, Some(transaction_hash))
+                }
+                Err(e) => {
+                    println!("Error {e}");
+                    (JobStatus::Failed(e.to_string()), None)
+                }
+            };
+        let keys = results.iter().map(|result| result.key).collect();
+        for result in results {
+            store::record_job_outcome(
+                result.key,
+                status.clone(),
+                Some(result.result),
+                transaction_hash.clone(),
+            );
+        }
+        settle_jobs(keys, transaction_hash, evm_rpc.clone());
+    }
+}
+
+/// Groups a batch by the contract its results are delivered to, keeping their order.
+fn group_by_contract(batch: Vec<BatchedResult>) -> BTreeMap<String, Vec<BatchedResult>> {
+    let mut groups: BTreeMap<String, Vec<BatchedResult>> = BTreeMap::new();
+    for result in batch {
+        groups
+            .entry(result.contract_address.clone())
+            .or_default()
+            .push(result);
+    }
+    groups
+}
+
+/// The `uint256[] ids` and `bytes[] results` arguments of `batchCallback`.
+fn batch_args(results: &[BatchedResult]) -> Vec<Token> {
+    let (job_ids, results): (Vec<_>, Vec<_>) = results
+        .iter()
+        .map(|result| {
+            (
+                Token::Uint(result.key.job_id),
+                Token::Bytes(result.encoded_result.clone()),
+            )
+        })
+        .unzip();
+    vec![Token::Array(job_ids), Token::Array(results)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::U256;
+
+    const CONTRACT: &str = "0x5fbdb2315678afecb367f032d93f642f64180aa3";
+    const OTHER_CONTRACT: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+
+    fn batched(job_id: u64, contract_address: &str) -> BatchedResult {
+        let result = [Token::String(job_id.to_string())];
+        BatchedResult {
+            key: JobKey {
+                contract_address: contract_address.parse().unwrap(),
+                job_id: U256::from(job_id),
+            },
+            contract_address: contract_address.to_string(),
+            result: job_id.to_string(),
+            encoded_result: encode(&result),
+        }
+    }
+
+    #[test]
+    fn should_group_batch_by_contract_in_order() {
+        let batch = vec![
+            batched(1, CONTRACT),
+            batched(2, OTHER_CONTRACT),
+            batched(3, CONTRACT),
+        ];
+
+        let groups = group_by_contract(batch);
+
+        assert_eq!(
+            groups,
+            BTreeMap::from([
+                (
+                    CONTRACT.to_string(),
+                    vec![batched(1, CONTRACT), batched(3, CONTRACT)]
+                ),
+                (OTHER_CONTRACT.to_string(), vec![batched(2, OTHER_CONTRACT)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_encode_ids_and_results_as_batch_callback_arguments() {
+        let results = vec![batched(1, CONTRACT), batched(3, CONTRACT)];
+
+        let args = batch_args(&results);
+
+        assert_eq!(
+            args,
+            vec![
+                Token::Array(vec![Token::Uint(U256::from(1)), Token::Uint(U256::from(3))]),
+                Token::Array(vec![
+                    Token::Bytes(encode(&[Token::String("1".to_string())])),
+                    Token::Bytes(encode(&[Token::String("3".to_string())])),
+                ]),
+            ]
+        );
+        let abi = parse_abi(&[BATCH_CALLBACK]).unwrap();
+        assert!(abi
+            .function("batchCallback")
+            .unwrap()
+            .encode_input(&args)
+            .is_ok());
+    }
+}
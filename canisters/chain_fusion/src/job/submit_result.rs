@@ -1,92 +1,111 @@
+use std::fmt;
+
 use ethers_core::{abi::Token, types::U256};
-use evm_rpc_canister_types::{BlockTag, GetTransactionCountArgs, EVM_RPC};
+use evm_rpc_canister_types::LogEntry;
 use ic_evm_utils::{
-    conversions::nat_to_u256,
-    eth_get_transaction_count::get_transaction_count,
-    eth_send_raw_transaction::{contract_interaction, ContractDetails},
+    eth_estimate_gas::GasEstimationConfig,
+    eth_send_raw_transaction::{
+        contract_interaction, ContractDetails, PreflightOptions, SendTransactionError,
+        TransactionHash,
+    },
+    evm_rpc_client::EvmRpcClient,
+};
+
+use crate::{
+    accounts::DEFAULT_DERIVATION_PATH,
+    state::{mutate_state, read_state, State},
 };
 
-use crate::state::{mutate_state, read_state, State};
+/// Sends a transaction calling the callback configured for `event` with a job's result
+/// and returns its hash.
+pub async fn submit_result(
+    result: Vec<Token>,
+    job_id: U256,
+    event: &LogEntry,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<TransactionHash, SubmitResultError> {
+    let callback = read_state(|s| s.callback(event));
+    let args = callback
+        .args(&result, job_id, event)
+        .map_err(SubmitResultError::InvalidArguments)?;
+    let contract_details = ContractDetails {
+        contract_address: callback.contract_address(event).to_string(),
+        abi: &callback.abi,
+        function_name: &callback.function_name,
+        args: &args,
+    };
+    send_transaction(contract_details, evm_rpc)
+        .await
+        .map_err(SubmitResultError::Send)
+}
 
-pub async fn submit_result(result: String, job_id: U256) {
+/// Sends a transaction calling a contract from the canister's default account and returns
+/// its hash. The nonce is reserved before the first await, so that transactions sent by
+/// other tasks in the meantime use the next one.
+pub async fn send_transaction(
+    contract_details: ContractDetails<'_>,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<TransactionHash, SendTransactionError> {
+    let nonce = mutate_state(|s| s.reserve_nonce(&DEFAULT_DERIVATION_PATH));
     // get necessary global state
-    let contract_address = &read_state(State::get_logs_addresses)[0];
     let rpc_services = read_state(State::rpc_services);
-    let nonce = read_state(State::nonce);
-    let key_id = read_state(State::key_id);
-
-    let abi_json = r#"
-   [
-        {
-            "type": "function",
-            "name": "callback",
-            "inputs": [
-                {
-                    "name": "_result",
-                    "type": "string",
-                    "internalType": "string"
-                },
-                {
-                    "name": "_job_id",
-                    "type": "uint256",
-                    "internalType": "uint256"
-                }
-            ],
-            "outputs": [],
-            "stateMutability": "nonpayable"
-        }
-   ]
-   "#;
+    let signer = read_state(State::signer);
+    let fee_strategy = read_state(State::fee_strategy);
+    let transaction_type = read_state(State::transaction_type);
+    let evm_address =
+        read_state(|s| s.evm_address(&DEFAULT_DERIVATION_PATH)).expect("EVM address should be set");
 
-    let abi =
-        serde_json::from_str::<ethers_core::abi::Contract>(abi_json).expect("should serialise");
-
-    let contract_details = ContractDetails {
-        contract_address: contract_address.clone(),
-        abi: &abi,
-        function_name: "callback",
-        args: &[Token::String(result), Token::Uint(job_id)],
+    // simulate the transaction first, so that we don't pay for a callback that would revert,
+    // estimate the gas limit with `eth_estimateGas` and attach an access list if it makes
+    // the callback cheaper
+    let preflight = PreflightOptions {
+        rpc_service: read_state(State::rpc_service),
+        from: evm_address,
+        simulate: true,
+        gas_estimation: GasEstimationConfig::default(),
+        create_access_list: true,
     };
 
-    // set the gas
-    let gas = Some(U256::from(5000000));
-
     // interact with the contract, this calls `eth_sendRawTransaction` under the hood
     let status = contract_interaction(
         contract_details,
-        gas,
-        rpc_services.clone(),
+        None,
+        rpc_services,
         nonce,
-        key_id,
-        vec![],
-        EVM_RPC,
+        &signer,
+        DEFAULT_DERIVATION_PATH,
+        Some(preflight),
+        fee_strategy.as_ref(),
+        transaction_type,
+        evm_rpc,
     )
     .await;
 
-    // check the status of the transaction
     match status {
-        Ok(transaction_hash) => {
-            let evm_address = read_state(|s| s.evm_address.clone());
-
-            let get_transaction_count_args = GetTransactionCountArgs {
-                address: evm_address.expect("EVM address should be set"),
-                block: BlockTag::Latest,
-            };
+        // the transaction was neither signed nor sent, so the next one can use its nonce
+        Err(
+            SendTransactionError::SimulationReverted(_)
+            | SendTransactionError::GasEstimation(_)
+            | SendTransactionError::Fees(_),
+        ) => mutate_state(|s| s.release_nonce(&DEFAULT_DERIVATION_PATH, nonce)),
+        // TODO: handle resubmission in the case of failure
+        Err(SendTransactionError::CallFailed(..)) | Ok(_) => {}
+    }
+    status
+}
 
-            let transaction_count =
-                get_transaction_count(rpc_services, get_transaction_count_args, EVM_RPC).await;
+#[derive(Debug)]
+pub enum SubmitResultError {
+    /// The result doesn't match the parameters of the callback function.
+    InvalidArguments(String),
+    Send(SendTransactionError),
+}
 
-            if nat_to_u256(&transaction_count) > nonce {
-                ic_cdk::println!("Success {transaction_hash:?}");
-                mutate_state(|s| {
-                    s.nonce += U256::from(1);
-                });
-            } else {
-                // TODO: handle resubmission in the case of failure
-            }
-        }
-        Err(e) => {
-            ic_cdk::println!("Error {e:?}");
+impl fmt::Display for SubmitResultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitResultError::InvalidArguments(e) => write!(f, "invalid callback arguments: {e}"),
+            SubmitResultError::Send(e) => write!(f, "{e}"),
         }
     }
 }
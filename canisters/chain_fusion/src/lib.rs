@@ -1,8 +1,14 @@
+mod accounts;
 mod guard;
 mod job;
 mod lifecycle;
 mod logs;
+mod memory;
+mod providers;
 mod state;
+#[cfg(test)]
+mod test_fixtures;
+mod time;
 // uncomment to enable serving stored assets via http requests
 // mod storage;
 
@@ -10,25 +16,33 @@ use std::time::Duration;
 
 use logs::scrape_eth_logs;
 
+use accounts::{get_or_create_account, AccountOwner, AccountView, DEFAULT_DERIVATION_PATH};
+use candid::Principal;
+use evm_rpc_canister_types::EVM_RPC;
+use job::{Attestation, JobKeyArg, JobPage, JobView, LedgerView, ListJobsArg};
 use lifecycle::InitArg;
-use state::{read_state, State};
+use providers::ProviderHealthView;
+use serde_bytes::ByteBuf;
+use state::read_state;
 
-use crate::state::{initialize_state, mutate_state};
+use crate::state::initialize_state;
 
 pub const SCRAPING_LOGS_INTERVAL: Duration = Duration::from_secs(3 * 60);
 
 fn setup_timers() {
-    let key_id = read_state(State::key_id);
     // as timers are synchronous, we need to spawn a new async task to get the public key
     ic_cdk_timers::set_timer(Duration::ZERO, || {
         ic_cdk::spawn(async {
-            let public_key =
-                ic_evm_utils::evm_signer::get_canister_public_key(key_id, None, vec![]).await;
-            let evm_address = ic_evm_utils::evm_signer::pubkey_bytes_to_address(&public_key);
-            mutate_state(|s| {
-                s.ecdsa_pub_key = Some(public_key);
-                s.evm_address = Some(evm_address);
-            });
+            // this also caches the public key in the signer, so that signing doesn't fetch it again
+            get_or_create_account(DEFAULT_DERIVATION_PATH).await;
+            // the account may have sent transactions before, e.g. prior to an upgrade
+            accounts::sync_nonce(DEFAULT_DERIVATION_PATH, EVM_RPC).await;
+            // chunked jobs that were running before an upgrade continue from their checkpoint
+            job::continue_running_jobs(EVM_RPC);
+            // and deferred jobs the restored surplus covers are run
+            job::resume_deferred_jobs(EVM_RPC);
+            // and the results of batched jobs are queued again
+            job::requeue_batched_jobs(EVM_RPC);
         })
     });
     // // Start scraping logs almost immediately after the install, then repeat with the interval.
@@ -42,9 +56,101 @@ fn init(arg: InitArg) {
     setup_timers();
 }
 
+/// The state on the heap starts over from `arg`, while the jobs in stable memory are kept
+/// and the ledger is rebuilt from them. Pass the block to continue scraping from as
+/// `last_scraped_block_number`, logs of jobs that were already recorded are skipped.
+#[ic_cdk::post_upgrade]
+fn post_upgrade(arg: InitArg) {
+    initialize_state(state::State::try_from(arg).expect("BUG: failed to upgrade canister"));
+    job::restore_ledger();
+    setup_timers();
+}
+
+/// Returns the EVM address of the account with the given derivation path, or the one
+/// of the default account if none is given. Returns `None` if the account was not
+/// derived yet, use `create_account` to derive it.
+#[ic_cdk::query]
+fn get_evm_address(derivation_path: Option<Vec<ByteBuf>>) -> Option<String> {
+    let derivation_path = derivation_path
+        .map(|path| path.into_iter().map(ByteBuf::into_vec).collect())
+        .unwrap_or(DEFAULT_DERIVATION_PATH);
+    read_state(|s| s.evm_address(&derivation_path))
+}
+
+/// Derives the account of a principal or tag. Principals other than the anonymous one can
+/// derive their own account as long as there are fewer than `MAX_ACCOUNTS` accounts, while
+/// accounts for other principals and tags can only be derived by controllers.
+#[ic_cdk::update]
+async fn create_account(owner: AccountOwner) -> AccountView {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        ic_cdk::trap("the anonymous principal cannot derive accounts");
+    }
+    let is_controller = ic_cdk::api::is_controller(&caller);
+    if owner != AccountOwner::Principal(caller) && !is_controller {
+        ic_cdk::trap("only controllers can derive accounts for other principals or tags");
+    }
+    let derivation_path = owner.derivation_path();
+    if !is_controller && !accounts::can_create_account(&derivation_path) {
+        ic_cdk::trap("the maximum number of accounts was reached");
+    }
+    get_or_create_account(derivation_path.clone()).await;
+    accounts::account_view(&derivation_path).expect("account was just created")
+}
+
+/// Reads the current balance of an already derived account.
+#[ic_cdk::update]
+async fn refresh_account_balance(derivation_path: Vec<ByteBuf>) -> AccountView {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("only controllers can refresh account balances");
+    }
+    let derivation_path: Vec<Vec<u8>> =
+        derivation_path.into_iter().map(ByteBuf::into_vec).collect();
+    if accounts::account_view(&derivation_path).is_none() {
+        ic_cdk::trap("unknown account");
+    }
+    if let Err(e) = accounts::refresh_balance(derivation_path.clone(), EVM_RPC).await {
+        ic_cdk::trap(&format!("failed to read the balance: {e}"));
+    }
+    accounts::account_view(&derivation_path).expect("account should exist")
+}
+
+/// Returns the signed result of a job run in attestation mode, which can be submitted
+/// to the contract that emitted the job with `submitAttestation`.
+#[ic_cdk::query]
+fn get_attestation(key: JobKeyArg) -> Option<Attestation> {
+    job::JobKey::try_from(&key)
+        .ok()
+        .and_then(job::get_attestation)
+}
+
+/// Returns the job with the given ID emitted by the given contract.
+#[ic_cdk::query]
+fn get_job(key: JobKeyArg) -> Option<JobView> {
+    job::JobKey::try_from(&key).ok().and_then(job::get_job)
+}
+
+/// Returns a page of jobs ordered by contract address and ID, starting at `start`. The
+/// `next` field of the result is the `start` of the following page.
+#[ic_cdk::query]
+fn list_jobs(arg: ListJobsArg) -> JobPage {
+    job::list_jobs(arg).unwrap_or_else(|e| ic_cdk::trap(&e))
+}
+
+/// Returns the payments and gas costs of all jobs and the deferred jobs.
 #[ic_cdk::query]
-fn get_evm_address() -> String {
-    read_state(|s| s.evm_address.clone()).expect("evm address should be initialized")
+fn get_ledger() -> LedgerView {
+    job::ledger_view()
+}
+
+#[ic_cdk::query]
+fn list_accounts() -> Vec<AccountView> {
+    accounts::accounts()
+}
+
+#[ic_cdk::query]
+fn get_provider_health() -> Vec<ProviderHealthView> {
+    providers::provider_health()
 }
 
 // uncomment this if you need to serve stored assets from `storage.rs` via http requests
@@ -68,3 +174,21 @@ fn get_evm_address() -> String {
 
 // Enables Candid export, read more [here](https://internetcomputer.org/docs/current/developer-docs/backend/rust/generating-candid/)
 ic_cdk::export_candid!();
+
+#[test]
+fn check_candid_interface() {
+    use candid_parser::utils::{service_equal, CandidSource};
+
+    // `__export_service` is generated by `export_candid!` and describes the actual interface
+    let new_interface = __export_service();
+
+    // check the actual interface against the declared one
+    let old_interface = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("chain_fusion.did");
+
+    service_equal(
+        CandidSource::Text(&new_interface),
+        CandidSource::File(old_interface.as_path()),
+    )
+    .expect("the declared candid interface in chain_fusion.did is out of date");
+}
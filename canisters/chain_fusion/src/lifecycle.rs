@@ -1,21 +1,179 @@
+use crate::job::{Callback, JobFee};
 use crate::state::{InvalidStateError, State};
 use candid::types::number::Nat;
 use candid::{CandidType, Deserialize};
-use ethers_core::types::{H256, U256};
+use ethers_core::{types::H256, utils::keccak256};
 use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
+use ic_evm_utils::conversions::nat_to_u256;
+use ic_evm_utils::eth_send_raw_transaction::TransactionType;
+use ic_evm_utils::evm_signer::ThresholdEcdsaSigner;
+use ic_evm_utils::fees::{
+    BaseFeeMultiplierStrategy, CappedFeeStrategy, FeeEstimates, FeeStrategy, FixedFeeStrategy,
+    HistoricalFeeStrategy,
+};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use evm_rpc_canister_types::{BlockTag, RpcService, RpcServices};
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct InitArg {
+    /// The providers used for scraping logs and submitting transactions.
     pub rpc_services: RpcServices,
+    /// The single provider used for raw JSON-RPC calls made through the EVM RPC canister's
+    /// `request` method, e.g. `eth_call` reads inside jobs.
     pub rpc_service: RpcService,
     pub get_logs_addresses: Vec<String>,
     pub get_logs_topics: Option<Vec<Vec<String>>>,
     pub last_scraped_block_number: Nat,
     pub ecdsa_key_id: EcdsaKeyId,
     pub block_tag: BlockTag,
+    /// The strategy used to choose the fees of the EIP-1559 transactions sent by the
+    /// canister, defaults to `Fast`.
+    pub fee_strategy: Option<FeeStrategyArg>,
+    /// The type of the transactions sent by the canister, defaults to `Eip1559`. Chains
+    /// that do not support EIP-1559 require `Legacy` or `Eip2930`, which pay the gas price
+    /// returned by `eth_gasPrice`.
+    pub transaction_type: Option<TransactionType>,
+    /// How job results are delivered to the contract, defaults to `Callback`.
+    pub submission_mode: Option<SubmissionMode>,
+    /// The functions called with the results of the jobs triggered by each event in
+    /// `Callback` mode. Events without a callback call `callback(string,uint256)` on the
+    /// contract that emitted them.
+    pub callbacks: Option<Vec<CallbackConfig>>,
+    /// The payment required for jobs. If set, the canister books the payment reported by
+    /// the event of every job and the gas spent on its callback.
+    pub job_fee: Option<JobFeeArg>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct JobFeeArg {
+    /// The minimum amount of wei the transaction that triggers a job has to pay.
+    pub min_payment: Nat,
+    /// What happens to jobs paying less than `min_payment`, defaults to `Refuse`.
+    pub underpaid_jobs: Option<UnderpaidJobs>,
+}
+
+/// What happens to jobs paying less than the minimum payment.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnderpaidJobs {
+    /// The job fails without being run.
+    #[default]
+    Refuse,
+    /// The job is run once the surplus of other jobs covers its shortfall.
+    Defer,
+}
+
+/// Where and how the results of the jobs triggered by one event type are delivered.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CallbackConfig {
+    /// The signature of the event, e.g. `NewJob(uint256,uint256)`.
+    pub event_signature: String,
+    /// The called contract, defaults to the contract that emitted the event.
+    pub contract_address: Option<String>,
+    /// The signature of the called function, e.g. `callback(bytes,uint256)`. Struct
+    /// parameters are declared as tuples, e.g. `onResult((uint256,address))`.
+    pub function_signature: String,
+    /// The arguments the function is called with, defaults to the tokens of the result
+    /// followed by the job ID.
+    pub args: Option<Vec<CallbackArg>>,
+}
+
+/// A value a callback function is called with.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum CallbackArg {
+    /// All tokens of the job's result, in order.
+    Result,
+    /// The token of the job's result at the given index.
+    ResultField(u64),
+    /// The job's result ABI-encoded as `bytes`.
+    EncodedResult,
+    /// The ID of the job as `uint256`.
+    JobId,
+    /// The topic of the event at the given index as `bytes32`.
+    EventTopic(u64),
+}
+
+/// How the canister delivers job results to the contract.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SubmissionMode {
+    /// The canister sends a `callback` transaction and pays for its gas.
+    #[default]
+    Callback,
+    /// The canister signs the result with EIP-712 and serves it via the `get_attestation`
+    /// query, so that users or relayers can submit it with `submitAttestation` themselves.
+    Attestation,
+    /// The canister collects the results of the jobs done within `window_seconds` and
+    /// sends them in a single `batchCallback` transaction per contract. A batch is sent
+    /// early once it holds `max_batch_size` results.
+    Batch {
+        window_seconds: u64,
+        max_batch_size: u64,
+    },
+}
+
+/// The fee strategies the canister can be configured with, see `ic_evm_utils::fees`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum FeeStrategyArg {
+    Conservative,
+    Fast,
+    Fixed {
+        max_fee_per_gas: Nat,
+        max_priority_fee_per_gas: Nat,
+    },
+    BaseFeeMultiplier {
+        multiplier_percent: u64,
+        max_priority_fee_per_gas: Nat,
+    },
+    Capped {
+        strategy: Box<FeeStrategyArg>,
+        max_fee_per_gas: Nat,
+    },
+}
+
+impl FeeStrategyArg {
+    pub fn to_fee_strategy(&self) -> Box<dyn FeeStrategy> {
+        match self {
+            FeeStrategyArg::Conservative => Box::new(HistoricalFeeStrategy::conservative()),
+            FeeStrategyArg::Fast => Box::new(HistoricalFeeStrategy::fast()),
+            FeeStrategyArg::Fixed {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => Box::new(FixedFeeStrategy(FeeEstimates {
+                max_fee_per_gas: nat_to_u256(max_fee_per_gas),
+                max_priority_fee_per_gas: nat_to_u256(max_priority_fee_per_gas),
+            })),
+            FeeStrategyArg::BaseFeeMultiplier {
+                multiplier_percent,
+                max_priority_fee_per_gas,
+            } => Box::new(BaseFeeMultiplierStrategy {
+                multiplier_percent: *multiplier_percent,
+                max_priority_fee_per_gas: nat_to_u256(max_priority_fee_per_gas),
+            }),
+            FeeStrategyArg::Capped {
+                strategy,
+                max_fee_per_gas,
+            } => Box::new(CappedFeeStrategy {
+                strategy: strategy.to_fee_strategy(),
+                max_fee_per_gas: nat_to_u256(max_fee_per_gas),
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<(), InvalidStateError> {
+        match self {
+            FeeStrategyArg::Fixed {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } if max_priority_fee_per_gas > max_fee_per_gas => {
+                Err(InvalidStateError::InvalidFeeStrategy(
+                    "max_priority_fee_per_gas must not exceed max_fee_per_gas".to_string(),
+                ))
+            }
+            FeeStrategyArg::Capped { strategy, .. } => strategy.validate(),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl TryFrom<InitArg> for State {
@@ -30,6 +188,11 @@ impl TryFrom<InitArg> for State {
             last_scraped_block_number,
             ecdsa_key_id,
             block_tag,
+            fee_strategy,
+            transaction_type,
+            submission_mode,
+            callbacks,
+            job_fee,
         }: InitArg,
     ) -> Result<Self, Self::Error> {
         // validate contract addresses
@@ -44,8 +207,24 @@ impl TryFrom<InitArg> for State {
                 validate_topics(topic)?;
             }
         }
+        // validate fee strategy
+        let fee_strategy = fee_strategy.unwrap_or(FeeStrategyArg::Fast);
+        fee_strategy.validate()?;
+        // validate submission mode
+        let submission_mode = submission_mode.unwrap_or_default();
+        if let SubmissionMode::Batch { max_batch_size, .. } = submission_mode {
+            if max_batch_size == 0 {
+                return Err(InvalidStateError::InvalidSubmissionMode(
+                    "max_batch_size must be at least 1".to_string(),
+                ));
+            }
+        }
+        // validate callbacks
+        let callbacks = parse_callbacks(callbacks.unwrap_or_default())?;
 
         let state = Self {
+            active_rpc_services: rpc_services.clone(),
+            provider_health: Default::default(),
             rpc_services,
             rpc_service,
             get_logs_addresses,
@@ -56,16 +235,55 @@ impl TryFrom<InitArg> for State {
             processed_logs: Default::default(),
             skipped_blocks: Default::default(),
             active_tasks: Default::default(),
-            ecdsa_pub_key: None,
-            ecdsa_key_id,
-            evm_address: None,
-            nonce: U256::zero(),
+            signer: ThresholdEcdsaSigner::new(ecdsa_key_id),
+            accounts: Default::default(),
             block_tag,
+            fee_strategy,
+            transaction_type: transaction_type.unwrap_or_default(),
+            submission_mode,
+            callbacks,
+            pending_batch: vec![],
+            batch_timer: None,
+            job_fee: job_fee.map(|job_fee| JobFee {
+                min_payment: nat_to_u256(&job_fee.min_payment),
+                underpaid_jobs: job_fee.underpaid_jobs.unwrap_or_default(),
+            }),
+            ledger: Default::default(),
         };
         Ok(state)
     }
 }
 
+/// Parses the callbacks keyed by the topic of their event.
+fn parse_callbacks(
+    callbacks: Vec<CallbackConfig>,
+) -> Result<BTreeMap<H256, Callback>, InvalidStateError> {
+    let mut parsed = BTreeMap::new();
+    for config in callbacks {
+        if let Some(contract_address) = &config.contract_address {
+            ethers_core::types::Address::from_str(contract_address).map_err(|e| {
+                InvalidStateError::InvalidCallback(format!(
+                    "invalid contract address {contract_address}: {e}"
+                ))
+            })?;
+        }
+        let callback = Callback::parse(
+            &config.function_signature,
+            config.contract_address,
+            config.args,
+        )
+        .map_err(InvalidStateError::InvalidCallback)?;
+        let topic = H256::from(keccak256(config.event_signature.trim()));
+        if parsed.insert(topic, callback).is_some() {
+            return Err(InvalidStateError::InvalidCallback(format!(
+                "multiple callbacks for {}",
+                config.event_signature
+            )));
+        }
+    }
+    Ok(parsed)
+}
+
 // Function to validate a single topic
 fn validate_topic(topic: &str) -> Result<ethers_core::types::TxHash, InvalidStateError> {
     H256::from_str(topic).map_err(|e| InvalidStateError::InvalidTopic(format!("ERROR: {}", e)))
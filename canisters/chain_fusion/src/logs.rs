@@ -1,23 +1,26 @@
-use std::{
-    cmp::{min, Ordering},
-    ops::{Add, Div, Sub},
-    time::Duration,
-};
+mod range;
+
+use std::time::Duration;
 
 use candid::Nat;
 use evm_rpc_canister_types::{
     BlockTag, GetBlockByNumberResult, GetLogsArgs, GetLogsResult, HttpOutcallError,
     MultiGetBlockByNumberResult, MultiGetLogsResult, RejectionCode, RpcError, EVM_RPC,
 };
+#[cfg(target_arch = "wasm32")]
 use ic_cdk::println;
+use ic_evm_utils::evm_rpc_client::EvmRpcClient;
 
+use self::range::{ScrapeRange, TooLarge};
 use crate::{
     guard::TimerGuard,
     job::job,
+    providers::{record_consistent, record_inconsistent, Outcome},
     state::{mutate_state, read_state, State, TaskType},
+    time::now,
 };
 
-async fn process_logs() {
+async fn process_logs(evm_rpc: impl EvmRpcClient + 'static) {
     let _guard = match TimerGuard::new(TaskType::ProcessLogs) {
         Ok(guard) => guard,
         Err(_) => return,
@@ -26,14 +29,16 @@ async fn process_logs() {
     let logs_to_process = read_state(|s| (s.logs_to_process.clone()));
 
     for (event_source, event) in logs_to_process {
-        job(event_source, event).await
+        job(event_source, event, evm_rpc.clone()).await
     }
 }
 
-pub async fn get_logs(from: &Nat, to: &Nat) -> GetLogsResult {
+/// Fetches the logs in the range `[from, to]` from the active RPC providers.
+/// Returns `None` if the providers gave inconsistent results.
+pub async fn get_logs(from: &Nat, to: &Nat, evm_rpc: impl EvmRpcClient) -> Option<GetLogsResult> {
     let get_logs_address = read_state(|s| s.get_logs_addresses.clone());
     let get_logs_topics = read_state(|s| s.get_logs_topics.clone());
-    let rpc_services = read_state(|s| s.rpc_services.clone());
+    let rpc_services = read_state(State::rpc_services);
     let get_logs_args: GetLogsArgs = GetLogsArgs {
         fromBlock: Some(BlockTag::Number(from.clone())),
         toBlock: Some(BlockTag::Number(to.clone())),
@@ -42,82 +47,31 @@ pub async fn get_logs(from: &Nat, to: &Nat) -> GetLogsResult {
     };
 
     let cycles = 10_000_000_000;
-    let (result,) = EVM_RPC
-        .eth_get_logs(rpc_services, None, get_logs_args, cycles)
+    let started_at = now();
+    let (result,) = evm_rpc
+        .eth_get_logs(rpc_services.clone(), None, get_logs_args, cycles)
         .await
         .expect("Call failed");
 
     match result {
-        MultiGetLogsResult::Consistent(r) => r,
-        MultiGetLogsResult::Inconsistent(_) => {
-            panic!("RPC providers gave inconsistent results")
-        }
-    }
-}
-
-/// Scraps Ethereum logs between `from` and `min(from + MAX_BLOCK_SPREAD, to)` since certain RPC providers
-/// require that the number of blocks queried is no greater than MAX_BLOCK_SPREAD.
-/// Returns the last block number that was scraped (which is `min(from + MAX_BLOCK_SPREAD, to)`) if there
-/// was no error when querying the providers, otherwise returns `None`.
-async fn scrape_eth_logs_range_inclusive(from: &Nat, to: &Nat) -> Option<Nat> {
-    /// The maximum block spread is introduced by Alchemy limits.
-    const MAX_BLOCK_SPREAD: u16 = 500;
-    match from.cmp(to) {
-        Ordering::Less | Ordering::Equal => {
-            let max_to = from.clone().add(Nat::from(MAX_BLOCK_SPREAD));
-            let mut last_block_number = min(max_to, to.clone());
-
-            let logs = loop {
-                match get_logs(from, &last_block_number).await {
-                    GetLogsResult::Ok(logs) => break logs,
-                    GetLogsResult::Err(e) => {
-                        println!(
-                          "Failed to get ETH logs from block {from} to block {last_block_number}: {e:?}",
-                      );
-                        match e {
-                            RpcError::HttpOutcallError(e) => {
-                                if e.is_response_too_large() {
-                                    if *from == last_block_number {
-                                        mutate_state(|s| {
-                                            s.record_skipped_block(last_block_number.clone());
-                                            s.last_scraped_block_number = last_block_number.clone();
-                                        });
-                                        return Some(last_block_number);
-                                    } else {
-                                        let new_last_block_number = from.clone().add(
-                                            last_block_number
-                                                .clone()
-                                                .sub(from.clone())
-                                                .div(Nat::from(2u32)),
-                                        );
-                                        println!( "Too many logs received in range [{from}, {last_block_number}]. Will retry with range [{from}, {new_last_block_number}]");
-                                        last_block_number = new_last_block_number;
-                                        continue;
-                                    }
-                                }
-                            }
-                            _ => return None,
-                        }
-                    }
-                };
+        MultiGetLogsResult::Consistent(r) => {
+            let outcome = match &r {
+                GetLogsResult::Ok(_) => Some(Outcome::Success),
+                // too many logs in the requested range is not the providers' fault
+                GetLogsResult::Err(RpcError::HttpOutcallError(e)) if e.is_response_too_large() => {
+                    None
+                }
+                GetLogsResult::Err(_) => Some(Outcome::Error),
             };
-
-            for log_entry in logs {
-                mutate_state(|s| s.record_log_to_process(&log_entry));
-            }
-            if read_state(State::has_logs_to_process) {
-                ic_cdk_timers::set_timer(Duration::from_secs(0), move || {
-                    ic_cdk::spawn(process_logs())
-                });
+            if let Some(outcome) = outcome {
+                record_consistent(&rpc_services, outcome, started_at);
             }
-            mutate_state(|s| s.last_scraped_block_number = last_block_number.clone());
-            Some(last_block_number)
+            Some(r)
         }
-        Ordering::Greater => {
-            ic_cdk::trap(&format!(
-              "BUG: last scraped block number ({:?}) is greater than the last queried block number ({:?})",
-              from, to
-          ));
+        MultiGetLogsResult::Inconsistent(results) => {
+            println!("RPC providers gave inconsistent results for logs in range [{from}, {to}]");
+            record_inconsistent(&results, |r| matches!(r, GetLogsResult::Err(_)), started_at);
+            None
         }
     }
 }
@@ -128,7 +82,19 @@ pub async fn scrape_eth_logs() {
         Err(_) => return,
     };
 
-    let last_block_number = match update_last_observed_block_number().await {
+    scrape_logs(EVM_RPC).await;
+
+    if read_state(State::has_logs_to_process) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), move || {
+            ic_cdk::spawn(process_logs(EVM_RPC))
+        });
+    }
+}
+
+/// Scrapes the logs from the last scraped block up to the latest block observed with
+/// `block_tag` and records them for processing.
+async fn scrape_logs(evm_rpc: impl EvmRpcClient) {
+    let last_block_number = match update_last_observed_block_number(evm_rpc.clone()).await {
         Some(block_number) => block_number,
         None => {
             println!(
@@ -138,44 +104,82 @@ pub async fn scrape_eth_logs() {
         }
     };
 
-    let mut last_scraped_block_number = read_state(|s| s.last_scraped_block_number.clone());
+    let last_scraped_block_number = read_state(|s| s.last_scraped_block_number.clone());
+    let mut range = ScrapeRange::new(last_scraped_block_number, last_block_number);
 
-    while last_scraped_block_number < last_block_number {
-        let next_block_to_query = last_scraped_block_number.add(Nat::from(1u32));
-        last_scraped_block_number =
-            match scrape_eth_logs_range_inclusive(&next_block_to_query, &last_block_number).await {
-                Some(last_scraped_block_number) => last_scraped_block_number,
-                None => {
-                    return;
+    while let Some((from, to)) = range.next_query() {
+        let result = match get_logs(&from, &to, evm_rpc.clone()).await {
+            Some(result) => result,
+            None => return,
+        };
+        match result {
+            GetLogsResult::Ok(logs) => {
+                let scraped = range.logs_received();
+                mutate_state(|s| {
+                    for log_entry in logs {
+                        s.record_log_to_process(&log_entry);
+                    }
+                    s.last_scraped_block_number = scraped;
+                });
+            }
+            GetLogsResult::Err(RpcError::HttpOutcallError(e)) if e.is_response_too_large() => {
+                match range.response_too_large() {
+                    TooLarge::Retry {
+                        from,
+                        to: new_to,
+                    } => println!(
+                        "Too many logs received in range [{from}, {to}]. Will retry with range [{from}, {new_to}]"
+                    ),
+                    TooLarge::Skip(block_number) => {
+                        println!("Too many logs received in block {block_number}. Skipping it");
+                        mutate_state(|s| {
+                            s.record_skipped_block(block_number.clone());
+                            s.last_scraped_block_number = block_number;
+                        });
+                    }
                 }
-            };
+            }
+            GetLogsResult::Err(e) => {
+                println!("Failed to get ETH logs from block {from} to block {to}: {e:?}");
+                return;
+            }
+        }
     }
 }
 
-async fn update_last_observed_block_number() -> Option<Nat> {
-    let rpc_providers = read_state(|s| s.rpc_services.clone());
+async fn update_last_observed_block_number(evm_rpc: impl EvmRpcClient) -> Option<Nat> {
+    let rpc_providers = read_state(State::rpc_services);
     let block_tag = read_state(|s| s.block_tag.clone());
 
     let cycles = 10_000_000_000;
-    let (result,) = EVM_RPC
-        .eth_get_block_by_number(rpc_providers, None, block_tag, cycles)
+    let started_at = now();
+    let (result,) = evm_rpc
+        .eth_get_block_by_number(rpc_providers.clone(), None, block_tag, cycles)
         .await
         .expect("Call failed");
 
     match result {
         MultiGetBlockByNumberResult::Consistent(r) => match r {
             GetBlockByNumberResult::Ok(latest_block) => {
+                record_consistent(&rpc_providers, Outcome::Success, started_at);
                 let block_number = Some(latest_block.number);
                 mutate_state(|s| s.last_observed_block_number.clone_from(&block_number));
                 block_number
             }
             GetBlockByNumberResult::Err(err) => {
+                record_consistent(&rpc_providers, Outcome::Error, started_at);
                 println!("Failed to get the latest finalized block number: {err:?}");
                 read_state(|s| s.last_observed_block_number.clone())
             }
         },
-        MultiGetBlockByNumberResult::Inconsistent(_) => {
-            panic!("RPC providers gave inconsistent results")
+        MultiGetBlockByNumberResult::Inconsistent(results) => {
+            println!("RPC providers gave inconsistent results for the latest block number");
+            record_inconsistent(
+                &results,
+                |r| matches!(r, GetBlockByNumberResult::Err(_)),
+                started_at,
+            );
+            read_state(|s| s.last_observed_block_number.clone())
         }
     }
 }
@@ -199,3 +203,183 @@ pub fn is_response_too_large(code: &RejectionCode, message: &str) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evm_rpc_canister_types::{Block, EthSepoliaService, RpcService};
+    use ic_evm_utils::evm_rpc_client::MockEvmRpcClient;
+
+    use crate::{
+        lifecycle::InitArg,
+        state::initialize_state,
+        test_fixtures::{block_on, init_arg, new_job_log},
+    };
+
+    fn init_state(last_scraped_block_number: u32) {
+        initialize_state(
+            State::try_from(InitArg {
+                last_scraped_block_number: Nat::from(last_scraped_block_number),
+                ..init_arg()
+            })
+            .expect("init arg should be valid"),
+        );
+    }
+
+    fn block(number: u32) -> GetBlockByNumberResult {
+        let zero = || Nat::from(0u32);
+        GetBlockByNumberResult::Ok(Block {
+            miner: String::new(),
+            totalDifficulty: zero(),
+            receiptsRoot: String::new(),
+            stateRoot: String::new(),
+            hash: String::new(),
+            difficulty: zero(),
+            size: zero(),
+            uncles: vec![],
+            baseFeePerGas: zero(),
+            extraData: String::new(),
+            transactionsRoot: None,
+            sha3Uncles: String::new(),
+            nonce: zero(),
+            number: Nat::from(number),
+            timestamp: zero(),
+            transactions: vec![],
+            gasLimit: zero(),
+            logsBloom: String::new(),
+            parentHash: String::new(),
+            gasUsed: zero(),
+            mixHash: String::new(),
+        })
+    }
+
+    fn response_too_large() -> MultiGetLogsResult {
+        MultiGetLogsResult::Consistent(GetLogsResult::Err(RpcError::HttpOutcallError(
+            HttpOutcallError::IcError {
+                code: RejectionCode::SysFatal,
+                message: "Http body exceeds size limit of 2000000 bytes.".to_string(),
+            },
+        )))
+    }
+
+    fn queried_range(args: &GetLogsArgs) -> (Nat, Nat) {
+        match (&args.fromBlock, &args.toBlock) {
+            (Some(BlockTag::Number(from)), Some(BlockTag::Number(to))) => {
+                (from.clone(), to.clone())
+            }
+            range => panic!("unexpected range {range:?}"),
+        }
+    }
+
+    #[test]
+    fn should_record_logs_up_to_last_observed_block() {
+        init_state(100);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc
+            .push_get_block_by_number(MultiGetBlockByNumberResult::Consistent(block(110)))
+            .push_get_logs(MultiGetLogsResult::Consistent(GetLogsResult::Ok(vec![
+                new_job_log(1, 105),
+                new_job_log(2, 107),
+            ])));
+
+        block_on(scrape_logs(evm_rpc.clone()));
+
+        assert!(evm_rpc.is_exhausted());
+        assert_eq!(
+            queried_range(&evm_rpc.get_logs_args()[0]),
+            (Nat::from(101u32), Nat::from(110u32))
+        );
+        read_state(|s| {
+            assert_eq!(s.last_scraped_block_number, Nat::from(110u32));
+            assert_eq!(s.last_observed_block_number, Some(Nat::from(110u32)));
+            assert_eq!(s.logs_to_process.len(), 2);
+        });
+    }
+
+    #[test]
+    fn should_halve_range_when_response_is_too_large() {
+        init_state(0);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc
+            .push_get_block_by_number(MultiGetBlockByNumberResult::Consistent(block(100)))
+            .push_get_logs(response_too_large())
+            .push_get_logs(MultiGetLogsResult::Consistent(GetLogsResult::Ok(vec![])))
+            .push_get_logs(MultiGetLogsResult::Consistent(GetLogsResult::Ok(vec![])));
+
+        block_on(scrape_logs(evm_rpc.clone()));
+
+        assert!(evm_rpc.is_exhausted());
+        let ranges: Vec<_> = evm_rpc.get_logs_args().iter().map(queried_range).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (Nat::from(1u32), Nat::from(100u32)),
+                (Nat::from(1u32), Nat::from(50u32)),
+                (Nat::from(51u32), Nat::from(100u32)),
+            ]
+        );
+        read_state(|s| assert_eq!(s.last_scraped_block_number, Nat::from(100u32)));
+    }
+
+    #[test]
+    fn should_skip_single_block_when_response_is_too_large() {
+        init_state(9);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc
+            .push_get_block_by_number(MultiGetBlockByNumberResult::Consistent(block(10)))
+            .push_get_logs(response_too_large());
+
+        block_on(scrape_logs(evm_rpc.clone()));
+
+        assert!(evm_rpc.is_exhausted());
+        read_state(|s| {
+            assert_eq!(s.last_scraped_block_number, Nat::from(10u32));
+            assert!(s.skipped_blocks.contains(&Nat::from(10u32)));
+        });
+    }
+
+    #[test]
+    fn should_stop_scraping_on_provider_error() {
+        init_state(0);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc
+            .push_get_block_by_number(MultiGetBlockByNumberResult::Consistent(block(1_000)))
+            .push_get_logs(MultiGetLogsResult::Consistent(GetLogsResult::Ok(vec![])))
+            .push_get_logs(MultiGetLogsResult::Consistent(GetLogsResult::Err(
+                RpcError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse {
+                    status: 500,
+                    body: String::new(),
+                    parsingError: None,
+                }),
+            )));
+
+        block_on(scrape_logs(evm_rpc.clone()));
+
+        assert!(evm_rpc.is_exhausted());
+        read_state(|s| {
+            assert_eq!(s.last_scraped_block_number, Nat::from(501u32));
+            assert!(s.skipped_blocks.is_empty());
+        });
+    }
+
+    #[test]
+    fn should_not_scrape_when_providers_disagree_on_block_number() {
+        init_state(100);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_get_block_by_number(MultiGetBlockByNumberResult::Inconsistent(vec![
+            (
+                RpcService::EthSepolia(EthSepoliaService::Alchemy),
+                block(110),
+            ),
+            (RpcService::EthSepolia(EthSepoliaService::Ankr), block(111)),
+        ]));
+
+        block_on(scrape_logs(evm_rpc.clone()));
+
+        assert!(evm_rpc.get_logs_args().is_empty());
+        read_state(|s| {
+            assert_eq!(s.last_scraped_block_number, Nat::from(100u32));
+            assert_eq!(s.last_observed_block_number, None);
+        });
+    }
+}
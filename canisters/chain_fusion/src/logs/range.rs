@@ -0,0 +1,200 @@
+//! Plans the `eth_getLogs` queries needed to scrape a range of blocks.
+//!
+//! The range is queried in chunks of at most `MAX_BLOCK_SPREAD + 1` blocks. A chunk whose
+//! response is too large is halved and retried, and a single block whose response is still
+//! too large is skipped. The planner is pure, so that its guarantees can be tested without
+//! any calls: every block of the range is either scraped exactly once or skipped, in order.
+use std::ops::{Add, Div, Sub};
+
+use candid::Nat;
+
+/// The maximum block spread is introduced by Alchemy limits.
+pub const MAX_BLOCK_SPREAD: u16 = 500;
+
+/// The state of scraping the blocks `[next_block, last_block]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrapeRange {
+    /// The first block that was neither scraped nor skipped yet.
+    next_block: Nat,
+    /// The last block to scrape.
+    last_block: Nat,
+    /// The last block of the next query.
+    query_to: Nat,
+}
+
+/// What happened after a response was too large.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TooLarge {
+    /// The query is retried with a range half as large.
+    Retry { from: Nat, to: Nat },
+    /// The query covered a single block, which is skipped.
+    Skip(Nat),
+}
+
+impl ScrapeRange {
+    /// Plans scraping the blocks after `last_scraped_block` up to and including
+    /// `last_block`. There is nothing to scrape if `last_block` was scraped already.
+    pub fn new(last_scraped_block: Nat, last_block: Nat) -> Self {
+        let next_block = last_scraped_block.add(Nat::from(1u32));
+        Self {
+            query_to: chunk_end(&next_block, &last_block),
+            next_block,
+            last_block,
+        }
+    }
+
+    /// Returns the range `[from, to]` to query next, with `from <= to`, or `None` once all
+    /// blocks were scraped or skipped.
+    pub fn next_query(&self) -> Option<(Nat, Nat)> {
+        if self.next_block > self.last_block {
+            return None;
+        }
+        Some((self.next_block.clone(), self.query_to.clone()))
+    }
+
+    /// Records that the logs of the next query were received and returns the last block
+    /// it covered, which is now scraped.
+    pub fn logs_received(&mut self) -> Nat {
+        let scraped = self.query_to.clone();
+        self.advance_past(scraped.clone());
+        scraped
+    }
+
+    /// Records that the response to the next query was too large.
+    pub fn response_too_large(&mut self) -> TooLarge {
+        if self.next_block == self.query_to {
+            let skipped = self.query_to.clone();
+            self.advance_past(skipped.clone());
+            return TooLarge::Skip(skipped);
+        }
+        self.query_to = self.next_block.clone().add(
+            self.query_to
+                .clone()
+                .sub(self.next_block.clone())
+                .div(Nat::from(2u32)),
+        );
+        TooLarge::Retry {
+            from: self.next_block.clone(),
+            to: self.query_to.clone(),
+        }
+    }
+
+    fn advance_past(&mut self, block: Nat) {
+        self.next_block = block.add(Nat::from(1u32));
+        self.query_to = chunk_end(&self.next_block, &self.last_block);
+    }
+}
+
+/// Returns the last block of a full chunk starting at `from`.
+fn chunk_end(from: &Nat, last_block: &Nat) -> Nat {
+    from.clone()
+        .add(Nat::from(MAX_BLOCK_SPREAD))
+        .min(last_block.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Drives a range to completion, answering the queries with the given pattern of too
+    /// large responses and afterwards with logs. Returns the scraped ranges and skipped
+    /// blocks.
+    fn scrape(
+        last_scraped: u64,
+        last_block: u64,
+        too_large: &[bool],
+    ) -> (Vec<(u64, u64)>, Vec<u64>) {
+        let mut range = ScrapeRange::new(Nat::from(last_scraped), Nat::from(last_block));
+        let mut responses = too_large.iter().copied();
+        let (mut scraped, mut skipped) = (vec![], vec![]);
+        while let Some((from, to)) = range.next_query() {
+            let (from, to) = (to_u64(&from), to_u64(&to));
+            assert!(from <= to, "queried the empty range [{from}, {to}]");
+            assert!(to - from <= MAX_BLOCK_SPREAD as u64);
+            if responses.next().unwrap_or(false) {
+                match range.response_too_large() {
+                    TooLarge::Retry {
+                        from: retry_from,
+                        to: retry_to,
+                    } => {
+                        assert_eq!(to_u64(&retry_from), from);
+                        assert!(to_u64(&retry_to) < to);
+                    }
+                    TooLarge::Skip(block) => {
+                        assert_eq!((from, to), (to_u64(&block), to_u64(&block)));
+                        skipped.push(to_u64(&block));
+                    }
+                }
+            } else {
+                assert_eq!(to_u64(&range.logs_received()), to);
+                scraped.push((from, to));
+            }
+        }
+        (scraped, skipped)
+    }
+
+    fn to_u64(n: &Nat) -> u64 {
+        u64::try_from(n.0.clone()).unwrap()
+    }
+
+    #[test]
+    fn should_split_range_into_chunks() {
+        let (scraped, skipped) = scrape(0, 1_200, &[]);
+        assert_eq!(scraped, vec![(1, 501), (502, 1_002), (1_003, 1_200)]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn should_halve_chunk_and_skip_single_block() {
+        let (scraped, skipped) = scrape(9, 12, &[true, true, true, false]);
+        // [10, 12] -> [10, 11] -> [10, 10] is skipped, then [11, 12] is scraped
+        assert_eq!(scraped, vec![(11, 12)]);
+        assert_eq!(skipped, vec![10]);
+    }
+
+    proptest! {
+        #[test]
+        fn should_scrape_or_skip_every_block_exactly_once(
+            last_scraped in 0u64..2_000,
+            blocks in 0u64..3_000,
+            too_large in proptest::collection::vec(any::<bool>(), 0..200),
+        ) {
+            let last_block = last_scraped + blocks;
+            let (scraped, skipped) = scrape(last_scraped, last_block, &too_large);
+
+            let mut covered: Vec<u64> = scraped
+                .iter()
+                .flat_map(|(from, to)| *from..=*to)
+                .chain(skipped.iter().copied())
+                .collect();
+            covered.sort_unstable();
+            let expected: Vec<u64> = (last_scraped + 1..=last_block).collect();
+            prop_assert_eq!(covered, expected);
+        }
+
+        #[test]
+        fn should_scrape_in_order(
+            last_scraped in 0u64..2_000,
+            blocks in 0u64..3_000,
+            too_large in proptest::collection::vec(any::<bool>(), 0..200),
+        ) {
+            let (scraped, _) = scrape(last_scraped, last_scraped + blocks, &too_large);
+            for window in scraped.windows(2) {
+                prop_assert!(window[0].1 < window[1].0);
+            }
+        }
+
+        #[test]
+        fn should_not_query_when_already_scraped(
+            last_scraped in 0u64..2_000,
+            behind in 0u64..2_000,
+        ) {
+            let range = ScrapeRange::new(
+                Nat::from(last_scraped),
+                Nat::from(last_scraped.saturating_sub(behind)),
+            );
+            prop_assert_eq!(range.next_query(), None);
+        }
+    }
+}
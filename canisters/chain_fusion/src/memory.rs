@@ -0,0 +1,23 @@
+//! Hands out the virtual stable memories of the canister. All stable structures share one
+//! memory manager, each one uses its own memory ID.
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    DefaultMemoryImpl,
+};
+use std::cell::RefCell;
+
+// memory 0 is used by the assets in `storage.rs`
+/// The memory of the job store, see `job/store.rs`.
+pub const JOBS_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+pub type VMem = VirtualMemory<DefaultMemoryImpl>;
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+}
+
+pub fn get_memory(id: MemoryId) -> VMem {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
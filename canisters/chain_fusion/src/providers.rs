@@ -0,0 +1,605 @@
+//! Tracks the health of the individual RPC providers behind the configured `RpcServices`
+//! and rebuilds the set of providers used for scraping and submission from the healthy ones.
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize};
+use evm_rpc_canister_types::{
+    EthMainnetService, EthSepoliaService, L2MainnetService, RpcService, RpcServices,
+};
+#[cfg(target_arch = "wasm32")]
+use ic_cdk::println;
+
+use crate::{
+    state::{mutate_state, read_state},
+    time::now,
+};
+
+/// Weight of the most recent observation in the exponential moving averages.
+const SMOOTHING_FACTOR: f64 = 0.2;
+/// Providers whose score drops below this threshold are demoted.
+const DEMOTION_THRESHOLD: f64 = 0.5;
+/// Score a demoted provider is given when it is re-admitted after its demotion period.
+const PROBATION_SCORE: f64 = 0.75;
+/// How long a demoted provider is excluded from the active set.
+const DEMOTION_PERIOD_NANOS: u64 = 30 * 60 * 1_000_000_000;
+/// Minimum number of observations before a provider can be demoted.
+const MIN_OBSERVATIONS: u64 = 3;
+
+/// The outcome of a call as observed for a single provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Error,
+    /// The provider answered, but disagreed with the majority of the other providers.
+    Inconsistent,
+}
+
+/// Health record of a single RPC provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderHealth {
+    pub successes: u64,
+    pub errors: u64,
+    pub inconsistencies: u64,
+    /// Exponential moving average of the call latency in milliseconds.
+    pub avg_latency_ms: f64,
+    /// Exponential moving average of the call outcomes, where 1.0 is a success and 0.0 a failure.
+    pub score: f64,
+    /// Timestamp (in nanoseconds) until which the provider is excluded from the active set.
+    pub demoted_until: Option<u64>,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        Self {
+            successes: 0,
+            errors: 0,
+            inconsistencies: 0,
+            avg_latency_ms: 0.0,
+            score: 1.0,
+            demoted_until: None,
+        }
+    }
+}
+
+impl ProviderHealth {
+    fn observations(&self) -> u64 {
+        self.successes + self.errors + self.inconsistencies
+    }
+
+    fn record(&mut self, outcome: Outcome, latency_ms: f64, now: u64) {
+        let value = match outcome {
+            Outcome::Success => {
+                self.successes += 1;
+                1.0
+            }
+            Outcome::Error => {
+                self.errors += 1;
+                0.0
+            }
+            Outcome::Inconsistent => {
+                self.inconsistencies += 1;
+                0.0
+            }
+        };
+        self.score = ema(self.score, value);
+        self.avg_latency_ms = if self.observations() == 1 {
+            latency_ms
+        } else {
+            ema(self.avg_latency_ms, latency_ms)
+        };
+        if !self.is_demoted(now)
+            && self.observations() >= MIN_OBSERVATIONS
+            && self.score < DEMOTION_THRESHOLD
+        {
+            self.demoted_until = Some(now + DEMOTION_PERIOD_NANOS);
+        }
+    }
+
+    pub fn is_demoted(&self, now: u64) -> bool {
+        self.demoted_until.is_some_and(|until| now < until)
+    }
+
+    /// Re-admits the provider on probation if its demotion period has passed.
+    fn reinstate_if_expired(&mut self, now: u64) -> bool {
+        match self.demoted_until {
+            Some(until) if now >= until => {
+                self.demoted_until = None;
+                self.score = PROBATION_SCORE;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn ema(previous: f64, value: f64) -> f64 {
+    SMOOTHING_FACTOR * value + (1.0 - SMOOTHING_FACTOR) * previous
+}
+
+/// Public view of a provider's health returned by the `get_provider_health` query.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProviderHealthView {
+    pub provider: String,
+    pub successes: u64,
+    pub errors: u64,
+    pub inconsistencies: u64,
+    pub avg_latency_ms: f64,
+    pub score: f64,
+    pub active: bool,
+    pub demoted_until: Option<u64>,
+}
+
+/// Returns a stable identifier for an RPC service.
+///
+/// Custom providers are identified by their URL only, so that API keys passed in the
+/// headers never end up in the health records.
+pub fn provider_key(service: &RpcService) -> String {
+    match service {
+        RpcService::EthSepolia(s) => format!("EthSepolia/{s:?}"),
+        RpcService::EthMainnet(s) => format!("EthMainnet/{s:?}"),
+        RpcService::BaseMainnet(s) => format!("BaseMainnet/{s:?}"),
+        RpcService::OptimismMainnet(s) => format!("OptimismMainnet/{s:?}"),
+        RpcService::ArbitrumOne(s) => format!("ArbitrumOne/{s:?}"),
+        RpcService::Custom(api) => format!("Custom/{}", api.url),
+        RpcService::Chain(id) => format!("Chain/{id}"),
+        RpcService::Provider(id) => format!("Provider/{id}"),
+    }
+}
+
+const ETH_SEPOLIA_SERVICES: [EthSepoliaService; 4] = [
+    EthSepoliaService::Alchemy,
+    EthSepoliaService::BlockPi,
+    EthSepoliaService::PublicNode,
+    EthSepoliaService::Ankr,
+];
+
+const ETH_MAINNET_SERVICES: [EthMainnetService; 5] = [
+    EthMainnetService::Alchemy,
+    EthMainnetService::BlockPi,
+    EthMainnetService::Cloudflare,
+    EthMainnetService::PublicNode,
+    EthMainnetService::Ankr,
+];
+
+const L2_MAINNET_SERVICES: [L2MainnetService; 4] = [
+    L2MainnetService::Alchemy,
+    L2MainnetService::BlockPi,
+    L2MainnetService::PublicNode,
+    L2MainnetService::Ankr,
+];
+
+/// Expands `RpcServices` into the individual providers it is made of.
+///
+/// If no providers are specified for a known chain, all providers supported by the
+/// EVM RPC canister for that chain are returned.
+pub fn expand(services: &RpcServices) -> Vec<RpcService> {
+    match services {
+        RpcServices::EthSepolia(s) => s
+            .clone()
+            .unwrap_or(ETH_SEPOLIA_SERVICES.to_vec())
+            .into_iter()
+            .map(RpcService::EthSepolia)
+            .collect(),
+        RpcServices::EthMainnet(s) => s
+            .clone()
+            .unwrap_or(ETH_MAINNET_SERVICES.to_vec())
+            .into_iter()
+            .map(RpcService::EthMainnet)
+            .collect(),
+        RpcServices::BaseMainnet(s) => s
+            .clone()
+            .unwrap_or(L2_MAINNET_SERVICES.to_vec())
+            .into_iter()
+            .map(RpcService::BaseMainnet)
+            .collect(),
+        RpcServices::OptimismMainnet(s) => s
+            .clone()
+            .unwrap_or(L2_MAINNET_SERVICES.to_vec())
+            .into_iter()
+            .map(RpcService::OptimismMainnet)
+            .collect(),
+        RpcServices::ArbitrumOne(s) => s
+            .clone()
+            .unwrap_or(L2_MAINNET_SERVICES.to_vec())
+            .into_iter()
+            .map(RpcService::ArbitrumOne)
+            .collect(),
+        RpcServices::Custom { services, .. } => {
+            services.iter().cloned().map(RpcService::Custom).collect()
+        }
+    }
+}
+
+/// Rebuilds `RpcServices` of the same kind as `configured`, keeping only the providers
+/// for which `keep` returns `true`.
+fn retain(configured: &RpcServices, keep: impl Fn(&RpcService) -> bool) -> RpcServices {
+    let kept: Vec<RpcService> = expand(configured).into_iter().filter(keep).collect();
+    match configured {
+        RpcServices::EthSepolia(_) => RpcServices::EthSepolia(Some(
+            kept.into_iter()
+                .filter_map(|s| match s {
+                    RpcService::EthSepolia(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        RpcServices::EthMainnet(_) => RpcServices::EthMainnet(Some(
+            kept.into_iter()
+                .filter_map(|s| match s {
+                    RpcService::EthMainnet(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        RpcServices::BaseMainnet(_) => RpcServices::BaseMainnet(Some(
+            kept.into_iter()
+                .filter_map(|s| match s {
+                    RpcService::BaseMainnet(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        RpcServices::OptimismMainnet(_) => RpcServices::OptimismMainnet(Some(
+            kept.into_iter()
+                .filter_map(|s| match s {
+                    RpcService::OptimismMainnet(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        RpcServices::ArbitrumOne(_) => RpcServices::ArbitrumOne(Some(
+            kept.into_iter()
+                .filter_map(|s| match s {
+                    RpcService::ArbitrumOne(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        RpcServices::Custom {
+            chainId: chain_id, ..
+        } => RpcServices::Custom {
+            chainId: *chain_id,
+            services: kept
+                .into_iter()
+                .filter_map(|s| match s {
+                    RpcService::Custom(api) => Some(api),
+                    _ => None,
+                })
+                .collect(),
+        },
+    }
+}
+
+/// Computes the active `RpcServices` from the configured ones, excluding demoted providers.
+///
+/// If every provider is demoted, the one with the highest score is kept so that the
+/// canister never ends up without a provider.
+pub fn active_services(
+    configured: &RpcServices,
+    health: &BTreeMap<String, ProviderHealth>,
+    now: u64,
+) -> RpcServices {
+    let is_demoted = |service: &RpcService| {
+        health
+            .get(&provider_key(service))
+            .is_some_and(|h| h.is_demoted(now))
+    };
+    if expand(configured).iter().any(|s| !is_demoted(s)) {
+        return retain(configured, |s| !is_demoted(s));
+    }
+    let score = |service: &RpcService| health.get(&provider_key(service)).map_or(1.0, |h| h.score);
+    let best = expand(configured)
+        .into_iter()
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+        .map(|s| provider_key(&s));
+    retain(configured, |s| Some(provider_key(s)) == best)
+}
+
+/// Records the outcomes of a call to the EVM RPC canister and rebuilds the active set of
+/// providers if the health of any provider changed its status.
+///
+/// # Arguments
+///
+/// * `outcomes` - The outcome of the call for every provider that was queried.
+/// * `started_at` - The time (in nanoseconds) the call was issued.
+pub fn record_outcomes(outcomes: Vec<(RpcService, Outcome)>, started_at: u64) {
+    let now = now();
+    let latency_ms = now.saturating_sub(started_at) as f64 / 1_000_000.0;
+    mutate_state(|s| {
+        let mut changed = false;
+        for health in s.provider_health.values_mut() {
+            changed |= health.reinstate_if_expired(now);
+        }
+        for (service, outcome) in outcomes {
+            let health = s.provider_health.entry(provider_key(&service)).or_default();
+            let was_demoted = health.is_demoted(now);
+            health.record(outcome, latency_ms, now);
+            changed |= was_demoted != health.is_demoted(now);
+        }
+        if changed {
+            s.active_rpc_services = active_services(&s.rpc_services, &s.provider_health, now);
+            println!(
+                "[providers]: rebuilt active RPC services: {:?}",
+                s.active_rpc_services
+            );
+        }
+    });
+}
+
+/// Records the same outcome for every provider in `services`.
+///
+/// Used for consistent results, where every queried provider agreed.
+pub fn record_consistent(services: &RpcServices, outcome: Outcome, started_at: u64) {
+    record_outcomes(
+        expand(services).into_iter().map(|s| (s, outcome)).collect(),
+        started_at,
+    );
+}
+
+/// Records the outcomes of an inconsistent call.
+///
+/// Providers that returned an error are recorded as failed. Among the remaining providers,
+/// those that agree with the largest group of identical responses are recorded as
+/// successful and all others receive an inconsistency vote.
+pub fn record_inconsistent<T: CandidType>(
+    results: &[(RpcService, T)],
+    is_err: impl Fn(&T) -> bool,
+    started_at: u64,
+) {
+    record_outcomes(inconsistent_outcomes(results, is_err), started_at);
+}
+
+fn inconsistent_outcomes<T: CandidType>(
+    results: &[(RpcService, T)],
+    is_err: impl Fn(&T) -> bool,
+) -> Vec<(RpcService, Outcome)> {
+    // responses are compared by their candid encoding, as the result types do not
+    // implement `PartialEq`.
+    let fingerprints: Vec<Option<Vec<u8>>> = results
+        .iter()
+        .map(|(_, r)| {
+            if is_err(r) {
+                None
+            } else {
+                candid::encode_one(r).ok()
+            }
+        })
+        .collect();
+    let mut votes: BTreeMap<&Vec<u8>, usize> = BTreeMap::new();
+    for fingerprint in fingerprints.iter().flatten() {
+        *votes.entry(fingerprint).or_default() += 1;
+    }
+    let majority = votes
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .filter(|(_, count)| votes.values().filter(|c| c == count).count() == 1)
+        .map(|(fingerprint, _)| fingerprint.to_vec());
+
+    results
+        .iter()
+        .zip(fingerprints.iter())
+        .map(|((service, _), fingerprint)| {
+            let outcome = match fingerprint {
+                None => Outcome::Error,
+                Some(f) if Some(f) == majority.as_ref() => Outcome::Success,
+                Some(_) => Outcome::Inconsistent,
+            };
+            (service.clone(), outcome)
+        })
+        .collect()
+}
+
+/// Returns the health of all providers that were observed so far.
+pub fn provider_health() -> Vec<ProviderHealthView> {
+    let now = now();
+    read_state(|s| {
+        let active: Vec<String> = expand(&s.active_rpc_services)
+            .iter()
+            .map(provider_key)
+            .collect();
+        expand(&s.rpc_services)
+            .iter()
+            .map(|service| {
+                let key = provider_key(service);
+                let health = s.provider_health.get(&key).cloned().unwrap_or_default();
+                ProviderHealthView {
+                    active: active.contains(&key),
+                    provider: key,
+                    successes: health.successes,
+                    errors: health.errors,
+                    inconsistencies: health.inconsistencies,
+                    avg_latency_ms: health.avg_latency_ms,
+                    score: health.score,
+                    demoted_until: health.demoted_until.filter(|until| now < *until),
+                }
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000_000_000;
+
+    fn sepolia(services: &[EthSepoliaService]) -> RpcServices {
+        RpcServices::EthSepolia(Some(services.to_vec()))
+    }
+
+    fn keys(services: &RpcServices) -> Vec<String> {
+        expand(services).iter().map(provider_key).collect()
+    }
+
+    fn outcome_keys(outcomes: Vec<(RpcService, Outcome)>) -> Vec<(String, Outcome)> {
+        outcomes
+            .into_iter()
+            .map(|(service, outcome)| (provider_key(&service), outcome))
+            .collect()
+    }
+
+    fn demoted(score: f64) -> ProviderHealth {
+        ProviderHealth {
+            errors: MIN_OBSERVATIONS,
+            score,
+            demoted_until: Some(NOW + DEMOTION_PERIOD_NANOS),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_average_score_and_latency() {
+        let mut health = ProviderHealth::default();
+
+        health.record(Outcome::Success, 100.0, NOW);
+        assert_eq!(health.score, 1.0);
+        assert_eq!(health.avg_latency_ms, 100.0);
+
+        health.record(Outcome::Error, 200.0, NOW);
+        assert!((health.score - 0.8).abs() < 1e-9);
+        assert!((health.avg_latency_ms - 120.0).abs() < 1e-9);
+
+        health.record(Outcome::Inconsistent, 120.0, NOW);
+        assert!((health.score - 0.64).abs() < 1e-9);
+        assert_eq!(
+            (health.successes, health.errors, health.inconsistencies),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn should_demote_provider_once_score_drops_below_threshold() {
+        let mut health = ProviderHealth::default();
+        for _ in 0..3 {
+            health.record(Outcome::Error, 10.0, NOW);
+        }
+        // 0.8^3 = 0.512 is still above the threshold
+        assert!(!health.is_demoted(NOW));
+
+        health.record(Outcome::Error, 10.0, NOW);
+
+        assert_eq!(health.demoted_until, Some(NOW + DEMOTION_PERIOD_NANOS));
+        assert!(health.is_demoted(NOW + DEMOTION_PERIOD_NANOS - 1));
+        assert!(!health.is_demoted(NOW + DEMOTION_PERIOD_NANOS));
+    }
+
+    #[test]
+    fn should_not_demote_provider_with_few_observations() {
+        let mut health = ProviderHealth {
+            score: 0.1,
+            ..Default::default()
+        };
+
+        health.record(Outcome::Error, 10.0, NOW);
+
+        assert_eq!(health.demoted_until, None);
+    }
+
+    #[test]
+    fn should_reinstate_provider_on_probation_after_demotion_period() {
+        let mut health = demoted(0.3);
+
+        assert!(!health.reinstate_if_expired(NOW));
+        assert!(health.is_demoted(NOW));
+
+        assert!(health.reinstate_if_expired(NOW + DEMOTION_PERIOD_NANOS));
+        assert_eq!(health.demoted_until, None);
+        assert_eq!(health.score, PROBATION_SCORE);
+        assert!(!health.reinstate_if_expired(NOW + DEMOTION_PERIOD_NANOS));
+    }
+
+    #[test]
+    fn should_exclude_demoted_providers_from_active_services() {
+        let configured = sepolia(&[EthSepoliaService::Alchemy, EthSepoliaService::Ankr]);
+        let health = BTreeMap::from([(
+            provider_key(&RpcService::EthSepolia(EthSepoliaService::Alchemy)),
+            demoted(0.3),
+        )]);
+
+        assert_eq!(
+            keys(&active_services(&configured, &health, NOW)),
+            keys(&sepolia(&[EthSepoliaService::Ankr]))
+        );
+        // the demotion has expired
+        assert_eq!(
+            keys(&active_services(
+                &configured,
+                &health,
+                NOW + DEMOTION_PERIOD_NANOS
+            )),
+            keys(&configured)
+        );
+    }
+
+    #[test]
+    fn should_keep_best_provider_if_all_are_demoted() {
+        let configured = sepolia(&[
+            EthSepoliaService::Alchemy,
+            EthSepoliaService::BlockPi,
+            EthSepoliaService::Ankr,
+        ]);
+        let health = BTreeMap::from([
+            (
+                provider_key(&RpcService::EthSepolia(EthSepoliaService::Alchemy)),
+                demoted(0.2),
+            ),
+            (
+                provider_key(&RpcService::EthSepolia(EthSepoliaService::BlockPi)),
+                demoted(0.4),
+            ),
+            (
+                provider_key(&RpcService::EthSepolia(EthSepoliaService::Ankr)),
+                demoted(0.3),
+            ),
+        ]);
+
+        assert_eq!(
+            keys(&active_services(&configured, &health, NOW)),
+            keys(&sepolia(&[EthSepoliaService::BlockPi]))
+        );
+    }
+
+    #[test]
+    fn should_vote_inconsistent_responses_by_majority() {
+        let alchemy = RpcService::EthSepolia(EthSepoliaService::Alchemy);
+        let block_pi = RpcService::EthSepolia(EthSepoliaService::BlockPi);
+        let public_node = RpcService::EthSepolia(EthSepoliaService::PublicNode);
+        let ankr = RpcService::EthSepolia(EthSepoliaService::Ankr);
+        let results: Vec<(RpcService, Result<u64, String>)> = vec![
+            (alchemy.clone(), Ok(7)),
+            (block_pi.clone(), Ok(8)),
+            (public_node.clone(), Ok(7)),
+            (ankr.clone(), Err("timeout".to_string())),
+        ];
+
+        let outcomes = outcome_keys(inconsistent_outcomes(&results, Result::is_err));
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (provider_key(&alchemy), Outcome::Success),
+                (provider_key(&block_pi), Outcome::Inconsistent),
+                (provider_key(&public_node), Outcome::Success),
+                (provider_key(&ankr), Outcome::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_reward_any_provider_without_majority() {
+        let alchemy = RpcService::EthSepolia(EthSepoliaService::Alchemy);
+        let ankr = RpcService::EthSepolia(EthSepoliaService::Ankr);
+        let results: Vec<(RpcService, Result<u64, String>)> =
+            vec![(alchemy.clone(), Ok(7)), (ankr.clone(), Ok(8))];
+
+        let outcomes = outcome_keys(inconsistent_outcomes(&results, Result::is_err));
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (provider_key(&alchemy), Outcome::Inconsistent),
+                (provider_key(&ankr), Outcome::Inconsistent)
+            ]
+        );
+    }
+}
@@ -1,12 +1,23 @@
 use evm_rpc_canister_types::{BlockTag, LogEntry, RpcService, RpcServices};
 
-use candid::Nat;
-use ethers_core::types::U256;
-use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
+use candid::{CandidType, Deserialize, Nat};
+use ethers_core::types::{H256, U256};
+use ic_cdk_timers::TimerId;
+use ic_evm_utils::eth_send_raw_transaction::TransactionType;
+use ic_evm_utils::evm_signer::ThresholdEcdsaSigner;
+use ic_evm_utils::fees::FeeStrategy;
 use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::str::FromStr;
 
 use std::cell::RefCell;
 
+use crate::{
+    accounts::{Account, DerivationPath},
+    job::{BatchedResult, Callback, JobFee, Ledger},
+    lifecycle::{FeeStrategyArg, SubmissionMode},
+    providers::ProviderHealth,
+};
+
 thread_local! {
     static STATE: RefCell<Option<State>> = RefCell::default();
 }
@@ -14,6 +25,9 @@ thread_local! {
 #[derive(Debug, Clone)]
 pub struct State {
     pub rpc_services: RpcServices,
+    /// The subset of `rpc_services` currently considered healthy, see `providers.rs`.
+    pub active_rpc_services: RpcServices,
+    pub provider_health: BTreeMap<String, ProviderHealth>,
     pub rpc_service: RpcService,
     pub get_logs_addresses: Vec<String>,
     pub get_logs_topics: Option<Vec<Vec<String>>>,
@@ -23,17 +37,33 @@ pub struct State {
     pub processed_logs: BTreeMap<LogSource, LogEntry>,
     pub skipped_blocks: BTreeSet<Nat>,
     pub active_tasks: HashSet<TaskType>,
-    pub ecdsa_pub_key: Option<Vec<u8>>,
-    pub ecdsa_key_id: EcdsaKeyId,
-    pub evm_address: Option<String>,
-    pub nonce: U256,
+    /// Signs the transactions of the canister and caches the public keys of its derivation paths.
+    pub signer: ThresholdEcdsaSigner,
+    /// The EVM accounts derived so far, keyed by their derivation path, see `accounts.rs`.
+    pub accounts: BTreeMap<DerivationPath, Account>,
     pub block_tag: BlockTag,
+    pub fee_strategy: FeeStrategyArg,
+    pub transaction_type: TransactionType,
+    pub submission_mode: SubmissionMode,
+    /// The configured callbacks, keyed by the topic of their event.
+    pub callbacks: BTreeMap<H256, Callback>,
+    /// The results waiting for the next `batchCallback` transaction in batch mode.
+    pub pending_batch: Vec<BatchedResult>,
+    /// The timer that sends the pending batch.
+    pub batch_timer: Option<TimerId>,
+    /// The payment required for jobs, `None` if jobs are free.
+    pub job_fee: Option<JobFee>,
+    /// The payments and gas costs of the jobs, see `job/ledger.rs`.
+    pub ledger: Ledger,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum InvalidStateError {
     InvalidEthereumContractAddress(String),
     InvalidTopic(String),
+    InvalidFeeStrategy(String),
+    InvalidCallback(String),
+    InvalidSubmissionMode(String),
 }
 
 impl State {
@@ -74,19 +104,81 @@ impl State {
     }
 
     pub fn rpc_services(&self) -> RpcServices {
-        self.rpc_services.clone()
+        self.active_rpc_services.clone()
+    }
+
+    pub fn rpc_service(&self) -> RpcService {
+        self.rpc_service.clone()
     }
 
-    pub fn key_id(&self) -> EcdsaKeyId {
-        self.ecdsa_key_id.clone()
+    pub fn signer(&self) -> ThresholdEcdsaSigner {
+        self.signer.clone()
     }
 
     pub fn get_logs_addresses(&self) -> Vec<String> {
         self.get_logs_addresses.clone()
     }
 
-    pub fn nonce(&self) -> U256 {
-        self.nonce
+    pub fn fee_strategy(&self) -> Box<dyn FeeStrategy> {
+        self.fee_strategy.to_fee_strategy()
+    }
+
+    pub fn submission_mode(&self) -> SubmissionMode {
+        self.submission_mode
+    }
+
+    /// Returns the callback for the results of the jobs triggered by `event`.
+    pub fn callback(&self, event: &LogEntry) -> Callback {
+        event
+            .topics
+            .first()
+            .and_then(|topic| H256::from_str(topic).ok())
+            .and_then(|topic| self.callbacks.get(&topic).cloned())
+            .unwrap_or_else(Callback::coprocessor)
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        self.transaction_type
+    }
+
+    pub fn evm_address(&self, derivation_path: &[Vec<u8>]) -> Option<String> {
+        self.accounts
+            .get(derivation_path)
+            .map(|account| account.address.clone())
+    }
+
+    /// Reserves the nonce of the next transaction sent from the account of
+    /// `derivation_path`. Call it before the first await, so that transactions sent by other
+    /// tasks in the meantime use the next nonce. Nonces given back with `release_nonce` are
+    /// reused first, so that no gap stalls the later transactions.
+    pub fn reserve_nonce(&mut self, derivation_path: &[Vec<u8>]) -> U256 {
+        let account = self
+            .accounts
+            .get_mut(derivation_path)
+            .expect("BUG: nonce of an unknown account reserved");
+        if let Some(nonce) = account.unused_nonces.pop_first() {
+            return nonce;
+        }
+        let nonce = account.nonce;
+        account.nonce += U256::from(1);
+        nonce
+    }
+
+    /// Gives back a nonce reserved for a transaction that was not signed.
+    pub fn release_nonce(&mut self, derivation_path: &[Vec<u8>], nonce: U256) {
+        let account = self
+            .accounts
+            .get_mut(derivation_path)
+            .expect("BUG: nonce of an unknown account released");
+        account.unused_nonces.insert(nonce);
+        // the unused nonces at the end are handed out by incrementing the nonce again
+        while let Some(last) = account.unused_nonces.last().copied() {
+            if last + U256::from(1) != account.nonce {
+                break;
+            }
+            account.unused_nonces.pop_last();
+            account.nonce = last;
+        }
     }
 }
 
@@ -111,7 +203,7 @@ impl IntoLogSource for LogEntry {
 
 /// A unique identifier of the event source: the source transaction hash and the log
 /// entry index.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LogSource {
     pub transaction_hash: String,
     pub log_index: Nat,
@@ -1,16 +1,13 @@
 use ic_stable_structures::{
-    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    storable::Bound,
-    storable::Storable,
-    DefaultMemoryImpl, StableBTreeMap,
+    memory_manager::MemoryId, storable::Bound, storable::Storable, StableBTreeMap,
 };
 use minicbor_derive::{Decode, Encode};
 use std::borrow::Cow;
 use std::cell::RefCell;
 
-const ASSETS_MEMORY_ID: MemoryId = MemoryId::new(0);
+use crate::memory::{get_memory, VMem};
 
-type VMem = VirtualMemory<DefaultMemoryImpl>;
+const ASSETS_MEMORY_ID: MemoryId = MemoryId::new(0);
 
 pub type AssetKey = String;
 type HeaderField = (String, String);
@@ -41,17 +38,9 @@ impl Storable for Asset {
 }
 
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
     // Initialize a `StableBTreeMap`
-    static ASSETS : RefCell<StableBTreeMap<AssetKey, Asset, VMem>> = MEMORY_MANAGER
-        .with(|m|
-            RefCell::new(
-                StableBTreeMap::init(
-                    m.borrow().get(ASSETS_MEMORY_ID)
-                )
-            )
+    static ASSETS : RefCell<StableBTreeMap<AssetKey, Asset, VMem>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ASSETS_MEMORY_ID))
     );
 }
 
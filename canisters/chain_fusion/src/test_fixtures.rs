@@ -0,0 +1,68 @@
+//! Helpers shared by the unit tests of the canister.
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+
+use candid::Nat;
+use evm_rpc_canister_types::{BlockTag, EthSepoliaService, LogEntry, RpcService, RpcServices};
+use ic_cdk::api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId};
+
+use crate::lifecycle::InitArg;
+
+/// The address of the `Coprocessor` contract deployed by `deploy.sh`.
+pub const CONTRACT_ADDRESS: &str = "0x5fbdb2315678afecb367f032d93f642f64180aa3";
+/// The topic of `NewJob(uint256,uint256)` events.
+pub const NEW_JOB_TOPIC: &str =
+    "0x99dc261edd638a734d0a114ada8bbce801eb101dfaa456f4bdcb1e9cfaa058ab";
+/// The wei paid for the jobs of `new_job_log`, i.e. the minimum payment of
+/// `Coprocessor.newJob`.
+pub const JOB_PAYMENT: u64 = 10_000_000_000_000_000;
+
+/// Returns an init arg scraping `CONTRACT_ADDRESS` with the defaults of the optional fields.
+pub fn init_arg() -> InitArg {
+    InitArg {
+        rpc_services: RpcServices::EthSepolia(None),
+        rpc_service: RpcService::EthSepolia(EthSepoliaService::Alchemy),
+        get_logs_addresses: vec![CONTRACT_ADDRESS.to_string()],
+        get_logs_topics: None,
+        last_scraped_block_number: Nat::from(0u32),
+        ecdsa_key_id: EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: "dfx_test_key".to_string(),
+        },
+        block_tag: BlockTag::Finalized,
+        fee_strategy: None,
+        transaction_type: None,
+        submission_mode: None,
+        callbacks: None,
+        job_fee: None,
+    }
+}
+
+/// Drives a future that never has to wait to completion, e.g. one of a job handler or one
+/// using the `MockEvmRpcClient`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future should never be pending"),
+    }
+}
+
+/// Returns the `NewJob` event of `job_id` emitted by `CONTRACT_ADDRESS` in `block_number`
+/// paying `JOB_PAYMENT`, every job is created by its own transaction.
+pub fn new_job_log(job_id: u64, block_number: u64) -> LogEntry {
+    LogEntry {
+        transactionHash: Some(format!("0x{:064x}", 0x1000 + job_id)),
+        blockNumber: Some(Nat::from(block_number)),
+        data: format!("0x{JOB_PAYMENT:064x}"),
+        blockHash: Some(format!("0x{block_number:064x}")),
+        transactionIndex: Some(Nat::from(0u32)),
+        topics: vec![NEW_JOB_TOPIC.to_string(), format!("0x{job_id:064x}")],
+        address: CONTRACT_ADDRESS.to_string(),
+        logIndex: Some(Nat::from(0u32)),
+        removed: false,
+    }
+}
@@ -0,0 +1,17 @@
+//! Time helpers shared by the modules that timestamp what they record.
+
+/// Returns the current time in nanoseconds. Outside of a canister, e.g. in unit tests, the
+/// system clock is used instead of the IC's.
+pub fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::time()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system time should be after the unix epoch")
+            .as_nanos() as u64
+    }
+}
@@ -0,0 +1,305 @@
+//! Integration tests running the full coprocessor loop in PocketIC: the `chain_fusion`
+//! canister scrapes `NewJob` logs from the `evm_rpc_fake` canister, which is installed with
+//! the ID of the EVM RPC canister, and sends the signed `callback` transactions back to it.
+//!
+//! The tests need the wasms of both canisters and a PocketIC server, so they are ignored by
+//! default. `./test.sh` builds and downloads everything and runs them.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use candid::{decode_one, encode_args, encode_one, CandidType, Deserialize, Nat, Principal};
+use candid_parser::IDLArgs;
+use ethers_core::abi::{encode, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, NameOrAddress, U256};
+use ethers_core::utils::{id, rlp::Rlp};
+use pocket_ic::{PocketIc, PocketIcBuilder, WasmResult};
+
+/// The ID `chain_fusion` calls the EVM RPC canister at, see `EVM_RPC`.
+const EVM_RPC_ID: &str = "7hfb6-caaaa-aaaar-qadga-cai";
+const CONTRACT_ADDRESS: &str = "0x5fbdb2315678afecb367f032d93f642f64180aa3";
+/// The topic of `NewJob(uint256,uint256)` events.
+const NEW_JOB_TOPIC: &str = "0x99dc261edd638a734d0a114ada8bbce801eb101dfaa456f4bdcb1e9cfaa058ab";
+/// The wei paid for every job, i.e. the minimum payment of `Coprocessor.newJob`.
+const JOB_PAYMENT: u64 = 10_000_000_000_000_000;
+const CHAIN_ID: u64 = 31_337;
+const MAX_FEE_PER_GAS: u64 = 2_000_000_000;
+const MAX_PRIORITY_FEE_PER_GAS: u64 = 1_000_000_000;
+/// The result of the `fibonacci(20)` job.
+const JOB_RESULT: &str = "6765";
+
+/// The init arg of `evm_rpc_fake`.
+#[derive(CandidType)]
+struct FakeInitArg {
+    chain_id: u64,
+    base_fee_per_gas: Option<Nat>,
+}
+
+/// The arg of `evm_rpc_fake`'s `inject_log`.
+#[derive(CandidType)]
+struct InjectLogArg {
+    address: String,
+    topics: Vec<String>,
+    data: Option<String>,
+}
+
+struct Setup {
+    pic: PocketIc,
+    chain_fusion: Principal,
+    evm_rpc: Principal,
+}
+
+impl Setup {
+    fn new() -> Self {
+        // the EVM RPC canister lives on the fiduciary subnet and threshold ECDSA keys on
+        // the II subnet
+        let pic = PocketIcBuilder::new()
+            .with_fiduciary_subnet()
+            .with_ii_subnet()
+            .with_application_subnet()
+            .build();
+
+        let evm_rpc = Principal::from_text(EVM_RPC_ID).unwrap();
+        pic.create_canister_with_id(None, None, evm_rpc)
+            .expect("should create the EVM RPC canister");
+        pic.add_cycles(evm_rpc, 100_000_000_000_000);
+        pic.install_canister(
+            evm_rpc,
+            wasm("evm_rpc_fake"),
+            encode_one(FakeInitArg {
+                chain_id: CHAIN_ID,
+                base_fee_per_gas: None,
+            })
+            .unwrap(),
+            None,
+        );
+
+        let app_subnet = pic.topology().get_app_subnets()[0];
+        let chain_fusion = pic.create_canister_on_subnet(None, None, app_subnet);
+        pic.add_cycles(chain_fusion, 100_000_000_000_000);
+        pic.install_canister(chain_fusion, wasm("chain_fusion"), init_arg(), None);
+
+        Self {
+            pic,
+            chain_fusion,
+            evm_rpc,
+        }
+    }
+
+    /// Mines a block with a `NewJob` event for `job_id` paying `JOB_PAYMENT` on the fake
+    /// chain.
+    fn inject_new_job(&self, job_id: u64) {
+        update(
+            &self.pic,
+            self.evm_rpc,
+            "inject_log",
+            encode_one(InjectLogArg {
+                address: CONTRACT_ADDRESS.to_string(),
+                topics: vec![NEW_JOB_TOPIC.to_string(), format!("0x{job_id:064x}")],
+                data: Some(format!("0x{JOB_PAYMENT:064x}")),
+            })
+            .unwrap(),
+        );
+    }
+
+    /// Replaces the last `depth` blocks of the fake chain with empty ones.
+    fn reorg(&self, depth: u64) {
+        update(&self.pic, self.evm_rpc, "reorg", encode_one(depth).unwrap());
+    }
+
+    fn sent_transactions(&self) -> Vec<(TypedTransaction, Address)> {
+        query::<Vec<String>>(
+            &self.pic,
+            self.evm_rpc,
+            "sent_transactions",
+            encode_args(()).unwrap(),
+        )
+        .iter()
+        .map(|tx_hex| {
+            let bytes = hex::decode(tx_hex.trim_start_matches("0x")).unwrap();
+            let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(&bytes))
+                .expect("should be a signed transaction");
+            let from = signature.recover(tx.sighash()).unwrap();
+            (tx, from)
+        })
+        .collect()
+    }
+
+    fn evm_address(&self) -> Address {
+        query::<Option<String>>(
+            &self.pic,
+            self.chain_fusion,
+            "get_evm_address",
+            encode_one(None::<Vec<Vec<u8>>>).unwrap(),
+        )
+        .expect("the default account should be derived")
+        .parse()
+        .unwrap()
+    }
+
+    /// Advances the time past the first scraping of logs and executes rounds until
+    /// `expected_transactions` were sent.
+    fn run_until_sent(&self, expected_transactions: usize) {
+        self.pic.advance_time(Duration::from_secs(15));
+        for _ in 0..100 {
+            self.pic.tick();
+            if self.sent_transactions().len() >= expected_transactions {
+                return;
+            }
+        }
+        panic!(
+            "expected {expected_transactions} transactions, got {}",
+            self.sent_transactions().len()
+        );
+    }
+}
+
+fn wasm(name: &str) -> Vec<u8> {
+    let path = std::env::var(format!("{}_WASM_PATH", name.to_uppercase()))
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../../target/wasm32-unknown-unknown/release")
+                .join(format!("{name}.wasm"))
+        });
+    std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}, run ./test.sh: {e}", path.display()))
+}
+
+fn init_arg() -> Vec<u8> {
+    let init_arg = format!(
+        r#"(
+          record {{
+            ecdsa_key_id = record {{ name = "dfx_test_key"; curve = variant {{ secp256k1 }} }};
+            get_logs_topics = opt vec {{ vec {{ "{NEW_JOB_TOPIC}" }} }};
+            last_scraped_block_number = 0 : nat;
+            rpc_services = variant {{
+              Custom = record {{
+                chainId = {CHAIN_ID} : nat64;
+                services = vec {{ record {{ url = "http://localhost:8545"; headers = null }} }};
+              }}
+            }};
+            rpc_service = variant {{ Custom = record {{ url = "http://localhost:8545"; headers = null }} }};
+            get_logs_addresses = vec {{ "{CONTRACT_ADDRESS}" }};
+            block_tag = variant {{ Latest }};
+            fee_strategy = opt variant {{
+              Fixed = record {{
+                max_fee_per_gas = {MAX_FEE_PER_GAS} : nat;
+                max_priority_fee_per_gas = {MAX_PRIORITY_FEE_PER_GAS} : nat;
+              }}
+            }};
+            transaction_type = opt variant {{ Eip1559 }};
+            submission_mode = opt variant {{ Callback }};
+          }},
+        )"#
+    );
+    init_arg
+        .parse::<IDLArgs>()
+        .expect("should parse the init arg")
+        .to_bytes()
+        .unwrap()
+}
+
+/// Makes an update call and returns the raw reply.
+fn update(pic: &PocketIc, canister: Principal, method: &str, arg: Vec<u8>) -> Vec<u8> {
+    match pic.update_call(canister, Principal::anonymous(), method, arg) {
+        Ok(WasmResult::Reply(bytes)) => bytes,
+        Ok(WasmResult::Reject(message)) => panic!("{method} was rejected: {message}"),
+        Err(e) => panic!("{method} failed: {e}"),
+    }
+}
+
+fn query<T: CandidType + for<'de> Deserialize<'de>>(
+    pic: &PocketIc,
+    canister: Principal,
+    method: &str,
+    arg: Vec<u8>,
+) -> T {
+    match pic.query_call(canister, Principal::anonymous(), method, arg) {
+        Ok(WasmResult::Reply(bytes)) => decode_one(&bytes).unwrap(),
+        Ok(WasmResult::Reject(message)) => panic!("{method} was rejected: {message}"),
+        Err(e) => panic!("{method} failed: {e}"),
+    }
+}
+
+fn callback_calldata(job_id: u64) -> Vec<u8> {
+    let mut calldata = id("callback(string,uint256)").to_vec();
+    calldata.extend(encode(&[
+        Token::String(JOB_RESULT.to_string()),
+        Token::Uint(U256::from(job_id)),
+    ]));
+    calldata
+}
+
+#[test]
+#[ignore = "requires the canister wasms and a PocketIC server, run ./test.sh"]
+fn should_submit_callback_for_new_job() {
+    let setup = Setup::new();
+    setup.inject_new_job(1);
+
+    setup.run_until_sent(1);
+
+    let transactions = setup.sent_transactions();
+    assert_eq!(transactions.len(), 1);
+    let (tx, from) = &transactions[0];
+    assert!(matches!(tx, TypedTransaction::Eip1559(_)));
+    assert_eq!(*from, setup.evm_address());
+    assert_eq!(tx.chain_id().map(|id| id.as_u64()), Some(CHAIN_ID));
+    assert_eq!(tx.nonce(), Some(&U256::zero()));
+    assert_eq!(
+        tx.to(),
+        Some(&NameOrAddress::Address(CONTRACT_ADDRESS.parse().unwrap()))
+    );
+    assert_eq!(
+        tx.data().map(|data| data.to_vec()),
+        Some(callback_calldata(1))
+    );
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            assert_eq!(tx.max_fee_per_gas, Some(U256::from(MAX_FEE_PER_GAS)));
+            assert_eq!(
+                tx.max_priority_fee_per_gas,
+                Some(U256::from(MAX_PRIORITY_FEE_PER_GAS))
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+#[ignore = "requires the canister wasms and a PocketIC server, run ./test.sh"]
+fn should_increment_nonce_for_consecutive_jobs() {
+    let setup = Setup::new();
+    setup.inject_new_job(1);
+    setup.inject_new_job(2);
+
+    setup.run_until_sent(2);
+
+    let transactions = setup.sent_transactions();
+    let nonces: Vec<_> = transactions
+        .iter()
+        .map(|(tx, _)| *tx.nonce().unwrap())
+        .collect();
+    assert_eq!(nonces, vec![U256::zero(), U256::one()]);
+    let calldata: Vec<_> = transactions
+        .iter()
+        .map(|(tx, _)| tx.data().unwrap().to_vec())
+        .collect();
+    assert_eq!(calldata, vec![callback_calldata(1), callback_calldata(2)]);
+}
+
+#[test]
+#[ignore = "requires the canister wasms and a PocketIC server, run ./test.sh"]
+fn should_not_submit_callback_for_logs_dropped_by_reorg() {
+    let setup = Setup::new();
+    // the log is dropped from the chain before it is scraped, so it must not run a job
+    setup.inject_new_job(1);
+    setup.reorg(1);
+
+    setup.pic.advance_time(Duration::from_secs(15));
+    for _ in 0..20 {
+        setup.pic.tick();
+    }
+
+    assert!(setup.sent_transactions().is_empty());
+}
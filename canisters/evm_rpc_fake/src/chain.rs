@@ -0,0 +1,505 @@
+//! The in-memory chain backing the fake EVM RPC canister.
+//!
+//! The chain does not execute transactions: every accepted transaction is mined into its
+//! own block with a successful receipt and only bumps the nonce of its sender. Events are
+//! not emitted by contracts but injected, and reorgs replace the most recent blocks.
+use std::collections::BTreeMap;
+
+use candid::Nat;
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, H256, U256};
+use ethers_core::utils::{keccak256, rlp::Rlp};
+use evm_rpc_canister_types::{
+    Block, BlockTag, FeeHistory, GetLogsArgs, LogEntry, SendRawTransactionStatus,
+    TransactionReceipt,
+};
+
+/// The priority fee paid by the transactions of the chain, reported by `eth_feeHistory`.
+pub const PRIORITY_FEE_PER_GAS: u128 = 1_000_000_000;
+/// The gas limit of every block.
+const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// A block of the fake chain.
+#[derive(Debug, Clone)]
+pub struct FakeBlock {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    /// The timestamp of the block in seconds.
+    pub timestamp: u64,
+    pub logs: Vec<LogEntry>,
+    pub transactions: Vec<FakeTransaction>,
+}
+
+/// A transaction mined into a block of the fake chain.
+#[derive(Debug, Clone)]
+pub struct FakeTransaction {
+    pub hash: H256,
+    pub from: Address,
+    pub nonce: u64,
+    pub receipt: TransactionReceipt,
+    /// The signed transaction as it was sent.
+    pub raw: Vec<u8>,
+}
+
+/// Why a raw transaction was rejected before being mined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The transaction could not be decoded or its signature recovered.
+    Invalid(String),
+    WrongChainId {
+        expected: u64,
+        actual: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub chain_id: u64,
+    pub base_fee_per_gas: u128,
+    /// The blocks of the canonical chain, indexed by their number.
+    blocks: Vec<FakeBlock>,
+    nonces: BTreeMap<Address, u64>,
+    balances: BTreeMap<Address, U256>,
+    /// The number of reorgs so far, mixed into block hashes so that replaced blocks differ.
+    reorgs: u64,
+}
+
+impl Chain {
+    /// Creates a chain with only the genesis block.
+    pub fn new(chain_id: u64, base_fee_per_gas: u128, timestamp: u64) -> Self {
+        let mut chain = Self {
+            chain_id,
+            base_fee_per_gas,
+            blocks: vec![],
+            nonces: BTreeMap::new(),
+            balances: BTreeMap::new(),
+            reorgs: 0,
+        };
+        chain.mine_block(timestamp);
+        chain
+    }
+
+    pub fn head(&self) -> &FakeBlock {
+        self.blocks.last().expect("there is always a genesis block")
+    }
+
+    /// Returns the block of `block_tag`. All tags but `Earliest` and `Number` refer to the
+    /// head, as the fake chain has no notion of finality.
+    pub fn block(&self, block_tag: &BlockTag) -> Option<&FakeBlock> {
+        match block_tag {
+            BlockTag::Earliest => self.blocks.first(),
+            BlockTag::Number(number) => self.blocks.get(nat_to_u64(number)? as usize),
+            BlockTag::Latest | BlockTag::Safe | BlockTag::Finalized | BlockTag::Pending => {
+                Some(self.head())
+            }
+        }
+    }
+
+    /// Appends an empty block to the chain.
+    pub fn mine_block(&mut self, timestamp: u64) -> &mut FakeBlock {
+        let (number, parent_hash) = match self.blocks.last() {
+            Some(parent) => (parent.number + 1, parent.hash),
+            None => (0, H256::zero()),
+        };
+        let mut preimage = parent_hash.as_bytes().to_vec();
+        preimage.extend(number.to_be_bytes());
+        preimage.extend(self.reorgs.to_be_bytes());
+        self.blocks.push(FakeBlock {
+            number,
+            hash: H256(keccak256(preimage)),
+            parent_hash,
+            timestamp,
+            logs: vec![],
+            transactions: vec![],
+        });
+        self.blocks.last_mut().expect("a block was just pushed")
+    }
+
+    /// Mines a block containing a single log, as if it were emitted by a transaction to
+    /// `address`, and returns the log.
+    pub fn inject_log(
+        &mut self,
+        address: Address,
+        topics: Vec<H256>,
+        data: Vec<u8>,
+        timestamp: u64,
+    ) -> LogEntry {
+        let block = self.mine_block(timestamp);
+        let mut preimage = block.hash.as_bytes().to_vec();
+        preimage.extend(b"log");
+        let log = LogEntry {
+            transactionHash: Some(hex(H256(keccak256(preimage)).as_bytes())),
+            blockNumber: Some(Nat::from(block.number)),
+            data: hex(&data),
+            blockHash: Some(hex(block.hash.as_bytes())),
+            transactionIndex: Some(Nat::from(0u32)),
+            topics: topics.iter().map(|topic| hex(topic.as_bytes())).collect(),
+            address: hex(address.as_bytes()),
+            logIndex: Some(Nat::from(0u32)),
+            removed: false,
+        };
+        block.logs.push(log.clone());
+        log
+    }
+
+    /// Mines a signed raw transaction into a new block.
+    ///
+    /// Transactions with a nonce other than the next one of their sender are rejected with
+    /// the status the EVM RPC canister reports for them.
+    pub fn send_raw_transaction(
+        &mut self,
+        raw_transaction: &[u8],
+        timestamp: u64,
+    ) -> Result<SendRawTransactionStatus, TransactionError> {
+        let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(raw_transaction))
+            .map_err(|e| TransactionError::Invalid(e.to_string()))?;
+        let chain_id = tx.chain_id().map(|id| id.as_u64());
+        if chain_id != Some(self.chain_id) {
+            return Err(TransactionError::WrongChainId {
+                expected: self.chain_id,
+                actual: chain_id,
+            });
+        }
+        let from = signature
+            .recover(tx.sighash())
+            .map_err(|e| TransactionError::Invalid(e.to_string()))?;
+        let nonce = tx.nonce().map_or(0, |nonce| nonce.as_u64());
+        let expected_nonce = self.transaction_count(&from);
+        if nonce < expected_nonce {
+            return Ok(SendRawTransactionStatus::NonceTooLow);
+        }
+        if nonce > expected_nonce {
+            return Ok(SendRawTransactionStatus::NonceTooHigh);
+        }
+
+        let hash = H256(keccak256(raw_transaction));
+        let (tx_type, effective_gas_price) = match &tx {
+            TypedTransaction::Legacy(tx) => ("0x0", tx.gas_price.unwrap_or_default()),
+            TypedTransaction::Eip2930(tx) => ("0x1", tx.tx.gas_price.unwrap_or_default()),
+            TypedTransaction::Eip1559(tx) => (
+                "0x2",
+                tx.max_fee_per_gas.unwrap_or_default().min(
+                    U256::from(self.base_fee_per_gas)
+                        + tx.max_priority_fee_per_gas.unwrap_or_default(),
+                ),
+            ),
+        };
+        let to = tx.to_addr().copied();
+        let gas_used = tx.gas().copied().unwrap_or_default();
+
+        let block = self.mine_block(timestamp);
+        let receipt = TransactionReceipt {
+            to: to.map(|to| hex(to.as_bytes())).unwrap_or_default(),
+            status: Nat::from(1u32),
+            transactionHash: hex(hash.as_bytes()),
+            blockNumber: Nat::from(block.number),
+            from: hex(from.as_bytes()),
+            logs: vec![],
+            blockHash: hex(block.hash.as_bytes()),
+            r#type: tx_type.to_string(),
+            transactionIndex: Nat::from(0u32),
+            effectiveGasPrice: u256_to_nat(effective_gas_price),
+            logsBloom: hex(&[0; 256]),
+            contractAddress: None,
+            gasUsed: u256_to_nat(gas_used),
+        };
+        block.transactions.push(FakeTransaction {
+            hash,
+            from,
+            nonce,
+            receipt,
+            raw: raw_transaction.to_vec(),
+        });
+        self.nonces.insert(from, nonce + 1);
+        Ok(SendRawTransactionStatus::Ok(Some(hex(hash.as_bytes()))))
+    }
+
+    /// Replaces the last `depth` blocks with as many empty blocks, dropping their logs and
+    /// transactions. The genesis block is never replaced.
+    pub fn reorg(&mut self, depth: u64, timestamp: u64) {
+        let depth = (depth as usize).min(self.blocks.len() - 1);
+        let dropped = self.blocks.split_off(self.blocks.len() - depth);
+        for tx in dropped.iter().flat_map(|block| &block.transactions) {
+            let nonce = self.nonces.entry(tx.from).or_default();
+            *nonce = (*nonce).min(tx.nonce);
+        }
+        self.reorgs += 1;
+        for _ in 0..depth {
+            self.mine_block(timestamp);
+        }
+    }
+
+    /// Returns the logs matching `args`, like `eth_getLogs`.
+    pub fn logs(&self, args: &GetLogsArgs) -> Vec<LogEntry> {
+        let number = |block_tag: &Option<BlockTag>| match block_tag {
+            Some(BlockTag::Number(number)) => nat_to_u64(number).unwrap_or(u64::MAX),
+            Some(BlockTag::Earliest) => 0,
+            _ => self.head().number,
+        };
+        let (from, to) = (number(&args.fromBlock), number(&args.toBlock));
+        let topics = args.topics.clone().unwrap_or_default();
+        self.blocks
+            .iter()
+            .filter(|block| from <= block.number && block.number <= to)
+            .flat_map(|block| &block.logs)
+            .filter(|log| {
+                args.addresses.is_empty()
+                    || args
+                        .addresses
+                        .iter()
+                        .any(|address| address.eq_ignore_ascii_case(&log.address))
+            })
+            .filter(|log| {
+                // every position matches any of its topics, an empty position matches all
+                topics.iter().enumerate().all(|(i, candidates)| {
+                    candidates.is_empty()
+                        || log.topics.get(i).is_some_and(|topic| {
+                            candidates
+                                .iter()
+                                .any(|candidate| candidate.eq_ignore_ascii_case(topic))
+                        })
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the nonce of the next transaction of `address`.
+    pub fn transaction_count(&self, address: &Address) -> u64 {
+        self.nonces.get(address).copied().unwrap_or_default()
+    }
+
+    pub fn balance(&self, address: &Address) -> U256 {
+        self.balances.get(address).copied().unwrap_or_default()
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        self.balances.insert(address, balance);
+    }
+
+    /// Returns the transactions of the canonical chain in the order they were mined.
+    pub fn transactions(&self) -> impl Iterator<Item = &FakeTransaction> {
+        self.blocks.iter().flat_map(|block| &block.transactions)
+    }
+
+    pub fn receipt(&self, transaction_hash: &H256) -> Option<TransactionReceipt> {
+        self.transactions()
+            .find(|tx| tx.hash == *transaction_hash)
+            .map(|tx| tx.receipt.clone())
+    }
+
+    /// Returns the fee history of the `block_count` blocks up to `newest_block`. The base fee
+    /// is constant and every transaction pays `PRIORITY_FEE_PER_GAS`.
+    pub fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: &BlockTag,
+        reward_percentiles: usize,
+    ) -> Option<FeeHistory> {
+        let newest = self.block(newest_block)?.number;
+        let block_count = block_count.clamp(1, newest + 1);
+        Some(FeeHistory {
+            reward: (0..block_count)
+                .map(|_| vec![Nat::from(PRIORITY_FEE_PER_GAS); reward_percentiles])
+                .collect(),
+            gasUsedRatio: vec![0.5; block_count as usize],
+            oldestBlock: Nat::from(newest + 1 - block_count),
+            // the base fee of the next block is included as well
+            baseFeePerGas: vec![Nat::from(self.base_fee_per_gas); block_count as usize + 1],
+        })
+    }
+}
+
+impl FakeBlock {
+    /// Returns the block as reported by `eth_getBlockByNumber`.
+    pub fn to_candid(&self, base_fee_per_gas: u128) -> Block {
+        let gas_used: u64 = self
+            .transactions
+            .iter()
+            .map(|tx| nat_to_u64(&tx.receipt.gasUsed).unwrap_or_default())
+            .sum();
+        Block {
+            miner: hex(Address::zero().as_bytes()),
+            totalDifficulty: Nat::from(0u32),
+            receiptsRoot: hex(H256::zero().as_bytes()),
+            stateRoot: hex(H256::zero().as_bytes()),
+            hash: hex(self.hash.as_bytes()),
+            difficulty: Nat::from(0u32),
+            size: Nat::from(0u32),
+            uncles: vec![],
+            baseFeePerGas: Nat::from(base_fee_per_gas),
+            extraData: "0x".to_string(),
+            transactionsRoot: None,
+            sha3Uncles: hex(H256::zero().as_bytes()),
+            nonce: Nat::from(0u32),
+            number: Nat::from(self.number),
+            timestamp: Nat::from(self.timestamp),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|tx| hex(tx.hash.as_bytes()))
+                .collect(),
+            gasLimit: Nat::from(BLOCK_GAS_LIMIT),
+            logsBloom: hex(&[0; 256]),
+            parentHash: hex(self.parent_hash.as_bytes()),
+            gasUsed: Nat::from(gas_used),
+            mixHash: hex(H256::zero().as_bytes()),
+        }
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", ethers_core::utils::hex::encode(bytes))
+}
+
+fn nat_to_u64(n: &Nat) -> Option<u64> {
+    u64::try_from(n.0.clone()).ok()
+}
+
+fn u256_to_nat(n: U256) -> Nat {
+    n.to_string()
+        .parse()
+        .expect("a U256 should always be a valid Nat")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::k256::ecdsa::SigningKey;
+    use ethers_core::types::{Eip1559TransactionRequest, Signature, U64};
+    use ethers_core::utils::secret_key_to_address;
+
+    const CHAIN_ID: u64 = 31_337;
+
+    fn contract() -> Address {
+        "0x5fbdb2315678afecb367f032d93f642f64180aa3"
+            .parse()
+            .unwrap()
+    }
+
+    fn signed_transaction(key: &SigningKey, nonce: u64, chain_id: u64) -> Vec<u8> {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(contract())
+            .nonce(nonce)
+            .gas(100_000)
+            .max_fee_per_gas(2_000_000_000u64)
+            .max_priority_fee_per_gas(PRIORITY_FEE_PER_GAS)
+            .chain_id(U64::from(chain_id))
+            .into();
+        let (signature, recovery_id) = key
+            .sign_prehash_recoverable(tx.sighash().as_bytes())
+            .unwrap();
+        let signature = Signature {
+            r: U256::from_big_endian(&signature.r().to_bytes()),
+            s: U256::from_big_endian(&signature.s().to_bytes()),
+            v: recovery_id.to_byte() as u64,
+        };
+        tx.rlp_signed(&signature).to_vec()
+    }
+
+    #[test]
+    fn should_mine_transactions_with_consecutive_nonces() {
+        let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let sender = secret_key_to_address(&key);
+        let mut chain = Chain::new(CHAIN_ID, 1_000_000_000, 0);
+
+        let status = chain.send_raw_transaction(&signed_transaction(&key, 0, CHAIN_ID), 1);
+
+        let Ok(SendRawTransactionStatus::Ok(Some(hash))) = status else {
+            panic!("unexpected status {status:?}");
+        };
+        assert_eq!(chain.transaction_count(&sender), 1);
+        assert_eq!(chain.head().number, 1);
+        let receipt = chain.receipt(&hash.parse().unwrap()).unwrap();
+        assert_eq!(receipt.from, hex(sender.as_bytes()));
+        assert_eq!(receipt.blockNumber, Nat::from(1u32));
+        assert_eq!(
+            chain
+                .transactions()
+                .map(|tx| tx.raw.clone())
+                .collect::<Vec<_>>(),
+            vec![signed_transaction(&key, 0, CHAIN_ID)]
+        );
+        assert!(matches!(
+            chain.send_raw_transaction(&signed_transaction(&key, 0, CHAIN_ID), 2),
+            Ok(SendRawTransactionStatus::NonceTooLow)
+        ));
+        assert!(matches!(
+            chain.send_raw_transaction(&signed_transaction(&key, 2, CHAIN_ID), 2),
+            Ok(SendRawTransactionStatus::NonceTooHigh)
+        ));
+        assert!(matches!(
+            chain.send_raw_transaction(&signed_transaction(&key, 1, 1), 2),
+            Err(TransactionError::WrongChainId {
+                expected: CHAIN_ID,
+                actual: Some(1)
+            })
+        ));
+    }
+
+    #[test]
+    fn should_filter_logs_by_range_address_and_topics() {
+        let mut chain = Chain::new(CHAIN_ID, 1_000_000_000, 0);
+        let new_job = H256::repeat_byte(1);
+        let other = H256::repeat_byte(2);
+        chain.inject_log(
+            contract(),
+            vec![new_job, H256::from_low_u64_be(1)],
+            vec![],
+            1,
+        );
+        chain.inject_log(contract(), vec![other], vec![], 2);
+        chain.inject_log(Address::zero(), vec![new_job], vec![], 3);
+        chain.inject_log(
+            contract(),
+            vec![new_job, H256::from_low_u64_be(2)],
+            vec![],
+            4,
+        );
+
+        let logs = chain.logs(&GetLogsArgs {
+            fromBlock: Some(BlockTag::Number(Nat::from(1u32))),
+            toBlock: Some(BlockTag::Number(Nat::from(3u32))),
+            addresses: vec![hex(contract().as_bytes())],
+            topics: Some(vec![vec![hex(new_job.as_bytes())]]),
+        });
+
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].blockNumber, Some(Nat::from(1u32)));
+        assert_eq!(logs[0].topics[1], hex(H256::from_low_u64_be(1).as_bytes()));
+    }
+
+    #[test]
+    fn should_replace_blocks_on_reorg() {
+        let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let sender = secret_key_to_address(&key);
+        let mut chain = Chain::new(CHAIN_ID, 1_000_000_000, 0);
+        chain.inject_log(contract(), vec![H256::repeat_byte(1)], vec![], 1);
+        chain
+            .send_raw_transaction(&signed_transaction(&key, 0, CHAIN_ID), 2)
+            .unwrap();
+        let replaced_hash = chain.head().hash;
+
+        chain.reorg(2, 3);
+
+        assert_eq!(chain.head().number, 2);
+        assert_ne!(chain.head().hash, replaced_hash);
+        assert_eq!(
+            chain.head().parent_hash,
+            chain
+                .block(&BlockTag::Number(Nat::from(1u32)))
+                .unwrap()
+                .hash
+        );
+        assert_eq!(chain.transaction_count(&sender), 0);
+        assert!(chain
+            .logs(&GetLogsArgs {
+                fromBlock: Some(BlockTag::Earliest),
+                toBlock: Some(BlockTag::Latest),
+                addresses: vec![],
+                topics: None,
+            })
+            .is_empty());
+    }
+}
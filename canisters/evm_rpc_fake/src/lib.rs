@@ -0,0 +1,325 @@
+//! A fake of the EVM RPC canister for local development.
+//!
+//! It implements the part of the EVM RPC canister's Candid interface used by `chain_fusion`
+//! against an in-memory chain, see `chain.rs`, instead of calling RPC providers. Controllers
+//! drive the chain with `inject_log`, `mine_blocks` and `reorg`, so that jobs can be
+//! triggered without Anvil and the results are deterministic. The RPC services passed to
+//! the methods are ignored and every result is `Consistent`. The chain lives on the heap
+//! and starts over when the canister is upgraded.
+//!
+//! The PocketIC integration tests of `chain_fusion` run against the fake as well, and check
+//! the transactions `chain_fusion` sent with `sent_transactions`.
+mod chain;
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Nat};
+use chain::{Chain, TransactionError};
+use ethers_core::types::{Address, H256, U256};
+use evm_rpc_canister_types::{
+    BlockTag, FeeHistoryArgs, FeeHistoryResult, GetBlockByNumberResult, GetLogsArgs, GetLogsResult,
+    GetTransactionCountArgs, GetTransactionCountResult, GetTransactionReceiptResult, JsonRpcError,
+    LogEntry, MultiFeeHistoryResult, MultiGetBlockByNumberResult, MultiGetLogsResult,
+    MultiGetTransactionCountResult, MultiGetTransactionReceiptResult,
+    MultiSendRawTransactionResult, RequestCostResult, RequestResult, RpcConfig, RpcError,
+    RpcService, RpcServices, SendRawTransactionResult, ValidationError,
+};
+use serde_json::{json, Value};
+
+/// The gas reported by `eth_estimateGas` and `eth_createAccessList`, as the fake chain
+/// does not execute transactions.
+const ESTIMATED_GAS: u64 = 100_000;
+
+thread_local! {
+    static CHAIN: RefCell<Option<Chain>> = RefCell::default();
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InitArg {
+    pub chain_id: u64,
+    /// The constant base fee of the chain in wei, defaults to 1 gwei.
+    pub base_fee_per_gas: Option<Nat>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InjectLogArg {
+    /// The address of the contract emitting the log.
+    pub address: String,
+    pub topics: Vec<String>,
+    /// The hex encoded data of the log, defaults to none.
+    pub data: Option<String>,
+}
+
+fn read_chain<R>(f: impl FnOnce(&Chain) -> R) -> R {
+    CHAIN.with_borrow(|chain| f(chain.as_ref().expect("BUG: chain is not initialized")))
+}
+
+fn mutate_chain<R>(f: impl FnOnce(&mut Chain) -> R) -> R {
+    CHAIN.with_borrow_mut(|chain| f(chain.as_mut().expect("BUG: chain is not initialized")))
+}
+
+/// The current time in seconds, used as the timestamp of new blocks.
+fn now() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+fn ensure_controller() {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("only controllers can modify the fake chain");
+    }
+}
+
+#[ic_cdk::init]
+fn init(arg: InitArg) {
+    let base_fee_per_gas = arg
+        .base_fee_per_gas
+        .map(|fee| u128::try_from(fee.0).expect("the base fee should fit into 128 bits"))
+        .unwrap_or(1_000_000_000);
+    CHAIN.set(Some(Chain::new(arg.chain_id, base_fee_per_gas, now())));
+}
+
+/// Mines `count` empty blocks and returns the number of the new head.
+#[ic_cdk::update]
+fn mine_blocks(count: u64) -> Nat {
+    ensure_controller();
+    mutate_chain(|chain| {
+        for _ in 0..count {
+            chain.mine_block(now());
+        }
+        Nat::from(chain.head().number)
+    })
+}
+
+/// Mines a block containing the given log, e.g. a `NewJob` event, and returns the log.
+#[ic_cdk::update]
+fn inject_log(arg: InjectLogArg) -> LogEntry {
+    ensure_controller();
+    let address: Address = arg
+        .address
+        .parse()
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("invalid address: {e}")));
+    let topics = arg
+        .topics
+        .iter()
+        .map(|topic| topic.parse::<H256>())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("invalid topic: {e}")));
+    let data = arg.data.unwrap_or_default();
+    let data = ethers_core::utils::hex::decode(data.trim_start_matches("0x"))
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("invalid data: {e}")));
+    mutate_chain(|chain| chain.inject_log(address, topics, data, now()))
+}
+
+/// Replaces the last `depth` blocks with empty ones, dropping their logs and transactions,
+/// and returns the number of the head.
+#[ic_cdk::update]
+fn reorg(depth: u64) -> Nat {
+    ensure_controller();
+    mutate_chain(|chain| {
+        chain.reorg(depth, now());
+        Nat::from(chain.head().number)
+    })
+}
+
+/// Sets the balance returned by `eth_getBalance` for `address`.
+#[ic_cdk::update]
+fn set_balance(address: String, balance: Nat) {
+    ensure_controller();
+    let address: Address = address
+        .parse()
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("invalid address: {e}")));
+    let balance = U256::from_dec_str(&balance.0.to_string())
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("invalid balance: {e}")));
+    mutate_chain(|chain| chain.set_balance(address, balance));
+}
+
+/// Returns the hex encoded signed transactions of the canonical chain in the order they
+/// were mined, e.g. to check the transactions sent by `chain_fusion` in tests.
+#[ic_cdk::query]
+fn sent_transactions() -> Vec<String> {
+    read_chain(|chain| chain.transactions().map(|tx| chain::hex(&tx.raw)).collect())
+}
+
+#[ic_cdk::update(name = "eth_getBlockByNumber")]
+fn eth_get_block_by_number(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    block_tag: BlockTag,
+) -> MultiGetBlockByNumberResult {
+    MultiGetBlockByNumberResult::Consistent(read_chain(|chain| match chain.block(&block_tag) {
+        Some(block) => GetBlockByNumberResult::Ok(block.to_candid(chain.base_fee_per_gas)),
+        None => GetBlockByNumberResult::Err(json_rpc_error(-32000, "block not found")),
+    }))
+}
+
+#[ic_cdk::update(name = "eth_getLogs")]
+fn eth_get_logs(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    args: GetLogsArgs,
+) -> MultiGetLogsResult {
+    MultiGetLogsResult::Consistent(GetLogsResult::Ok(read_chain(|chain| chain.logs(&args))))
+}
+
+#[ic_cdk::update(name = "eth_feeHistory")]
+fn eth_fee_history(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    args: FeeHistoryArgs,
+) -> MultiFeeHistoryResult {
+    let block_count = u64::try_from(args.blockCount.0).unwrap_or(u64::MAX);
+    let reward_percentiles = args.rewardPercentiles.map_or(0, |p| p.len());
+    MultiFeeHistoryResult::Consistent(FeeHistoryResult::Ok(read_chain(|chain| {
+        chain.fee_history(block_count, &args.newestBlock, reward_percentiles)
+    })))
+}
+
+#[ic_cdk::update(name = "eth_getTransactionCount")]
+fn eth_get_transaction_count(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    args: GetTransactionCountArgs,
+) -> MultiGetTransactionCountResult {
+    MultiGetTransactionCountResult::Consistent(match args.address.parse::<Address>() {
+        Ok(address) => GetTransactionCountResult::Ok(Nat::from(read_chain(|chain| {
+            chain.transaction_count(&address)
+        }))),
+        Err(e) => GetTransactionCountResult::Err(validation_error(e)),
+    })
+}
+
+#[ic_cdk::update(name = "eth_getTransactionReceipt")]
+fn eth_get_transaction_receipt(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    transaction_hash: String,
+) -> MultiGetTransactionReceiptResult {
+    MultiGetTransactionReceiptResult::Consistent(match transaction_hash.parse::<H256>() {
+        Ok(hash) => GetTransactionReceiptResult::Ok(read_chain(|chain| chain.receipt(&hash))),
+        Err(e) => GetTransactionReceiptResult::Err(validation_error(e)),
+    })
+}
+
+#[ic_cdk::update(name = "eth_sendRawTransaction")]
+fn eth_send_raw_transaction(
+    _rpc_services: RpcServices,
+    _config: Option<RpcConfig>,
+    raw_signed_transaction_hex: String,
+) -> MultiSendRawTransactionResult {
+    let result = match ethers_core::utils::hex::decode(
+        raw_signed_transaction_hex.trim_start_matches("0x"),
+    ) {
+        Ok(raw_transaction) => {
+            match mutate_chain(|chain| chain.send_raw_transaction(&raw_transaction, now())) {
+                Ok(status) => SendRawTransactionResult::Ok(status),
+                Err(TransactionError::Invalid(e)) => {
+                    SendRawTransactionResult::Err(json_rpc_error(-32000, &e))
+                }
+                Err(TransactionError::WrongChainId { expected, actual }) => {
+                    SendRawTransactionResult::Err(json_rpc_error(
+                        -32000,
+                        &format!("invalid chain id {actual:?}, expected {expected}"),
+                    ))
+                }
+            }
+        }
+        Err(e) => SendRawTransactionResult::Err(validation_error(e)),
+    };
+    MultiSendRawTransactionResult::Consistent(result)
+}
+
+/// Answers the raw JSON-RPC requests made by `ic-evm-utils`, e.g. `eth_call` or
+/// `eth_estimateGas`. Calls are not executed and return empty data.
+#[ic_cdk::update]
+fn request(
+    _rpc_service: RpcService,
+    json_rpc_payload: String,
+    _max_response_bytes: u64,
+) -> RequestResult {
+    let payload: Value = match serde_json::from_str(&json_rpc_payload) {
+        Ok(payload) => payload,
+        Err(e) => return RequestResult::Err(validation_error(e)),
+    };
+    let params = &payload["params"];
+    let address = |value: &Value| value.as_str().and_then(|a| a.parse::<Address>().ok());
+    let result = match payload["method"].as_str().unwrap_or_default() {
+        "eth_blockNumber" => Ok(json!(quantity(read_chain(|chain| chain.head().number)))),
+        "eth_chainId" => Ok(json!(quantity(read_chain(|chain| chain.chain_id)))),
+        "eth_gasPrice" => Ok(json!(format!(
+            "{:#x}",
+            read_chain(|chain| chain.base_fee_per_gas)
+        ))),
+        "eth_getBalance" => match address(&params[0]) {
+            Some(address) => Ok(json!(format!(
+                "{:#x}",
+                read_chain(|chain| chain.balance(&address))
+            ))),
+            None => Err(json!({ "code": -32602, "message": "invalid address" })),
+        },
+        "eth_getTransactionCount" => match address(&params[0]) {
+            Some(address) => Ok(json!(quantity(read_chain(|chain| {
+                chain.transaction_count(&address)
+            })))),
+            None => Err(json!({ "code": -32602, "message": "invalid address" })),
+        },
+        "eth_call" => Ok(json!("0x")),
+        "eth_estimateGas" => Ok(json!(quantity(ESTIMATED_GAS))),
+        "eth_createAccessList" => Ok(json!({
+            "accessList": [],
+            "gasUsed": quantity(ESTIMATED_GAS),
+        })),
+        method => Err(json!({
+            "code": -32601,
+            "message": format!("the method {method} is not supported by the fake EVM RPC canister"),
+        })),
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": payload["id"], "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": payload["id"], "error": error }),
+    };
+    RequestResult::Ok(response.to_string())
+}
+
+#[ic_cdk::query(name = "requestCost")]
+fn request_cost(
+    _rpc_service: RpcService,
+    _json_rpc_payload: String,
+    _max_response_bytes: u64,
+) -> RequestCostResult {
+    RequestCostResult::Ok(Nat::from(0u32))
+}
+
+fn quantity(n: u64) -> String {
+    format!("{n:#x}")
+}
+
+fn json_rpc_error(code: i64, message: &str) -> RpcError {
+    RpcError::JsonRpcError(JsonRpcError {
+        code,
+        message: message.to_string(),
+    })
+}
+
+fn validation_error(e: impl std::fmt::Display) -> RpcError {
+    RpcError::ValidationError(ValidationError::Custom(e.to_string()))
+}
+
+ic_cdk::export_candid!();
+
+#[test]
+fn check_candid_interface() {
+    use candid_parser::utils::{service_equal, CandidSource};
+
+    // `__export_service` is generated by `export_candid!` and describes the actual interface
+    let new_interface = __export_service();
+
+    // check the actual interface against the declared one
+    let old_interface = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("evm_rpc_fake.did");
+
+    service_equal(
+        CandidSource::Text(&new_interface),
+        CandidSource::File(old_interface.as_path()),
+    )
+    .expect("the declared candid interface in evm_rpc_fake.did is out of date");
+}
@@ -1,17 +1,25 @@
 // SPDX-License-Identifier: MIT
 pragma solidity 0.8.20;
 
-contract Coprocessor {
+import "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+import "@openzeppelin/contracts/utils/cryptography/EIP712.sol";
+
+contract Coprocessor is EIP712 {
     uint job_id = 0;
     address payable private immutable coprocessor;
 
-    constructor(address _coprocessor) {
+    // the EIP-712 type of the results signed by the coprocessor in attestation mode
+    bytes32 private constant JOB_RESULT_TYPEHASH =
+        keccak256("JobResult(uint256 jobId,string result)");
+
+    constructor(address _coprocessor) EIP712("Coprocessor", "1") {
         coprocessor = payable(_coprocessor);
     }
 
     mapping(uint => string) public jobs;
 
-    event NewJob(uint indexed job_id);
+    // the payment lets the coprocessor account for the job without reading the transaction
+    event NewJob(uint indexed job_id, uint payment);
 
     // Function to create a new job
     function newJob() public payable {
@@ -24,7 +32,7 @@ contract Coprocessor {
         coprocessor.transfer(msg.value);
 
         // Emit the new job event
-        emit NewJob(job_id);
+        emit NewJob(job_id, msg.value);
 
         // Increment job counter
         job_id++;
@@ -41,4 +49,42 @@ contract Coprocessor {
         );
         jobs[_job_id] = _result;
     }
+
+    // Function to submit the results of several jobs in a single transaction, used by
+    // the coprocessor in batch mode. Every result is the ABI-encoded string of its job.
+    function batchCallback(
+        uint256[] calldata _job_ids,
+        bytes[] calldata _results
+    ) public {
+        require(
+            msg.sender == coprocessor,
+            "Only the coprocessor can call this function"
+        );
+        require(
+            _job_ids.length == _results.length,
+            "Job IDs and results must have the same length"
+        );
+        for (uint i = 0; i < _job_ids.length; i++) {
+            jobs[_job_ids[i]] = abi.decode(_results[i], (string));
+        }
+    }
+
+    // Function to submit a result signed by the coprocessor, which anyone can call
+    // with an attestation served by the canister's `get_attestation` query
+    function submitAttestation(
+        string calldata _result,
+        uint256 _job_id,
+        bytes calldata _signature
+    ) public {
+        bytes32 digest = _hashTypedDataV4(
+            keccak256(
+                abi.encode(JOB_RESULT_TYPEHASH, _job_id, keccak256(bytes(_result)))
+            )
+        );
+        require(
+            ECDSA.recover(digest, _signature) == coprocessor,
+            "Attestation not signed by the coprocessor"
+        );
+        jobs[_job_id] = _result;
+    }
 }
@@ -41,7 +41,7 @@ dfx canister install --wasm target/wasm32-unknown-unknown/release/chain_fusion.w
     };
     get_logs_topics = opt vec {
       vec {
-        "0x031ada964b8e520743eb9508d0ace62654b126430b7e5a92b42e78eebb61602e";
+        "0x99dc261edd638a734d0a114ada8bbce801eb101dfaa456f4bdcb1e9cfaa058ab";
       };
     };
     last_scraped_block_number = 0: nat;
@@ -59,11 +59,14 @@ dfx canister install --wasm target/wasm32-unknown-unknown/release/chain_fusion.w
     };
     get_logs_addresses = vec { "0x5FbDB2315678afecb367f032d93F642f64180aa3" };
     block_tag = variant { Latest = null };
+    fee_strategy = opt variant { Fast };
+    transaction_type = opt variant { Eip1559 };
+    submission_mode = opt variant { Callback };
   },
 )'
 # sleep for 3 seconds to allow the evm address to be generated
 sleep 3
 # save the chain_fusion canisters evm address
-export EVM_ADDRESS=$(dfx canister call chain_fusion get_evm_address | awk -F'"' '{print $2}')
+export EVM_ADDRESS=$(dfx canister call chain_fusion get_evm_address '(null)' | awk -F'"' '{print $2}')
 # deploy the contract passing the chain_fusion canisters evm address to receive the fees and create a couple of new jobs
 forge script script/Coprocessor.s.sol:MyScript --fork-url http://localhost:8545 --broadcast --sig "run(address)" $EVM_ADDRESS
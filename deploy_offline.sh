@@ -0,0 +1,65 @@
+#!/bin/bash
+
+# deploys the chain_fusion canister against the fake EVM RPC canister, which simulates the
+# chain in memory, so neither Anvil nor any RPC provider is needed
+dfx stop
+# Find process IDs listening on port 4943 (dfx)
+dfx=$(lsof -t -i:4943)
+# Check if any PIDs were found
+if [ -z "$dfx" ]; then
+    echo "dfx not running."
+else
+    # Kill the processes
+    kill $dfx && echo "Terminating running dfx instance."
+    sleep 3
+fi
+dfx start --clean --background
+dfx ledger fabricate-cycles --icp 10000 --canister $(dfx identity get-wallet)
+# the fake is installed with the ID of the EVM RPC canister, see `dfx.json`
+dfx deploy evm_rpc_fake
+cargo build --release --target wasm32-unknown-unknown --package chain_fusion
+dfx canister create --with-cycles 10_000_000_000_000 chain_fusion
+dfx canister install --wasm target/wasm32-unknown-unknown/release/chain_fusion.wasm chain_fusion --argument '(
+  record {
+    ecdsa_key_id = record {
+      name = "dfx_test_key";
+      curve = variant { secp256k1 };
+    };
+    get_logs_topics = opt vec {
+      vec {
+        "0x99dc261edd638a734d0a114ada8bbce801eb101dfaa456f4bdcb1e9cfaa058ab";
+      };
+    };
+    last_scraped_block_number = 0: nat;
+    rpc_services = variant {
+      Custom = record {
+        chainId = 31_337 : nat64;
+        services = vec { record { url = "http://localhost:8545"; headers = null } };
+      }
+    };
+    rpc_service = variant {
+      Custom = record {
+        url = "http://localhost:8545";
+        headers = null;
+      }
+    };
+    get_logs_addresses = vec { "0x5FbDB2315678afecb367f032d93F642f64180aa3" };
+    block_tag = variant { Latest = null };
+    fee_strategy = opt variant { Fast };
+    transaction_type = opt variant { Eip1559 };
+    submission_mode = opt variant { Callback };
+  },
+)'
+# emit a couple of NewJob events paying 0.01 ETH on the fake chain, as the Coprocessor contract would
+for job_id in 0 1 2; do
+  dfx canister call evm_rpc_fake inject_log "(
+    record {
+      address = \"0x5FbDB2315678afecb367f032d93F642f64180aa3\";
+      topics = vec {
+        \"0x99dc261edd638a734d0a114ada8bbce801eb101dfaa456f4bdcb1e9cfaa058ab\";
+        \"0x$(printf '%064x' $job_id)\";
+      };
+      data = opt \"0x$(printf '%064x' 10000000000000000)\";
+    },
+  )"
+done
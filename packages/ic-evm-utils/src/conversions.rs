@@ -2,6 +2,7 @@
 use candid::Nat;
 use ethers_core::types::U256;
 use num_traits::ToPrimitive;
+use std::str::FromStr;
 
 /// Converts a `Nat` to a `U256`.
 ///
@@ -37,3 +38,16 @@ pub fn nat_to_u256(n: &Nat) -> U256 {
 pub fn nat_to_u128(n: &Nat) -> u128 {
     n.0.to_u128().unwrap()
 }
+
+/// Converts a `U256` to a `Nat`.
+///
+/// # Arguments
+///
+/// * `n` - The `U256` to convert.
+///
+/// # Returns
+///
+/// The `Nat` representation of the `U256`.
+pub fn u256_to_nat(n: U256) -> Nat {
+    Nat::from_str(&n.to_string()).expect("a U256 should always be a valid Nat")
+}
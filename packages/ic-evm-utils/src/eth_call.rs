@@ -1,20 +1,28 @@
 //! This module contains functions for interacting with Ethereum contracts using JSON-RPC requests.
-use ethers_core::abi::Token;
+use std::fmt;
+
+use ethers_core::abi::{Contract, Token};
 use ethers_core::types::U256;
 use ethers_core::utils::hex;
 use hex::FromHexError;
 use serde::{Deserialize, Serialize};
 
-use evm_rpc_canister_types::{EvmRpcCanister, RequestResult, RpcService};
+use evm_rpc_canister_types::{RequestResult, RpcError, RpcService};
 
 use crate::eth_send_raw_transaction::{get_data, get_function, ContractDetails};
-use crate::request::{request, JsonRpcResult};
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::request::{request, JsonRpcError, JsonRpcResult};
+use crate::revert::{decode_revert_reason, RevertReason};
 
 /// Represents the parameters for an Ethereum call.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct EthCallParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
     pub to: String,
     pub data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
 }
 
 /// Represents a JSON-RPC request for an Ethereum call.
@@ -26,6 +34,40 @@ pub struct EthCallJsonRpcRequest {
     pub params: (EthCallParams, String),
 }
 
+/// Represents the errors that can occur when executing an Ethereum call.
+#[derive(Clone, Debug)]
+pub enum EthCallError {
+    /// The request to the RPC provider failed.
+    Transport(RpcError),
+    /// The RPC provider returned a response that is not a valid JSON-RPC response.
+    InvalidResponse(String),
+    /// The RPC provider returned a JSON-RPC error unrelated to the execution of the call.
+    JsonRpc { code: isize, message: String },
+    /// The execution of the call reverted.
+    Reverted {
+        reason: RevertReason,
+        message: String,
+    },
+    /// The output of the call does not match the function's ABI.
+    AbiMismatch(String),
+}
+
+impl fmt::Display for EthCallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EthCallError::Transport(e) => write!(f, "transport error: {e:?}"),
+            EthCallError::InvalidResponse(e) => write!(f, "invalid JSON-RPC response: {e}"),
+            EthCallError::JsonRpc { code, message } => {
+                write!(f, "JSON-RPC error {code}: {message}")
+            }
+            EthCallError::Reverted { reason, message } => {
+                write!(f, "execution reverted: {reason} ({message})")
+            }
+            EthCallError::AbiMismatch(e) => write!(f, "ABI mismatch: {e}"),
+        }
+    }
+}
+
 /// Executes an Ethereum call.
 ///
 /// # Arguments
@@ -34,46 +76,137 @@ pub struct EthCallJsonRpcRequest {
 /// * `block_number` - The block number to execute the call on.
 /// * `rpc_service` - The RPC service to use for the call.
 /// * `max_response_bytes` - The maximum number of response bytes to accept.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
 ///
 /// # Returns
 ///
-/// The decoded output of the call as a vector of tokens.
+/// The decoded output of the call as a vector of tokens, or an `EthCallError` if the request
+/// failed, the call reverted or the output could not be decoded.
 pub async fn eth_call(
     contract_details: ContractDetails<'_>,
     block_number: &str,
     rpc_service: RpcService,
     max_response_bytes: u64,
-    evm_rpc: EvmRpcCanister,
-) -> Vec<Token> {
+    evm_rpc: impl EvmRpcClient,
+) -> Result<Vec<Token>, EthCallError> {
     let function = get_function(&contract_details);
     let data = get_data(function, &contract_details);
+    let params = EthCallParams {
+        to: contract_details.contract_address.clone(),
+        data: to_hex(&data),
+        ..Default::default()
+    };
+
+    let result = eth_call_raw(
+        params,
+        block_number,
+        Some(contract_details.abi),
+        rpc_service,
+        max_response_bytes,
+        evm_rpc,
+    )
+    .await?;
+    function
+        .decode_output(&result)
+        .map_err(|e| EthCallError::AbiMismatch(e.to_string()))
+}
+
+/// Executes an Ethereum call with arbitrary parameters and returns the undecoded output.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the call.
+/// * `block_number` - The block number to execute the call on.
+/// * `abi` - The ABI of the called contract, used to decode custom errors if the call reverts.
+/// * `rpc_service` - The RPC service to use for the call.
+/// * `max_response_bytes` - The maximum number of response bytes to accept.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The raw output of the call, or an `EthCallError` if the request failed or the call reverted.
+pub async fn eth_call_raw(
+    params: EthCallParams,
+    block_number: &str,
+    abi: Option<&Contract>,
+    rpc_service: RpcService,
+    max_response_bytes: u64,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<Vec<u8>, EthCallError> {
     let json_rpc_payload = serde_json::to_string(&EthCallJsonRpcRequest {
         id: 1,
         jsonrpc: "2.0".to_string(),
         method: "eth_call".to_string(),
-        params: (
-            EthCallParams {
-                to: contract_details.contract_address.clone(),
-                data: to_hex(&data),
-            },
-            block_number.to_string(),
-        ),
+        params: (params, block_number.to_string()),
     })
     .expect("Error while encoding JSON-RPC request");
 
     let res = request(rpc_service, json_rpc_payload, max_response_bytes, evm_rpc).await;
 
-    match res {
-        RequestResult::Ok(ok) => {
-            let json: JsonRpcResult =
-                serde_json::from_str(&ok).expect("JSON was not well-formatted");
-            let result = from_hex(&json.result.expect("Unexpected JSON response")).unwrap();
-            function
-                .decode_output(&result)
-                .expect("Error decoding output")
-        }
-        RequestResult::Err(err) => panic!("Response error: {err:?}"),
+    let json: JsonRpcResult = match res {
+        RequestResult::Ok(ok) => serde_json::from_str(&ok)
+            .map_err(|e| EthCallError::InvalidResponse(format!("{e}: {ok}")))?,
+        RequestResult::Err(err) => return Err(EthCallError::Transport(err)),
+    };
+
+    if let Some(error) = json.error {
+        return Err(into_eth_call_error(error, abi));
+    }
+
+    let result = json
+        .result
+        .ok_or_else(|| EthCallError::InvalidResponse("missing `result`".to_string()))?;
+    from_hex(&result)
+        .map_err(|e| EthCallError::InvalidResponse(format!("invalid hex in `result`: {e}")))
+}
+
+/// Simulates a transaction by executing it as an Ethereum call against the `pending` block.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the transaction, including its sender and value.
+/// * `abi` - The ABI of the called contract, used to decode custom errors if the call reverts.
+/// * `rpc_service` - The RPC service to use for the call.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// `Ok(())` if the transaction would succeed, otherwise an `EthCallError` which holds the
+/// decoded revert reason if the transaction would revert.
+pub async fn simulate_transaction(
+    params: EthCallParams,
+    abi: Option<&Contract>,
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<(), EthCallError> {
+    let max_response_bytes = 4096;
+    eth_call_raw(
+        params,
+        "pending",
+        abi,
+        rpc_service,
+        max_response_bytes,
+        evm_rpc,
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Converts a JSON-RPC error returned for an `eth_call` into an `EthCallError`, decoding the
+/// revert reason if the call reverted.
+pub(crate) fn into_eth_call_error(error: JsonRpcError, abi: Option<&Contract>) -> EthCallError {
+    let revert_data = error.revert_data();
+    // geth and most providers use code 3 for reverts carrying revert data, others only
+    // signal the revert in the message.
+    if revert_data.is_none() && error.code != 3 && !error.message.contains("revert") {
+        return EthCallError::JsonRpc {
+            code: error.code,
+            message: error.message,
+        };
+    }
+    EthCallError::Reverted {
+        reason: decode_revert_reason(&revert_data.unwrap_or_default(), abi),
+        message: error.message,
     }
 }
 
@@ -84,7 +217,7 @@ pub async fn eth_call(
 /// * `contract_address` - The address of the ERC20 token contract.
 /// * `account` - The account to retrieve the balance for.
 /// * `rpc_service` - The RPC service to use for the call.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
 ///
 /// # Returns
 ///
@@ -93,8 +226,8 @@ pub async fn erc20_balance_of(
     contract_address: String,
     account: String,
     rpc_service: RpcService,
-    evm_rpc: EvmRpcCanister,
-) -> U256 {
+    evm_rpc: impl EvmRpcClient,
+) -> Result<U256, EthCallError> {
     let max_response_bytes = 2048;
     // Define the ABI JSON as a string literal
     let abi_json = r#"
@@ -130,20 +263,20 @@ pub async fn erc20_balance_of(
         )],
     };
 
-    let Token::Uint(balance) = eth_call(
+    let output = eth_call(
         contract_details,
         "latest",
         rpc_service,
         max_response_bytes,
         evm_rpc,
     )
-    .await
-    .first()
-    .unwrap()
-    .clone() else {
-        panic!("oops")
-    };
-    balance
+    .await?;
+    match output.first() {
+        Some(Token::Uint(balance)) => Ok(*balance),
+        _ => Err(EthCallError::AbiMismatch(format!(
+            "expected a uint256 balance, got {output:?}"
+        ))),
+    }
 }
 
 /// Converts a byte slice to a hexadecimal string representation.
@@ -155,7 +288,7 @@ pub async fn erc20_balance_of(
 /// # Returns
 ///
 /// The hexadecimal string representation of the byte slice.
-fn to_hex(data: &[u8]) -> String {
+pub fn to_hex(data: &[u8]) -> String {
     format!("0x{}", hex::encode(data))
 }
 
@@ -169,5 +302,185 @@ fn to_hex(data: &[u8]) -> String {
 ///
 /// The byte slice representation of the hexadecimal string, or an error if the conversion fails.
 fn from_hex(data: &str) -> Result<Vec<u8>, FromHexError> {
-    hex::decode(&data[2..])
+    hex::decode(data.strip_prefix("0x").unwrap_or(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::{encode, parse_abi, Address};
+    use evm_rpc_canister_types::{EthSepoliaService, HttpOutcallError, RejectionCode};
+
+    use crate::evm_rpc_client::MockEvmRpcClient;
+    use crate::revert::ERROR_STRING_SELECTOR;
+    use crate::test_fixtures::block_on;
+
+    fn rpc_service() -> RpcService {
+        RpcService::EthSepolia(EthSepoliaService::Alchemy)
+    }
+
+    fn error_response(error: &str) -> RequestResult {
+        RequestResult::Ok(format!(r#"{{"jsonrpc":"2.0","id":1,"error":{error}}}"#))
+    }
+
+    fn call_raw(response: RequestResult) -> Result<Vec<u8>, EthCallError> {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(response);
+        block_on(eth_call_raw(
+            EthCallParams::default(),
+            "latest",
+            None,
+            rpc_service(),
+            4096,
+            evm_rpc,
+        ))
+    }
+
+    fn revert_data(reason: &str) -> String {
+        let mut data = ERROR_STRING_SELECTOR.to_vec();
+        data.extend(encode(&[Token::String(reason.to_string())]));
+        to_hex(&data)
+    }
+
+    #[test]
+    fn should_return_output_of_call() {
+        let output = call_raw(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x2a"}"#.to_string(),
+        ));
+
+        assert_eq!(output.unwrap(), vec![0x2a]);
+    }
+
+    #[test]
+    fn should_classify_transport_error() {
+        let result = call_raw(RequestResult::Err(RpcError::HttpOutcallError(
+            HttpOutcallError::IcError {
+                code: RejectionCode::SysTransient,
+                message: "timeout".to_string(),
+            },
+        )));
+
+        assert!(
+            matches!(result, Err(EthCallError::Transport(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn should_classify_invalid_response() {
+        let result = call_raw(RequestResult::Ok("not json".to_string()));
+
+        assert!(
+            matches!(result, Err(EthCallError::InvalidResponse(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn should_classify_json_rpc_error_unrelated_to_execution() {
+        let result = call_raw(error_response(
+            r#"{"code":-32000,"message":"header not found"}"#,
+        ));
+
+        assert!(
+            matches!(
+                &result,
+                Err(EthCallError::JsonRpc { code: -32000, message }) if message == "header not found"
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn should_decode_revert_reason_of_revert_data() {
+        // (error, expected revert reason), providers return the revert data as a string
+        // or nested in an object, some only signal the revert in the message
+        let cases = [
+            (
+                format!(
+                    r#"{{"code":3,"message":"execution reverted: not enough","data":"{}"}}"#,
+                    revert_data("not enough")
+                ),
+                RevertReason::Error("not enough".to_string()),
+            ),
+            (
+                format!(
+                    r#"{{"code":-32000,"message":"execution reverted","data":{{"data":"{}"}}}}"#,
+                    revert_data("not enough")
+                ),
+                RevertReason::Error("not enough".to_string()),
+            ),
+            (
+                r#"{"code":-32000,"message":"execution reverted"}"#.to_string(),
+                RevertReason::Unknown(vec![]),
+            ),
+        ];
+        for (error, expected) in cases {
+            let result = call_raw(error_response(&error));
+
+            assert!(
+                matches!(&result, Err(EthCallError::Reverted { reason, .. }) if *reason == expected),
+                "{error}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_classify_output_not_matching_abi() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x"}"#.to_string(),
+        ));
+        let abi = parse_abi(&["function balanceOf(address) view returns (uint256)"]).unwrap();
+
+        let result = block_on(eth_call(
+            ContractDetails {
+                contract_address: "0x5fbdb2315678afecb367f032d93f642f64180aa3".to_string(),
+                abi: &abi,
+                function_name: "balanceOf",
+                args: &[Token::Address(Address::zero())],
+            },
+            "latest",
+            rpc_service(),
+            4096,
+            evm_rpc,
+        ));
+
+        assert!(
+            matches!(result, Err(EthCallError::AbiMismatch(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn should_simulate_transaction_against_pending_block() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x"}"#.to_string(),
+        ));
+        evm_rpc.push_request(error_response(&format!(
+            r#"{{"code":3,"message":"execution reverted","data":"{}"}}"#,
+            revert_data("not enough")
+        )));
+
+        assert!(block_on(simulate_transaction(
+            EthCallParams::default(),
+            None,
+            rpc_service(),
+            evm_rpc.clone()
+        ))
+        .is_ok());
+        let result = block_on(simulate_transaction(
+            EthCallParams::default(),
+            None,
+            rpc_service(),
+            evm_rpc.clone(),
+        ));
+
+        assert!(
+            matches!(result, Err(EthCallError::Reverted { .. })),
+            "{result:?}"
+        );
+        assert!(evm_rpc.requests()[0].contains(r#""pending"]"#));
+    }
 }
@@ -0,0 +1,188 @@
+//! This module provides functions for generating the access list of a transaction by calling `eth_createAccessList`.
+use ethers_core::abi::Contract;
+use ethers_core::types::transaction::eip2930::{AccessList, AccessListWithGasUsed};
+use ethers_core::types::U256;
+use evm_rpc_canister_types::{RequestResult, RpcService};
+use serde::{Deserialize, Serialize};
+
+use crate::eth_call::{into_eth_call_error, EthCallError, EthCallParams};
+use crate::eth_estimate_gas::{apply_safety_margin, GasEstimationConfig};
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::request::{request, JsonRpcError};
+
+/// Represents a JSON-RPC request for creating the access list of a transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateAccessListJsonRpcRequest {
+    pub id: u64,
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: (EthCallParams, String),
+}
+
+/// Represents the JSON-RPC result of `eth_createAccessList`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateAccessListJsonRpcResult {
+    pub result: Option<AccessListWithGasUsed>,
+    pub error: Option<JsonRpcError>,
+}
+
+/// Creates the access list of a transaction against the `pending` block.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the transaction, including its sender and value.
+/// * `abi` - The ABI of the called contract, used to decode custom errors if the transaction would revert.
+/// * `max_response_bytes` - The maximum number of response bytes to accept, which grows with
+///   the number of storage slots the transaction touches.
+/// * `rpc_service` - The RPC service used to interact with the EVM.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The access list together with the gas used by the transaction when the list is attached,
+/// or an `EthCallError` if the access list could not be created.
+pub async fn create_access_list(
+    params: EthCallParams,
+    abi: Option<&Contract>,
+    max_response_bytes: u64,
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<AccessListWithGasUsed, EthCallError> {
+    let json_rpc_payload = serde_json::to_string(&CreateAccessListJsonRpcRequest {
+        id: 1,
+        jsonrpc: "2.0".to_string(),
+        method: "eth_createAccessList".to_string(),
+        params: (params, "pending".to_string()),
+    })
+    .expect("Error while encoding JSON-RPC request");
+
+    let res = request(rpc_service, json_rpc_payload, max_response_bytes, evm_rpc).await;
+
+    let json: CreateAccessListJsonRpcResult = match res {
+        RequestResult::Ok(ok) => serde_json::from_str(&ok)
+            .map_err(|e| EthCallError::InvalidResponse(format!("{e}: {ok}")))?,
+        RequestResult::Err(err) => return Err(EthCallError::Transport(err)),
+    };
+
+    if let Some(error) = json.error {
+        return Err(into_eth_call_error(error, abi));
+    }
+
+    json.result
+        .ok_or_else(|| EthCallError::InvalidResponse("missing `result`".to_string()))
+}
+
+/// Creates the access list of a transaction and returns it only if attaching it makes the
+/// transaction cheaper.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the transaction, including its sender and value.
+/// * `abi` - The ABI of the called contract, used to decode custom errors if the transaction would revert.
+/// * `max_response_bytes` - The maximum number of response bytes to accept.
+/// * `gas` - The gas limit of the transaction without an access list.
+/// * `config` - The safety multiplier and cap applied to the gas used with the access list.
+/// * `rpc_service` - The RPC service used to interact with the EVM.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The access list and the gas limit of the transaction with the list attached if that
+/// limit is below `gas`, `None` otherwise, or an `EthCallError` if the access list could
+/// not be created.
+pub async fn create_access_list_if_cheaper(
+    params: EthCallParams,
+    abi: Option<&Contract>,
+    max_response_bytes: u64,
+    gas: U256,
+    config: &GasEstimationConfig,
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<Option<(AccessList, U256)>, EthCallError> {
+    let AccessListWithGasUsed {
+        access_list,
+        gas_used,
+    } = create_access_list(params, abi, max_response_bytes, rpc_service, evm_rpc).await?;
+    match apply_safety_margin(gas_used, config) {
+        Ok(gas_with_access_list) if gas_with_access_list < gas => {
+            Ok(Some((access_list, gas_with_access_list)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::transaction::eip2930::AccessListItem;
+    use ethers_core::types::{Address, H256};
+    use evm_rpc_canister_types::EthSepoliaService;
+
+    use crate::evm_rpc_client::MockEvmRpcClient;
+    use crate::test_fixtures::block_on;
+
+    fn access_list() -> AccessList {
+        AccessList(vec![AccessListItem {
+            address: Address::from([0x35; 20]),
+            storage_keys: vec![H256::zero()],
+        }])
+    }
+
+    fn create(gas_used: u64, gas: u64) -> Result<Option<(AccessList, U256)>, EthCallError> {
+        let evm_rpc = MockEvmRpcClient::new();
+        let result = AccessListWithGasUsed {
+            access_list: access_list(),
+            gas_used: U256::from(gas_used),
+        };
+        evm_rpc.push_request(RequestResult::Ok(format!(
+            r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#,
+            serde_json::to_string(&result).unwrap()
+        )));
+        block_on(create_access_list_if_cheaper(
+            EthCallParams::default(),
+            None,
+            8192,
+            U256::from(gas),
+            &GasEstimationConfig::default(),
+            RpcService::EthSepolia(EthSepoliaService::Alchemy),
+            evm_rpc,
+        ))
+    }
+
+    #[test]
+    fn should_attach_access_list_only_if_cheaper() {
+        // the gas used with the access list gets a margin of 20%
+        assert_eq!(
+            create(50_000, 100_000).unwrap(),
+            Some((access_list(), U256::from(60_000)))
+        );
+        assert_eq!(create(90_000, 100_000).unwrap(), None);
+        assert_eq!(create(100_000, 120_000).unwrap(), None);
+        // the gas used exceeds the cap of the gas estimation
+        assert_eq!(create(10_000_001, 100_000).unwrap(), None);
+    }
+
+    #[test]
+    fn should_return_error_if_access_list_cannot_be_created() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"the method eth_createAccessList does not exist"}}"#
+                .to_string(),
+        ));
+
+        let result = block_on(create_access_list_if_cheaper(
+            EthCallParams::default(),
+            None,
+            8192,
+            U256::from(100_000),
+            &GasEstimationConfig::default(),
+            RpcService::EthSepolia(EthSepoliaService::Alchemy),
+            evm_rpc,
+        ));
+
+        assert!(
+            matches!(result, Err(EthCallError::JsonRpc { code: -32601, .. })),
+            "{result:?}"
+        );
+    }
+}
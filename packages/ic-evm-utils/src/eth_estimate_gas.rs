@@ -0,0 +1,180 @@
+//! This module provides functions for estimating the gas limit of a transaction by calling `eth_estimateGas`.
+use std::fmt;
+
+use ethers_core::abi::Contract;
+use ethers_core::types::U256;
+use evm_rpc_canister_types::{RequestResult, RpcService};
+use serde::{Deserialize, Serialize};
+
+use crate::eth_call::{into_eth_call_error, EthCallError, EthCallParams};
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::request::{request, JsonRpcResult};
+
+/// Represents a JSON-RPC request for estimating the gas of a transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EstimateGasJsonRpcRequest {
+    pub id: u64,
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: (EthCallParams,),
+}
+
+/// Represents the configuration of the gas estimation.
+#[derive(Clone, Debug)]
+pub struct GasEstimationConfig {
+    /// The percentage the estimate is multiplied with to leave a safety margin, e.g. `120`
+    /// for 20% on top of the estimate.
+    pub multiplier_percent: u64,
+    /// The maximum gas limit that is ever used.
+    pub cap: U256,
+}
+
+impl Default for GasEstimationConfig {
+    fn default() -> Self {
+        Self {
+            multiplier_percent: 120,
+            cap: U256::from(10_000_000),
+        }
+    }
+}
+
+/// Represents the errors that can occur when estimating the gas of a transaction.
+#[derive(Clone, Debug)]
+pub enum EstimateGasError {
+    /// The `eth_estimateGas` call failed, e.g. because the transaction would revert.
+    Call(EthCallError),
+    /// The estimate exceeds the configured cap, so the transaction would run out of gas.
+    ExceedsCap { estimate: U256, cap: U256 },
+}
+
+impl fmt::Display for EstimateGasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EstimateGasError::Call(e) => write!(f, "{e}"),
+            EstimateGasError::ExceedsCap { estimate, cap } => {
+                write!(f, "gas estimate {estimate} exceeds the cap of {cap}")
+            }
+        }
+    }
+}
+
+/// Estimates the gas limit of a transaction.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the transaction, including its sender and value.
+/// * `abi` - The ABI of the called contract, used to decode custom errors if the transaction would revert.
+/// * `config` - The safety multiplier and cap applied to the estimate.
+/// * `rpc_service` - The RPC service used to interact with the EVM.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The estimate multiplied by the safety multiplier and limited to the cap, or an
+/// `EstimateGasError` if the estimation failed or the estimate itself exceeds the cap.
+pub async fn estimate_gas(
+    params: EthCallParams,
+    abi: Option<&Contract>,
+    config: &GasEstimationConfig,
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<U256, EstimateGasError> {
+    let max_response_bytes = 1024;
+    let json_rpc_payload = serde_json::to_string(&EstimateGasJsonRpcRequest {
+        id: 1,
+        jsonrpc: "2.0".to_string(),
+        method: "eth_estimateGas".to_string(),
+        params: (params,),
+    })
+    .expect("Error while encoding JSON-RPC request");
+
+    let res = request(rpc_service, json_rpc_payload, max_response_bytes, evm_rpc).await;
+
+    let json: JsonRpcResult = match res {
+        RequestResult::Ok(ok) => serde_json::from_str(&ok).map_err(|e| {
+            EstimateGasError::Call(EthCallError::InvalidResponse(format!("{e}: {ok}")))
+        })?,
+        RequestResult::Err(err) => {
+            return Err(EstimateGasError::Call(EthCallError::Transport(err)));
+        }
+    };
+
+    if let Some(error) = json.error {
+        return Err(EstimateGasError::Call(into_eth_call_error(error, abi)));
+    }
+
+    let estimate = json
+        .result
+        .and_then(|result| U256::from_str_radix(result.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| {
+            EstimateGasError::Call(EthCallError::InvalidResponse(
+                "missing or invalid `result`".to_string(),
+            ))
+        })?;
+
+    apply_safety_margin(estimate, config)
+}
+
+/// Applies the safety multiplier and cap of `config` to a gas estimate.
+pub(crate) fn apply_safety_margin(
+    estimate: U256,
+    config: &GasEstimationConfig,
+) -> Result<U256, EstimateGasError> {
+    if estimate > config.cap {
+        return Err(EstimateGasError::ExceedsCap {
+            estimate,
+            cap: config.cap,
+        });
+    }
+    let gas = estimate.saturating_mul(U256::from(config.multiplier_percent)) / 100;
+    Ok(gas.min(config.cap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_apply_multiplier_and_cap_to_estimate() {
+        let config = GasEstimationConfig::default();
+        // (estimate, expected gas limit)
+        let cases = [
+            (0, 0),
+            (21_000, 25_200),
+            (100_001, 120_001),
+            // the margin is clamped to the cap
+            (9_000_000, 10_000_000),
+            (10_000_000, 10_000_000),
+        ];
+        for (estimate, expected) in cases {
+            assert_eq!(
+                apply_safety_margin(U256::from(estimate), &config).unwrap(),
+                U256::from(expected),
+                "estimate {estimate}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_use_estimate_as_is_without_margin() {
+        let config = GasEstimationConfig {
+            multiplier_percent: 100,
+            cap: U256::from(50_000),
+        };
+        assert_eq!(
+            apply_safety_margin(U256::from(21_000), &config).unwrap(),
+            U256::from(21_000)
+        );
+    }
+
+    #[test]
+    fn should_reject_estimate_above_cap() {
+        let config = GasEstimationConfig::default();
+        let result = apply_safety_margin(U256::from(10_000_001), &config);
+        assert!(matches!(
+            result,
+            Err(EstimateGasError::ExceedsCap { estimate, cap })
+                if estimate == U256::from(10_000_001) && cap == U256::from(10_000_000)
+        ));
+    }
+}
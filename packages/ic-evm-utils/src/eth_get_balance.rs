@@ -0,0 +1,64 @@
+//! This module provides functions for getting the ETH balance of an account by calling `eth_getBalance`.
+use ethers_core::types::U256;
+use evm_rpc_canister_types::{RequestResult, RpcService};
+use serde::{Deserialize, Serialize};
+
+use crate::eth_call::EthCallError;
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::request::{request, JsonRpcResult};
+
+/// Represents a JSON-RPC request for getting the balance of an account.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBalanceJsonRpcRequest {
+    pub id: u64,
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: (String, String),
+}
+
+/// Gets the ETH balance of an account.
+///
+/// # Arguments
+///
+/// * `address` - The address of the account.
+/// * `block_number` - The block number or tag, e.g. `latest`, at which the balance is read.
+/// * `rpc_service` - The RPC service used to interact with the EVM.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The balance of the account in wei, or an `EthCallError` if it could not be read.
+pub async fn get_balance(
+    address: String,
+    block_number: &str,
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<U256, EthCallError> {
+    let max_response_bytes = 1024;
+    let json_rpc_payload = serde_json::to_string(&GetBalanceJsonRpcRequest {
+        id: 1,
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getBalance".to_string(),
+        params: (address, block_number.to_string()),
+    })
+    .expect("Error while encoding JSON-RPC request");
+
+    let res = request(rpc_service, json_rpc_payload, max_response_bytes, evm_rpc).await;
+
+    let json: JsonRpcResult = match res {
+        RequestResult::Ok(ok) => serde_json::from_str(&ok)
+            .map_err(|e| EthCallError::InvalidResponse(format!("{e}: {ok}")))?,
+        RequestResult::Err(err) => return Err(EthCallError::Transport(err)),
+    };
+
+    if let Some(error) = json.error {
+        return Err(EthCallError::JsonRpc {
+            code: error.code,
+            message: error.message,
+        });
+    }
+
+    json.result
+        .and_then(|result| U256::from_str_radix(result.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| EthCallError::InvalidResponse("missing or invalid `result`".to_string()))
+}
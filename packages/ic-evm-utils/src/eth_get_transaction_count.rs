@@ -1,16 +1,17 @@
 use candid::Nat;
 use evm_rpc_canister_types::{
-    EvmRpcCanister, GetTransactionCountArgs, GetTransactionCountResult,
-    MultiGetTransactionCountResult, RpcServices,
+    GetTransactionCountArgs, GetTransactionCountResult, MultiGetTransactionCountResult, RpcServices,
 };
 
+use crate::evm_rpc_client::EvmRpcClient;
+
 /// Gets the transaction count of an account.
 ///
 /// # Arguments
 ///
 /// * `rpc_services` - The RPC services used to interact with the EVM.
 /// * `get_transaction_count_args` - The arguments for getting the transaction count.
-/// * `evm_rpc` - The EVM RPC canister used to send the transaction.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister, used to send the transaction.
 ///
 /// # Returns
 ///
@@ -22,7 +23,7 @@ use evm_rpc_canister_types::{
 pub async fn get_transaction_count(
     rpc_services: RpcServices,
     get_transaction_count_args: GetTransactionCountArgs,
-    evm_rpc: EvmRpcCanister,
+    evm_rpc: impl EvmRpcClient,
 ) -> Nat {
     let cycles = 10_000_000_000;
     let mut retry_counter = 0;
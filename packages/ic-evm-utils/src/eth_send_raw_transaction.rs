@@ -1,19 +1,29 @@
 //! This module provides functions for sending raw transactions to the Ethereum Virtual Machine (EVM).
 //! It includes functions for transferring ETH from one account to another and interacting with smart contracts.
 //! The transactions are signed using t-ECDSA and sent via the EVM RPC canister.
+use candid::CandidType;
 use ethers_core::abi::{Address, Contract, Function, FunctionExt, Token};
-use ethers_core::types::{Eip1559TransactionRequest, NameOrAddress, U256, U64};
-use evm_rpc_canister_types::{EvmRpcCanister, RpcServices};
-use ic_cdk::api::call::CallResult;
-use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::transaction::eip2930::{AccessList, Eip2930TransactionRequest};
+use ethers_core::types::{
+    Bytes, Eip1559TransactionRequest, NameOrAddress, TransactionRequest, U256, U64,
+};
+use evm_rpc_canister_types::{RpcService, RpcServices};
+use ic_cdk::api::call::{CallResult, RejectionCode};
+#[cfg(target_arch = "wasm32")]
+use ic_cdk::println;
+use serde::Deserialize;
 
+use std::fmt;
 use std::str::FromStr;
 
-use crate::evm_signer::SignedTransaction;
-use crate::{
-    evm_signer::sign_eip1559_transaction,
-    fees::{estimate_transaction_fees, FeeEstimates},
-};
+use crate::eth_call::{simulate_transaction, to_hex, EthCallError, EthCallParams};
+use crate::eth_create_access_list::create_access_list_if_cheaper;
+use crate::eth_estimate_gas::{estimate_gas, EstimateGasError, GasEstimationConfig};
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::evm_signer::{pubkey_bytes_to_address, SignedTransaction, Signer};
+use crate::fees::{estimate_transaction_fees, gas_price, FeeError, FeeEstimates, FeeStrategy};
+use crate::request::first_rpc_service;
 
 /// Represents the arguments for a transfer.
 pub struct TransferArgs {
@@ -24,6 +34,117 @@ pub struct TransferArgs {
 
 pub type TransactionHash = String;
 
+/// The maximum number of response bytes accepted from `eth_createAccessList`.
+const ACCESS_LIST_MAX_RESPONSE_BYTES: u64 = 8192;
+
+/// Represents the type of the transactions sent to a chain.
+///
+/// Chains that do not support EIP-1559 only accept legacy or EIP-2930 transactions.
+/// These are priced with the single gas price returned by `eth_gasPrice`, the fee
+/// strategy only applies to EIP-1559 transactions.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransactionType {
+    /// A legacy transaction, replay protected by its chain ID as specified by EIP-155.
+    Legacy,
+    /// An EIP-2930 transaction with an access list.
+    Eip2930,
+    /// An EIP-1559 transaction with a priority fee and a maximum fee per gas.
+    #[default]
+    Eip1559,
+}
+
+/// Represents the fees of a transaction, see `TransactionType::estimate_fees`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionFees {
+    /// The gas price of a legacy or EIP-2930 transaction.
+    GasPrice(U256),
+    /// The fees of an EIP-1559 transaction.
+    Eip1559(FeeEstimates),
+}
+
+impl TransactionType {
+    /// Estimates the fees of a transaction of this type: the gas price with `eth_gasPrice`
+    /// for legacy and EIP-2930 transactions, the EIP-1559 fees with the fee strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `fee_strategy` - The strategy used to choose the fees of EIP-1559 transactions.
+    /// * `rpc_services` - The RPC services used to interact with the EVM, the gas price is
+    ///   requested from the first one.
+    /// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+    ///
+    /// # Returns
+    ///
+    /// The fees of the transaction, or a `FeeError` if they could not be estimated.
+    pub async fn estimate_fees(
+        self,
+        fee_strategy: &dyn FeeStrategy,
+        rpc_services: RpcServices,
+        evm_rpc: impl EvmRpcClient,
+    ) -> Result<TransactionFees, FeeError> {
+        match self {
+            TransactionType::Legacy | TransactionType::Eip2930 => {
+                gas_price(first_rpc_service(&rpc_services), evm_rpc)
+                    .await
+                    .map(TransactionFees::GasPrice)
+            }
+            TransactionType::Eip1559 => {
+                estimate_transaction_fees(fee_strategy, rpc_services, evm_rpc)
+                    .await
+                    .map(TransactionFees::Eip1559)
+            }
+        }
+    }
+
+    /// Assembles a transaction of this type.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The fields shared by all transaction types. Its gas price is ignored.
+    /// * `fees` - The fees of the transaction as estimated by `estimate_fees`. A gas price
+    ///   pays both fees of an EIP-1559 transaction, the maximum fee of EIP-1559 fees is the
+    ///   gas price of other transactions.
+    /// * `access_list` - The access list of the transaction, dropped for legacy transactions
+    ///   as they cannot carry one.
+    ///
+    /// # Returns
+    ///
+    /// The transaction to be signed.
+    pub fn build(
+        self,
+        tx: TransactionRequest,
+        fees: TransactionFees,
+        access_list: AccessList,
+    ) -> TypedTransaction {
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match fees {
+            TransactionFees::GasPrice(gas_price) => (gas_price, gas_price, gas_price),
+            TransactionFees::Eip1559(FeeEstimates {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            }) => (max_fee_per_gas, max_fee_per_gas, max_priority_fee_per_gas),
+        };
+        match self {
+            TransactionType::Legacy => TypedTransaction::Legacy(tx.gas_price(gas_price)),
+            TransactionType::Eip2930 => TypedTransaction::Eip2930(Eip2930TransactionRequest::new(
+                tx.gas_price(gas_price),
+                access_list,
+            )),
+            TransactionType::Eip1559 => TypedTransaction::Eip1559(Eip1559TransactionRequest {
+                from: tx.from,
+                to: tx.to,
+                gas: tx.gas,
+                value: tx.value,
+                data: tx.data,
+                nonce: tx.nonce,
+                access_list,
+                max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
+                max_fee_per_gas: Some(max_fee_per_gas),
+                chain_id: tx.chain_id,
+            }),
+        }
+    }
+}
+
 /// Transfers ETH from one account to another.
 ///
 /// # Warning
@@ -34,46 +155,51 @@ pub type TransactionHash = String;
 ///
 /// * `transfer_args` - The transfer arguments including the value, recipient, and gas limit.
 /// * `rpc_services` - The RPC services used to estimate transaction fees and get the chain ID.
-/// * `key_id` - The ID of the ECDSA key used for signing the transaction.
+/// * `signer` - The signer of the transaction, e.g. a `ThresholdEcdsaSigner`.
 /// * `derivation_path` - The derivation path of the ECDSA key.
 /// * `nonce` - The nonce of the sender's account.
-/// * `evm_rpc` - The EVM RPC canister used to send the transaction.
+/// * `fee_strategy` - The strategy used to choose the transaction fees.
+/// * `tx_type` - The type of the transaction, depending on what the chain supports.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister, used to send the transaction.
 ///
 /// # Returns
 ///
-/// The status of the raw transaction send operation.
+/// The transaction hash, or a `SendTransactionError` if the fees could not be estimated or
+/// the transaction could not be sent.
+#[allow(clippy::too_many_arguments)]
 pub async fn transfer_eth(
     transfer_args: TransferArgs,
     rpc_services: RpcServices,
-    key_id: EcdsaKeyId,
+    signer: &impl Signer,
     derivation_path: Vec<Vec<u8>>,
     nonce: U256,
-    evm_rpc: EvmRpcCanister,
-) -> CallResult<TransactionHash> {
+    fee_strategy: &dyn FeeStrategy,
+    tx_type: TransactionType,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<TransactionHash, SendTransactionError> {
     // use the user provided gas_limit or fallback to default 210000
     let gas = transfer_args.gas.unwrap_or(U256::from(21000));
-    // estimate the transaction fees by calling eth_feeHistory
-    let FeeEstimates {
-        max_fee_per_gas,
-        max_priority_fee_per_gas,
-    } = estimate_transaction_fees(9, rpc_services.clone(), evm_rpc.clone()).await;
-    // assemble the EIP 1559 transaction to be signed with t-ECDSA
-    let tx = Eip1559TransactionRequest {
+    // estimate the transaction fees by calling eth_feeHistory or eth_gasPrice
+    let fees = tx_type
+        .estimate_fees(fee_strategy, rpc_services.clone(), evm_rpc.clone())
+        .await
+        .map_err(SendTransactionError::Fees)?;
+    // assemble the transaction to be signed with t-ECDSA
+    let tx = TransactionRequest {
         from: None,
         to: transfer_args.to,
         value: Some(transfer_args.value),
-        max_fee_per_gas: Some(max_fee_per_gas),
-        max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
         gas: Some(gas),
+        gas_price: None,
         nonce: Some(nonce),
         chain_id: Some(rpc_services.chain_id()),
         data: Default::default(),
-        access_list: Default::default(),
     };
+    let tx = tx_type.build(tx, fees, AccessList::default());
 
-    let tx = sign_eip1559_transaction(tx, key_id, derivation_path).await;
+    let tx = signer.sign_transaction(tx, derivation_path).await;
 
-    send_raw_transaction(tx.clone(), rpc_services, evm_rpc).await
+    Ok(send_raw_transaction(tx, rpc_services, evm_rpc).await?)
 }
 
 /// Represents the details of a contract including the contract address, ABI, function name, and arguments.
@@ -142,61 +268,252 @@ pub fn get_data<'a>(function: &Function, contract_details: &'a ContractDetails<'
         .expect("Error while encoding input args")
 }
 
+/// Represents the checks made against the EVM before a contract interaction is signed.
+#[derive(Clone, Debug)]
+pub struct PreflightOptions {
+    /// The RPC service used for the JSON-RPC requests made by the checks.
+    pub rpc_service: RpcService,
+    /// The address the transaction is sent from.
+    pub from: String,
+    /// Whether to simulate the transaction with `eth_call` against the `pending` block and
+    /// abort before signing if it would revert.
+    pub simulate: bool,
+    /// The safety multiplier and cap applied when the gas limit is estimated with
+    /// `eth_estimateGas` because no gas limit was given.
+    pub gas_estimation: GasEstimationConfig,
+    /// Whether to create an access list with `eth_createAccessList` and attach it to the
+    /// transaction if it makes the transaction cheaper, lowering an estimated gas limit.
+    /// Ignored for legacy transactions.
+    pub create_access_list: bool,
+}
+
+/// Represents the errors that can occur when interacting with a contract.
+#[derive(Clone, Debug)]
+pub enum SendTransactionError {
+    /// The simulation of the transaction reverted, the transaction was neither signed nor sent.
+    SimulationReverted(EthCallError),
+    /// The gas limit could not be estimated, the transaction was neither signed nor sent.
+    GasEstimation(EstimateGasError),
+    /// The fees could not be estimated, the transaction was neither signed nor sent.
+    Fees(FeeError),
+    /// The call to the EVM RPC canister failed.
+    CallFailed(RejectionCode, String),
+}
+
+impl From<(RejectionCode, String)> for SendTransactionError {
+    fn from((code, message): (RejectionCode, String)) -> Self {
+        SendTransactionError::CallFailed(code, message)
+    }
+}
+
+impl fmt::Display for SendTransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTransactionError::SimulationReverted(e) => write!(f, "simulation failed: {e}"),
+            SendTransactionError::GasEstimation(e) => write!(f, "gas estimation failed: {e}"),
+            SendTransactionError::Fees(e) => write!(f, "fee estimation failed: {e}"),
+            SendTransactionError::CallFailed(code, message) => {
+                write!(f, "call failed with {code:?}: {message}")
+            }
+        }
+    }
+}
+
 /// Interacts with a contract.
 ///
 /// # Arguments
 ///
 /// * `contract_details` - The contract details including the contract address, ABI, function name, and arguments.
-/// * `gas` - The gas limit for the transaction.
+/// * `gas` - The gas limit for the transaction, estimated with `eth_estimateGas` if `None`.
 /// * `rpc_services` - The RPC services used to interact with the EVM.
 /// * `nonce` - The nonce of the sender's account.
-/// * `key_id` - The ID of the ECDSA key used for signing the transaction.
+/// * `signer` - The signer of the transaction, e.g. a `ThresholdEcdsaSigner`.
 /// * `derivation_path` - The derivation path of the ECDSA key.
-/// * `evm_rpc` - The EVM RPC canister used to send the transaction.
+/// * `preflight` - The optional checks made before the transaction is signed.
+/// * `fee_strategy` - The strategy used to choose the transaction fees.
+/// * `tx_type` - The type of the transaction, depending on what the chain supports.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister, used to send the transaction.
 ///
 /// # Returns
 ///
-/// The transaction hash CallResult.
+/// The transaction hash, or a `SendTransactionError` if a pre-flight check failed or the
+/// transaction could not be sent. Without `preflight`, a missing gas limit is estimated with the
+/// default `GasEstimationConfig` against the first of the `rpc_services`.
+#[allow(clippy::too_many_arguments)]
 pub async fn contract_interaction(
     contract_details: ContractDetails<'_>,
     gas: Option<U256>,
     rpc_services: RpcServices,
     nonce: U256,
-    key_id: EcdsaKeyId,
+    signer: &impl Signer,
     derivation_path: Vec<Vec<u8>>,
-    evm_rpc: EvmRpcCanister,
-) -> CallResult<TransactionHash> {
+    preflight: Option<PreflightOptions>,
+    fee_strategy: &dyn FeeStrategy,
+    tx_type: TransactionType,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<TransactionHash, SendTransactionError> {
     let function = get_function(&contract_details);
     let data = get_data(function, &contract_details);
 
-    let FeeEstimates {
-        max_fee_per_gas,
-        max_priority_fee_per_gas,
-    } = estimate_transaction_fees(9, rpc_services.clone(), evm_rpc.clone()).await;
+    // use the user provided gas limit or estimate it, during the pre-flight checks if any
+    let (gas, access_list) = match preflight {
+        Some(preflight) => {
+            run_preflight_checks(
+                &preflight,
+                &contract_details,
+                &data,
+                gas,
+                tx_type,
+                evm_rpc.clone(),
+            )
+            .await?
+        }
+        None => {
+            let gas = match gas {
+                Some(gas) => gas,
+                None => estimate_gas(
+                    EthCallParams {
+                        from: Some(pubkey_bytes_to_address(
+                            &signer.public_key(&derivation_path).await,
+                        )),
+                        to: contract_details.contract_address.clone(),
+                        data: to_hex(&data),
+                        value: None,
+                    },
+                    Some(contract_details.abi),
+                    &GasEstimationConfig::default(),
+                    first_rpc_service(&rpc_services),
+                    evm_rpc.clone(),
+                )
+                .await
+                .map_err(SendTransactionError::GasEstimation)?,
+            };
+            (gas, AccessList::default())
+        }
+    };
+
+    let fees = tx_type
+        .estimate_fees(fee_strategy, rpc_services.clone(), evm_rpc.clone())
+        .await
+        .map_err(SendTransactionError::Fees)?;
 
     // assemble the transaction
-    let tx = Eip1559TransactionRequest {
+    let tx = TransactionRequest {
         to: Some(
             Address::from_str(&contract_details.contract_address)
                 .expect("should be a valid address")
                 .into(),
         ),
-        gas,
-        data: Some(data.into()),
+        gas: Some(gas),
+        data: Some(Bytes::from(data)),
         nonce: Some(nonce),
-        max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-        max_fee_per_gas: Some(max_fee_per_gas),
         chain_id: Some(rpc_services.chain_id()),
         from: Default::default(),
         value: Default::default(),
-        access_list: Default::default(),
+        gas_price: Default::default(),
     };
+    let tx = tx_type.build(tx, fees, access_list);
 
     // sign the transaction using chain key signatures
-    let tx = sign_eip1559_transaction(tx, key_id, derivation_path).await;
+    let tx = signer.sign_transaction(tx, derivation_path).await;
 
     // send the transaction via the EVM RPC canister
-    send_raw_transaction(tx, rpc_services, evm_rpc).await
+    Ok(send_raw_transaction(tx, rpc_services, evm_rpc).await?)
+}
+
+/// Runs the pre-flight checks of a contract interaction.
+///
+/// # Arguments
+///
+/// * `preflight` - The pre-flight checks to run.
+/// * `contract_details` - The contract details including the contract address, ABI, function name, and arguments.
+/// * `data` - The encoded call data of the transaction.
+/// * `gas` - The user provided gas limit, if any, which is kept even if an access list is attached.
+/// * `tx_type` - The type of the transaction, legacy transactions never get an access list.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The gas limit and access list of the transaction, or a `SendTransactionError` if the
+/// transaction would revert or its gas limit could not be estimated.
+async fn run_preflight_checks(
+    preflight: &PreflightOptions,
+    contract_details: &ContractDetails<'_>,
+    data: &[u8],
+    gas: Option<U256>,
+    tx_type: TransactionType,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<(U256, AccessList), SendTransactionError> {
+    let params = EthCallParams {
+        from: Some(preflight.from.clone()),
+        to: contract_details.contract_address.clone(),
+        data: to_hex(data),
+        value: None,
+    };
+
+    if preflight.simulate {
+        match simulate_transaction(
+            params.clone(),
+            Some(contract_details.abi),
+            preflight.rpc_service.clone(),
+            evm_rpc.clone(),
+        )
+        .await
+        {
+            Ok(()) => {}
+            Err(e @ EthCallError::Reverted { .. }) => {
+                return Err(SendTransactionError::SimulationReverted(e))
+            }
+            // only a revert proves that the transaction would fail, so we still send it
+            // if the simulation itself could not be executed
+            Err(e) => println!("Skipping transaction simulation: {e}"),
+        }
+    }
+
+    let (gas, estimated) = match gas {
+        Some(gas) => (gas, false),
+        None => (
+            estimate_gas(
+                params.clone(),
+                Some(contract_details.abi),
+                &preflight.gas_estimation,
+                preflight.rpc_service.clone(),
+                evm_rpc.clone(),
+            )
+            .await
+            .map_err(SendTransactionError::GasEstimation)?,
+            true,
+        ),
+    };
+
+    if !preflight.create_access_list || tx_type == TransactionType::Legacy {
+        return Ok((gas, AccessList::default()));
+    }
+
+    // the access list is optional, so we send the transaction without one if it could not be
+    // created, e.g. because the provider does not support `eth_createAccessList`
+    match create_access_list_if_cheaper(
+        params,
+        Some(contract_details.abi),
+        ACCESS_LIST_MAX_RESPONSE_BYTES,
+        gas,
+        &preflight.gas_estimation,
+        preflight.rpc_service.clone(),
+        evm_rpc,
+    )
+    .await
+    {
+        // a gas limit given by the caller is kept, only an estimated one is lowered
+        Ok(Some((access_list, gas_with_access_list))) if estimated => {
+            Ok((gas_with_access_list, access_list))
+        }
+        Ok(Some((access_list, _))) => Ok((gas, access_list)),
+        Ok(None) => Ok((gas, AccessList::default())),
+        Err(e) => {
+            println!("Skipping access list creation: {e}");
+            Ok((gas, AccessList::default()))
+        }
+    }
 }
 
 /// Sends a raw transaction to the EVM.
@@ -205,7 +522,7 @@ pub async fn contract_interaction(
 ///
 /// * `tx` - The raw transaction to send.
 /// * `rpc_services` - The RPC services used to interact with the EVM.
-/// * `evm_rpc` - The EVM RPC canister used to send the transaction.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister, used to send the transaction.
 ///
 /// # Returns
 ///
@@ -213,7 +530,7 @@ pub async fn contract_interaction(
 pub async fn send_raw_transaction(
     tx: SignedTransaction,
     rpc_services: RpcServices,
-    evm_rpc: EvmRpcCanister,
+    evm_rpc: impl EvmRpcClient,
 ) -> CallResult<TransactionHash> {
     let cycles = 10_000_000_000;
 
@@ -222,7 +539,7 @@ pub async fn send_raw_transaction(
         .await
     {
         Ok((_res,)) => {
-            ic_cdk::println!("Transaction hash: {}", tx.tx_hash);
+            println!("Transaction hash: {}", tx.tx_hash);
             Ok(tx.tx_hash)
         }
         Err(e) => Err(e),
@@ -250,3 +567,428 @@ impl IntoChainId for RpcServices {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::{encode, parse_abi};
+    use ethers_core::types::transaction::eip2930::{AccessListItem, AccessListWithGasUsed};
+    use ethers_core::types::H256;
+    use evm_rpc_canister_types::{
+        EthSepoliaService, FeeHistoryResult, HttpOutcallError, JsonRpcError, MultiFeeHistoryResult,
+        MultiSendRawTransactionResult, RejectionCode, RequestResult, RpcError,
+        SendRawTransactionResult, SendRawTransactionStatus,
+    };
+
+    use crate::evm_rpc_client::MockEvmRpcClient;
+    use crate::evm_signer::LocalSigner;
+    use crate::fees::{FixedFeeStrategy, HistoricalFeeStrategy};
+    use crate::revert::{RevertReason, ERROR_STRING_SELECTOR};
+    use crate::test_fixtures::block_on;
+
+    /// The address of the `Coprocessor` contract deployed by `deploy.sh`.
+    const CONTRACT_ADDRESS: &str = "0x5fbdb2315678afecb367f032d93f642f64180aa3";
+
+    fn transaction() -> TransactionRequest {
+        TransactionRequest {
+            from: None,
+            to: Some(Address::from([0x35; 20]).into()),
+            gas: Some(U256::from(50_000)),
+            gas_price: None,
+            value: None,
+            data: Some(Bytes::from(vec![0xab, 0xcd])),
+            nonce: Some(U256::from(9)),
+            chain_id: Some(U64::from(1)),
+        }
+    }
+
+    fn access_list() -> AccessList {
+        AccessList(vec![AccessListItem {
+            address: Address::from([0x35; 20]),
+            storage_keys: vec![H256::zero()],
+        }])
+    }
+
+    fn eip1559_fees() -> FeeEstimates {
+        FeeEstimates {
+            max_fee_per_gas: U256::from(30),
+            max_priority_fee_per_gas: U256::from(2),
+        }
+    }
+
+    #[test]
+    fn should_build_legacy_transaction_with_gas_price() {
+        let tx = TransactionType::Legacy.build(
+            transaction(),
+            TransactionFees::GasPrice(U256::from(20)),
+            access_list(),
+        );
+
+        assert_eq!(
+            tx,
+            TypedTransaction::Legacy(transaction().gas_price(U256::from(20)))
+        );
+    }
+
+    #[test]
+    fn should_build_eip2930_transaction_with_gas_price_and_access_list() {
+        let tx = TransactionType::Eip2930.build(
+            transaction(),
+            TransactionFees::GasPrice(U256::from(20)),
+            access_list(),
+        );
+
+        assert_eq!(
+            tx,
+            TypedTransaction::Eip2930(Eip2930TransactionRequest::new(
+                transaction().gas_price(U256::from(20)),
+                access_list(),
+            ))
+        );
+    }
+
+    #[test]
+    fn should_build_eip1559_transaction_with_fees_and_access_list() {
+        let tx = TransactionType::Eip1559.build(
+            transaction(),
+            TransactionFees::Eip1559(eip1559_fees()),
+            access_list(),
+        );
+
+        let TypedTransaction::Eip1559(tx) = tx else {
+            panic!("expected an EIP-1559 transaction, got {tx:?}");
+        };
+        assert_eq!(tx.max_fee_per_gas, Some(U256::from(30)));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(U256::from(2)));
+        assert_eq!(tx.access_list, access_list());
+        assert_eq!(tx.to, transaction().to);
+        assert_eq!(tx.gas, transaction().gas);
+        assert_eq!(tx.data, transaction().data);
+        assert_eq!(tx.nonce, transaction().nonce);
+        assert_eq!(tx.chain_id, transaction().chain_id);
+    }
+
+    #[test]
+    fn should_price_legacy_transactions_with_eth_gas_price() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x1dcd65000"}"#.to_string(),
+        ));
+
+        let fees = block_on(TransactionType::Legacy.estimate_fees(
+            &FixedFeeStrategy(eip1559_fees()),
+            RpcServices::EthSepolia(None),
+            evm_rpc.clone(),
+        ));
+
+        assert_eq!(
+            fees.unwrap(),
+            TransactionFees::GasPrice(U256::from(8_000_000_000u64))
+        );
+        assert!(evm_rpc.requests()[0].contains("\"eth_gasPrice\""));
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_return_error_if_chain_does_not_support_fee_history() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_fee_history(MultiFeeHistoryResult::Consistent(FeeHistoryResult::Err(
+            RpcError::JsonRpcError(JsonRpcError {
+                code: -32601,
+                message: "the method eth_feeHistory does not exist".to_string(),
+            }),
+        )));
+
+        let fees = block_on(TransactionType::Eip1559.estimate_fees(
+            &HistoricalFeeStrategy::fast(),
+            RpcServices::EthSepolia(None),
+            evm_rpc,
+        ));
+
+        assert!(matches!(fees, Err(FeeError::FeeHistory(_))), "{fees:?}");
+    }
+
+    fn push_sent(evm_rpc: &MockEvmRpcClient) {
+        evm_rpc.push_send_raw_transaction(MultiSendRawTransactionResult::Consistent(
+            SendRawTransactionResult::Ok(SendRawTransactionStatus::Ok(None)),
+        ));
+    }
+
+    #[test]
+    fn should_sign_and_send_contract_interaction() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let evm_rpc = MockEvmRpcClient::new();
+        push_sent(&evm_rpc);
+        let abi = parse_abi(&["function callback(string,uint256)"]).unwrap();
+        let args = [
+            Token::String("6765".to_string()),
+            Token::Uint(U256::from(1)),
+        ];
+        let fees = FeeEstimates {
+            max_fee_per_gas: U256::from(30_000_000_000u64),
+            max_priority_fee_per_gas: U256::from(1_500_000_000u64),
+        };
+
+        let transaction_hash = block_on(contract_interaction(
+            ContractDetails {
+                contract_address: CONTRACT_ADDRESS.to_string(),
+                abi: &abi,
+                function_name: "callback",
+                args: &args,
+            },
+            Some(U256::from(100_000)),
+            RpcServices::EthSepolia(None),
+            U256::from(7),
+            &signer,
+            vec![],
+            None,
+            &FixedFeeStrategy(fees),
+            TransactionType::Eip1559,
+            evm_rpc.clone(),
+        ));
+
+        assert_eq!(
+            evm_rpc.sent_transactions(),
+            vec!["0x02f8f483aa36a7078459682f008506fc23ac00830186a0945fbdb2315678afecb367f032d93f642f64180aa380b88442d1f6fd0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000043637363500000000000000000000000000000000000000000000000000000000c001a0a2643dc8bea6bd6f0bc5c1c6f89920da7c4940fc9ac2782a363206c179f0607ea067f9cdc6e59107aa9bac79905536001ef0fdbc041fd11ec1a4fc93f667ba6806"]
+        );
+        assert_eq!(
+            transaction_hash.unwrap(),
+            "0x67047de1485898f89424fbf4cb7ebbd7e996fe3ef6d9eb5e5e8cf74882444721"
+        );
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_estimate_gas_without_preflight_options() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x186a0"}"#.to_string(),
+        ));
+        push_sent(&evm_rpc);
+        let abi = parse_abi(&["function callback(string,uint256)"]).unwrap();
+        let args = [
+            Token::String("6765".to_string()),
+            Token::Uint(U256::from(1)),
+        ];
+        let fees = FeeEstimates {
+            max_fee_per_gas: U256::from(30_000_000_000u64),
+            max_priority_fee_per_gas: U256::from(1_500_000_000u64),
+        };
+
+        let transaction_hash = block_on(contract_interaction(
+            ContractDetails {
+                contract_address: CONTRACT_ADDRESS.to_string(),
+                abi: &abi,
+                function_name: "callback",
+                args: &args,
+            },
+            None,
+            RpcServices::EthSepolia(None),
+            U256::from(7),
+            &signer,
+            vec![],
+            None,
+            &FixedFeeStrategy(fees),
+            TransactionType::Eip1559,
+            evm_rpc.clone(),
+        ));
+
+        // the estimate of 100000 is raised by the default 20% safety margin
+        assert_eq!(
+            evm_rpc.sent_transactions(),
+            vec!["0x02f8f483aa36a7078459682f008506fc23ac008301d4c0945fbdb2315678afecb367f032d93f642f64180aa380b88442d1f6fd0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000043637363500000000000000000000000000000000000000000000000000000000c001a00f2ba7b89d807d78197393624ecd115dffc4d467b9670a5158edd7ae403063aaa054dc0a03d46ab6752349e8d163933e0c82c0a174fdb87f88d7df7eb9206c80d0"]
+        );
+        assert_eq!(
+            transaction_hash.unwrap(),
+            "0x86a8cc7ae8d6a5198b62bce66e2c05d902097ec72a81d4faa67989d1ef5d03a8"
+        );
+        let requests = evm_rpc.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("\"eth_estimateGas\""));
+        assert!(requests[0].contains("0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F"));
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_sign_and_send_legacy_transfer() {
+        // the transaction of the example of https://eips.ethereum.org/EIPS/eip-155, priced
+        // with the gas price returned by `eth_gasPrice`
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x4a817c800"}"#.to_string(),
+        ));
+        push_sent(&evm_rpc);
+
+        let transaction_hash = block_on(transfer_eth(
+            TransferArgs {
+                value: U256::exp10(18),
+                to: Some(Address::from([0x35; 20]).into()),
+                gas: None,
+            },
+            RpcServices::EthMainnet(None),
+            &signer,
+            vec![],
+            U256::from(9),
+            &HistoricalFeeStrategy::fast(),
+            TransactionType::Legacy,
+            evm_rpc.clone(),
+        ));
+
+        assert_eq!(
+            evm_rpc.sent_transactions(),
+            vec!["0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"]
+        );
+        assert_eq!(
+            transaction_hash.unwrap(),
+            "0x33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788"
+        );
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    fn preflight(simulate: bool) -> PreflightOptions {
+        PreflightOptions {
+            rpc_service: RpcService::EthSepolia(EthSepoliaService::Alchemy),
+            from: "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f".to_string(),
+            simulate,
+            gas_estimation: GasEstimationConfig::default(),
+            create_access_list: true,
+        }
+    }
+
+    fn json_rpc_result(result: &str) -> RequestResult {
+        RequestResult::Ok(format!(r#"{{"jsonrpc":"2.0","id":1,"result":{result}}}"#))
+    }
+
+    fn access_list_result(gas_used: u64) -> RequestResult {
+        json_rpc_result(
+            &serde_json::to_string(&AccessListWithGasUsed {
+                access_list: access_list(),
+                gas_used: U256::from(gas_used),
+            })
+            .unwrap(),
+        )
+    }
+
+    fn run_checks(
+        preflight: PreflightOptions,
+        gas: Option<U256>,
+        evm_rpc: &MockEvmRpcClient,
+    ) -> Result<(U256, AccessList), SendTransactionError> {
+        let abi = parse_abi(&["function callback(string,uint256)"]).unwrap();
+        let contract_details = ContractDetails {
+            contract_address: CONTRACT_ADDRESS.to_string(),
+            abi: &abi,
+            function_name: "callback",
+            args: &[],
+        };
+        block_on(run_preflight_checks(
+            &preflight,
+            &contract_details,
+            &[0xab, 0xcd],
+            gas,
+            TransactionType::Eip1559,
+            evm_rpc.clone(),
+        ))
+    }
+
+    #[test]
+    fn should_keep_given_gas_limit_when_attaching_access_list() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(access_list_result(50_000));
+
+        let (gas, access_list) =
+            run_checks(preflight(false), Some(U256::from(200_000)), &evm_rpc).unwrap();
+
+        assert_eq!(gas, U256::from(200_000));
+        assert_eq!(access_list, self::access_list());
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_lower_estimated_gas_limit_when_attaching_access_list() {
+        let evm_rpc = MockEvmRpcClient::new();
+        // an estimate of 100000 becomes a gas limit of 120000, 50000 with the access list
+        // a gas limit of 60000
+        evm_rpc.push_request(json_rpc_result(r#""0x186a0""#));
+        evm_rpc.push_request(access_list_result(50_000));
+
+        let (gas, access_list) = run_checks(preflight(false), None, &evm_rpc).unwrap();
+
+        assert_eq!(gas, U256::from(60_000));
+        assert_eq!(access_list, self::access_list());
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_abort_contract_interaction_if_simulation_reverts() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let evm_rpc = MockEvmRpcClient::new();
+        let mut revert_data = ERROR_STRING_SELECTOR.to_vec();
+        revert_data.extend(encode(&[Token::String(
+            "Only the coprocessor can call this function".to_string(),
+        )]));
+        evm_rpc.push_request(RequestResult::Ok(format!(
+            r#"{{"jsonrpc":"2.0","id":1,"error":{{"code":3,"message":"execution reverted","data":"{}"}}}}"#,
+            to_hex(&revert_data)
+        )));
+        let abi = parse_abi(&["function callback(string,uint256)"]).unwrap();
+        let args = [
+            Token::String("6765".to_string()),
+            Token::Uint(U256::from(1)),
+        ];
+
+        let result = block_on(contract_interaction(
+            ContractDetails {
+                contract_address: CONTRACT_ADDRESS.to_string(),
+                abi: &abi,
+                function_name: "callback",
+                args: &args,
+            },
+            Some(U256::from(100_000)),
+            RpcServices::EthSepolia(None),
+            U256::from(7),
+            &signer,
+            vec![],
+            Some(preflight(true)),
+            &FixedFeeStrategy(eip1559_fees()),
+            TransactionType::Eip1559,
+            evm_rpc.clone(),
+        ));
+
+        assert!(
+            matches!(
+                &result,
+                Err(SendTransactionError::SimulationReverted(EthCallError::Reverted { reason, .. }))
+                    if *reason == RevertReason::Error(
+                        "Only the coprocessor can call this function".to_string()
+                    )
+            ),
+            "{result:?}"
+        );
+        // neither the gas was estimated nor the transaction signed and sent
+        assert!(evm_rpc.sent_transactions().is_empty());
+        assert_eq!(evm_rpc.requests().len(), 1);
+        assert!(evm_rpc.is_exhausted());
+    }
+
+    #[test]
+    fn should_continue_if_simulation_cannot_be_executed() {
+        let evm_rpc = MockEvmRpcClient::new();
+        evm_rpc.push_request(RequestResult::Err(RpcError::HttpOutcallError(
+            HttpOutcallError::IcError {
+                code: RejectionCode::SysTransient,
+                message: "timeout".to_string(),
+            },
+        )));
+        evm_rpc.push_request(access_list_result(150_000));
+
+        let (gas, access_list) =
+            run_checks(preflight(true), Some(U256::from(100_000)), &evm_rpc).unwrap();
+
+        assert_eq!(gas, U256::from(100_000));
+        assert_eq!(access_list, AccessList::default());
+        assert!(evm_rpc.is_exhausted());
+    }
+}
@@ -0,0 +1,395 @@
+//! This module provides the `EvmRpcClient` trait, which abstracts the calls made to the EVM RPC canister.
+//! It is implemented by `EvmRpcCanister` for inter canister calls and by `MockEvmRpcClient`, which
+//! returns scripted responses so that code using the EVM RPC canister can be tested on the host.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use candid::Nat;
+use evm_rpc_canister_types::{
+    BlockTag, EvmRpcCanister, FeeHistoryArgs, GetLogsArgs, GetTransactionCountArgs,
+    MultiFeeHistoryResult, MultiGetBlockByNumberResult, MultiGetLogsResult,
+    MultiGetTransactionCountResult, MultiGetTransactionReceiptResult,
+    MultiSendRawTransactionResult, RequestCostResult, RequestResult, RpcConfig, RpcService,
+    RpcServices,
+};
+use ic_cdk::api::call::CallResult;
+
+/// The calls made to the EVM RPC canister.
+///
+/// The methods mirror the ones of `EvmRpcCanister`, see the EVM RPC canister's Candid interface.
+// the IC executes futures on a single thread, so callers never need them to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait EvmRpcClient: Clone {
+    async fn eth_fee_history(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: FeeHistoryArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiFeeHistoryResult,)>;
+
+    async fn eth_get_block_by_number(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        block_tag: BlockTag,
+        cycles: u128,
+    ) -> CallResult<(MultiGetBlockByNumberResult,)>;
+
+    async fn eth_get_logs(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: GetLogsArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiGetLogsResult,)>;
+
+    async fn eth_get_transaction_count(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: GetTransactionCountArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiGetTransactionCountResult,)>;
+
+    async fn eth_get_transaction_receipt(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        transaction_hash: String,
+        cycles: u128,
+    ) -> CallResult<(MultiGetTransactionReceiptResult,)>;
+
+    async fn eth_send_raw_transaction(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        raw_signed_transaction_hex: String,
+        cycles: u128,
+    ) -> CallResult<(MultiSendRawTransactionResult,)>;
+
+    async fn request(
+        &self,
+        rpc_service: RpcService,
+        json_rpc_payload: String,
+        max_response_bytes: u64,
+        cycles: u128,
+    ) -> CallResult<(RequestResult,)>;
+
+    async fn request_cost(
+        &self,
+        rpc_service: RpcService,
+        json_rpc_payload: String,
+        max_response_bytes: u64,
+    ) -> CallResult<(RequestCostResult,)>;
+}
+
+impl EvmRpcClient for EvmRpcCanister {
+    async fn eth_fee_history(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: FeeHistoryArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiFeeHistoryResult,)> {
+        EvmRpcCanister::eth_fee_history(self, rpc_services, config, args, cycles).await
+    }
+
+    async fn eth_get_block_by_number(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        block_tag: BlockTag,
+        cycles: u128,
+    ) -> CallResult<(MultiGetBlockByNumberResult,)> {
+        EvmRpcCanister::eth_get_block_by_number(self, rpc_services, config, block_tag, cycles).await
+    }
+
+    async fn eth_get_logs(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: GetLogsArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiGetLogsResult,)> {
+        EvmRpcCanister::eth_get_logs(self, rpc_services, config, args, cycles).await
+    }
+
+    async fn eth_get_transaction_count(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        args: GetTransactionCountArgs,
+        cycles: u128,
+    ) -> CallResult<(MultiGetTransactionCountResult,)> {
+        EvmRpcCanister::eth_get_transaction_count(self, rpc_services, config, args, cycles).await
+    }
+
+    async fn eth_get_transaction_receipt(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        transaction_hash: String,
+        cycles: u128,
+    ) -> CallResult<(MultiGetTransactionReceiptResult,)> {
+        EvmRpcCanister::eth_get_transaction_receipt(
+            self,
+            rpc_services,
+            config,
+            transaction_hash,
+            cycles,
+        )
+        .await
+    }
+
+    async fn eth_send_raw_transaction(
+        &self,
+        rpc_services: RpcServices,
+        config: Option<RpcConfig>,
+        raw_signed_transaction_hex: String,
+        cycles: u128,
+    ) -> CallResult<(MultiSendRawTransactionResult,)> {
+        EvmRpcCanister::eth_send_raw_transaction(
+            self,
+            rpc_services,
+            config,
+            raw_signed_transaction_hex,
+            cycles,
+        )
+        .await
+    }
+
+    async fn request(
+        &self,
+        rpc_service: RpcService,
+        json_rpc_payload: String,
+        max_response_bytes: u64,
+        cycles: u128,
+    ) -> CallResult<(RequestResult,)> {
+        EvmRpcCanister::request(
+            self,
+            rpc_service,
+            json_rpc_payload,
+            max_response_bytes,
+            cycles,
+        )
+        .await
+    }
+
+    async fn request_cost(
+        &self,
+        rpc_service: RpcService,
+        json_rpc_payload: String,
+        max_response_bytes: u64,
+    ) -> CallResult<(RequestCostResult,)> {
+        EvmRpcCanister::request_cost(self, rpc_service, json_rpc_payload, max_response_bytes).await
+    }
+}
+
+/// An in-memory `EvmRpcClient` returning scripted responses in the order they were pushed.
+///
+/// Every call records its arguments and pops the next scripted response of its method,
+/// panicking if there is none left. `request_cost` always returns 0 cycles. Clones of a
+/// mock share the same responses and recorded calls.
+#[derive(Clone, Debug, Default)]
+pub struct MockEvmRpcClient {
+    inner: Rc<RefCell<MockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    fee_history: VecDeque<MultiFeeHistoryResult>,
+    get_block_by_number: VecDeque<MultiGetBlockByNumberResult>,
+    get_logs: VecDeque<MultiGetLogsResult>,
+    get_transaction_count: VecDeque<MultiGetTransactionCountResult>,
+    get_transaction_receipt: VecDeque<MultiGetTransactionReceiptResult>,
+    send_raw_transaction: VecDeque<MultiSendRawTransactionResult>,
+    request: VecDeque<RequestResult>,
+    get_logs_args: Vec<GetLogsArgs>,
+    sent_transactions: Vec<String>,
+    requests: Vec<String>,
+}
+
+impl MockEvmRpcClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_fee_history(&self, result: MultiFeeHistoryResult) -> &Self {
+        self.inner.borrow_mut().fee_history.push_back(result);
+        self
+    }
+
+    pub fn push_get_block_by_number(&self, result: MultiGetBlockByNumberResult) -> &Self {
+        self.inner
+            .borrow_mut()
+            .get_block_by_number
+            .push_back(result);
+        self
+    }
+
+    pub fn push_get_logs(&self, result: MultiGetLogsResult) -> &Self {
+        self.inner.borrow_mut().get_logs.push_back(result);
+        self
+    }
+
+    pub fn push_get_transaction_count(&self, result: MultiGetTransactionCountResult) -> &Self {
+        self.inner
+            .borrow_mut()
+            .get_transaction_count
+            .push_back(result);
+        self
+    }
+
+    pub fn push_get_transaction_receipt(&self, result: MultiGetTransactionReceiptResult) -> &Self {
+        self.inner
+            .borrow_mut()
+            .get_transaction_receipt
+            .push_back(result);
+        self
+    }
+
+    pub fn push_send_raw_transaction(&self, result: MultiSendRawTransactionResult) -> &Self {
+        self.inner
+            .borrow_mut()
+            .send_raw_transaction
+            .push_back(result);
+        self
+    }
+
+    pub fn push_request(&self, result: RequestResult) -> &Self {
+        self.inner.borrow_mut().request.push_back(result);
+        self
+    }
+
+    /// Returns the arguments of all `eth_getLogs` calls made so far.
+    pub fn get_logs_args(&self) -> Vec<GetLogsArgs> {
+        self.inner.borrow().get_logs_args.clone()
+    }
+
+    /// Returns the raw transactions sent with `eth_sendRawTransaction` so far.
+    pub fn sent_transactions(&self) -> Vec<String> {
+        self.inner.borrow().sent_transactions.clone()
+    }
+
+    /// Returns the JSON-RPC payloads sent with `request` so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.inner.borrow().requests.clone()
+    }
+
+    /// Returns whether all scripted responses were consumed.
+    pub fn is_exhausted(&self) -> bool {
+        let state = self.inner.borrow();
+        state.fee_history.is_empty()
+            && state.get_block_by_number.is_empty()
+            && state.get_logs.is_empty()
+            && state.get_transaction_count.is_empty()
+            && state.get_transaction_receipt.is_empty()
+            && state.send_raw_transaction.is_empty()
+            && state.request.is_empty()
+    }
+}
+
+fn next<T>(responses: &mut VecDeque<T>, method: &str) -> CallResult<(T,)> {
+    match responses.pop_front() {
+        Some(response) => Ok((response,)),
+        None => panic!("no scripted response left for {method}"),
+    }
+}
+
+impl EvmRpcClient for MockEvmRpcClient {
+    async fn eth_fee_history(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        _args: FeeHistoryArgs,
+        _cycles: u128,
+    ) -> CallResult<(MultiFeeHistoryResult,)> {
+        next(&mut self.inner.borrow_mut().fee_history, "eth_feeHistory")
+    }
+
+    async fn eth_get_block_by_number(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        _block_tag: BlockTag,
+        _cycles: u128,
+    ) -> CallResult<(MultiGetBlockByNumberResult,)> {
+        next(
+            &mut self.inner.borrow_mut().get_block_by_number,
+            "eth_getBlockByNumber",
+        )
+    }
+
+    async fn eth_get_logs(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        args: GetLogsArgs,
+        _cycles: u128,
+    ) -> CallResult<(MultiGetLogsResult,)> {
+        let mut state = self.inner.borrow_mut();
+        state.get_logs_args.push(args);
+        next(&mut state.get_logs, "eth_getLogs")
+    }
+
+    async fn eth_get_transaction_count(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        _args: GetTransactionCountArgs,
+        _cycles: u128,
+    ) -> CallResult<(MultiGetTransactionCountResult,)> {
+        next(
+            &mut self.inner.borrow_mut().get_transaction_count,
+            "eth_getTransactionCount",
+        )
+    }
+
+    async fn eth_get_transaction_receipt(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        _transaction_hash: String,
+        _cycles: u128,
+    ) -> CallResult<(MultiGetTransactionReceiptResult,)> {
+        next(
+            &mut self.inner.borrow_mut().get_transaction_receipt,
+            "eth_getTransactionReceipt",
+        )
+    }
+
+    async fn eth_send_raw_transaction(
+        &self,
+        _rpc_services: RpcServices,
+        _config: Option<RpcConfig>,
+        raw_signed_transaction_hex: String,
+        _cycles: u128,
+    ) -> CallResult<(MultiSendRawTransactionResult,)> {
+        let mut state = self.inner.borrow_mut();
+        state.sent_transactions.push(raw_signed_transaction_hex);
+        next(&mut state.send_raw_transaction, "eth_sendRawTransaction")
+    }
+
+    async fn request(
+        &self,
+        _rpc_service: RpcService,
+        json_rpc_payload: String,
+        _max_response_bytes: u64,
+        _cycles: u128,
+    ) -> CallResult<(RequestResult,)> {
+        let mut state = self.inner.borrow_mut();
+        state.requests.push(json_rpc_payload);
+        next(&mut state.request, "request")
+    }
+
+    async fn request_cost(
+        &self,
+        _rpc_service: RpcService,
+        _json_rpc_payload: String,
+        _max_response_bytes: u64,
+    ) -> CallResult<(RequestCostResult,)> {
+        Ok((RequestCostResult::Ok(Nat::from(0u32)),))
+    }
+}
@@ -1,16 +1,22 @@
-//! This module provides functions for signing EIP-1559 transactions using t-ECDSA, getting the public key of the canister, and converting the public key to an Ethereum address.
+//! This module provides signers for legacy, EIP-2930 and EIP-1559 transactions as well as EIP-191 and EIP-712 messages using t-ECDSA or an in-process key, functions for getting the public key of the canister, and converting the public key to an Ethereum address.
 use candid::Principal;
 use ethers_core::abi::ethereum_types::{Address, U256};
+use ethers_core::k256::ecdsa::SigningKey;
 use ethers_core::types::transaction::eip1559::Eip1559TransactionRequest;
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::transaction::eip712::Eip712;
 use ethers_core::types::Signature;
-use ethers_core::utils::{hex, keccak256};
+use ethers_core::utils::{hash_message, hex, keccak256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
 
 use ic_cdk::api::management_canister::ecdsa::{
     ecdsa_public_key, sign_with_ecdsa, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
 };
 
 /// A signed transaction.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SignedTransaction {
     pub tx_hex: String,
     pub tx_hash: String,
@@ -42,8 +48,251 @@ pub async fn get_canister_public_key(
     key.public_key
 }
 
+/// Signs transactions and messages with a secp256k1 key per derivation path.
+///
+/// Implementors only provide the public key and the signature of a prehashed message, the
+/// encoding of transactions and messages is shared. `ThresholdEcdsaSigner` signs with the
+/// canister's t-ECDSA key, `LocalSigner` with an in-process key for tests.
+// the IC executes futures on a single thread, so callers never need them to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Signer {
+    /// Gets the SEC1 encoded public key of a derivation path.
+    async fn public_key(&self, derivation_path: &[Vec<u8>]) -> Vec<u8>;
+
+    /// Signs a 32 byte prehash and returns the 64 byte signature `r || s`.
+    async fn sign_hash(&self, hash: [u8; 32], derivation_path: &[Vec<u8>]) -> Vec<u8>;
+
+    /// Signs a legacy (EIP-155), EIP-2930 or EIP-1559 transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The transaction to sign.
+    /// * `derivation_path` - The derivation path of the ECDSA key.
+    ///
+    /// # Returns
+    ///
+    /// The signed transaction.
+    ///
+    /// # Panics
+    ///
+    /// If `tx` is a legacy transaction without a chain ID, as it could be replayed on other chains.
+    async fn sign_transaction(
+        &self,
+        tx: TypedTransaction,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> SignedTransaction {
+        // the sighash of typed transactions includes the transaction type,
+        // the one of legacy transactions the chain ID as specified by EIP-155
+        let txhash = tx.sighash();
+
+        let (signature, parity) = sign_with_parity(self, txhash.0, &derivation_path).await;
+        let v = match &tx {
+            TypedTransaction::Legacy(legacy) => {
+                let chain_id = legacy
+                    .chain_id
+                    .expect("legacy transactions must specify a chain ID")
+                    .as_u64();
+                parity + 35 + 2 * chain_id
+            }
+            TypedTransaction::Eip2930(_) | TypedTransaction::Eip1559(_) => parity,
+        };
+
+        let signature = Signature {
+            v,
+            r: U256::from_big_endian(&signature[0..32]),
+            s: U256::from_big_endian(&signature[32..64]),
+        };
+
+        // typed transactions are prefixed with their transaction type
+        let signed_tx_bytes = tx.rlp_signed(&signature).to_vec();
+
+        SignedTransaction {
+            tx_hex: format!("0x{}", hex::encode(&signed_tx_bytes)),
+            tx_hash: format!("0x{}", hex::encode(keccak256(&signed_tx_bytes))),
+        }
+    }
+
+    /// Signs a message as specified by EIP-191, i.e. like `personal_sign` of Ethereum wallets.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to sign, which is prefixed with `"\x19Ethereum Signed Message:\n"`
+    ///   and its length before hashing.
+    /// * `derivation_path` - The derivation path of the ECDSA key.
+    ///
+    /// # Returns
+    ///
+    /// The recoverable signature, which `Signature::to_vec` turns into the 65 bytes `r || s || v`
+    /// expected by `ecrecover` and OpenZeppelin's `ECDSA.recover`.
+    async fn sign_personal_message(
+        &self,
+        message: &[u8],
+        derivation_path: Vec<Vec<u8>>,
+    ) -> Signature {
+        sign_recoverable(self, hash_message(message).0, &derivation_path).await
+    }
+
+    /// Signs typed structured data as specified by EIP-712.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The typed data to sign, e.g. a `TypedData` parsed from JSON or a struct
+    ///   implementing `Eip712`.
+    /// * `derivation_path` - The derivation path of the ECDSA key.
+    ///
+    /// # Returns
+    ///
+    /// The recoverable signature of the EIP-712 encoding of `payload`, or the error of
+    /// `payload` if it could not be encoded.
+    async fn sign_typed_data<T: Eip712>(
+        &self,
+        payload: &T,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> Result<Signature, T::Error> {
+        let hash = payload.encode_eip712()?;
+        Ok(sign_recoverable(self, hash, &derivation_path).await)
+    }
+}
+
+/// Signs a 32 byte hash and computes the parity bit of the signature.
+async fn sign_with_parity<S: Signer + ?Sized>(
+    signer: &S,
+    hash: [u8; 32],
+    derivation_path: &[Vec<u8>],
+) -> (Vec<u8>, u64) {
+    let public_key = signer.public_key(derivation_path).await;
+    let signature = signer.sign_hash(hash, derivation_path).await;
+    let parity = y_parity(&hash, &signature, &public_key);
+    (signature, parity)
+}
+
+/// Signs a 32 byte hash and returns a signature with `v` set to 27 or 28, as used
+/// for messages.
+async fn sign_recoverable<S: Signer + ?Sized>(
+    signer: &S,
+    hash: [u8; 32],
+    derivation_path: &[Vec<u8>],
+) -> Signature {
+    let (signature, parity) = sign_with_parity(signer, hash, derivation_path).await;
+    Signature {
+        v: parity + 27,
+        r: U256::from_big_endian(&signature[0..32]),
+        s: U256::from_big_endian(&signature[32..64]),
+    }
+}
+
+/// A t-ECDSA signer that caches the public key of every derivation path it signs with.
+///
+/// The public key is needed to compute the parity bit of every signature. Caching it means
+/// it is fetched from the management canister at most once per derivation path instead of
+/// once per signature. Clones of a signer share the same cache.
+#[derive(Clone, Debug)]
+pub struct ThresholdEcdsaSigner {
+    key_id: EcdsaKeyId,
+    public_keys: Rc<RefCell<BTreeMap<Vec<Vec<u8>>, Vec<u8>>>>,
+}
+
+impl ThresholdEcdsaSigner {
+    /// Creates a signer with an empty public key cache.
+    pub fn new(key_id: EcdsaKeyId) -> Self {
+        Self {
+            key_id,
+            public_keys: Default::default(),
+        }
+    }
+
+    /// Returns the ID of the ECDSA key used for signing.
+    pub fn key_id(&self) -> &EcdsaKeyId {
+        &self.key_id
+    }
+}
+
+impl Signer for ThresholdEcdsaSigner {
+    /// Gets the public key of a derivation path, fetching it from the management canister
+    /// if it is not cached yet.
+    async fn public_key(&self, derivation_path: &[Vec<u8>]) -> Vec<u8> {
+        if let Some(public_key) = self.public_keys.borrow().get(derivation_path) {
+            return public_key.clone();
+        }
+        let public_key =
+            get_canister_public_key(self.key_id.clone(), None, derivation_path.to_vec()).await;
+        self.public_keys
+            .borrow_mut()
+            .insert(derivation_path.to_vec(), public_key.clone());
+        public_key
+    }
+
+    async fn sign_hash(&self, hash: [u8; 32], derivation_path: &[Vec<u8>]) -> Vec<u8> {
+        sign_with_ecdsa(SignWithEcdsaArgument {
+            message_hash: hash.to_vec(),
+            derivation_path: derivation_path.to_vec(),
+            key_id: self.key_id.clone(),
+        })
+        .await
+        .expect("failed to sign the message hash")
+        .0
+        .signature
+    }
+}
+
+/// A signer holding its secp256k1 key in process, e.g. for unit tests that run outside
+/// of a replica.
+///
+/// The empty derivation path signs with the key itself. Other derivation paths sign with a
+/// key derived by hashing the key together with the path, which gives every path its own
+/// address but does not match the derivation of threshold ECDSA keys.
+#[derive(Clone)]
+pub struct LocalSigner {
+    signing_key: SigningKey,
+}
+
+impl LocalSigner {
+    /// Creates a signer from a 32 byte secret key.
+    ///
+    /// # Panics
+    ///
+    /// If `secret_key` is not a valid secp256k1 secret key.
+    pub fn new(secret_key: &[u8]) -> Self {
+        Self {
+            signing_key: SigningKey::from_slice(secret_key).expect("invalid secret key"),
+        }
+    }
+
+    fn signing_key(&self, derivation_path: &[Vec<u8>]) -> SigningKey {
+        if derivation_path.is_empty() {
+            return self.signing_key.clone();
+        }
+        let mut preimage = self.signing_key.to_bytes().to_vec();
+        for segment in derivation_path {
+            preimage.extend((segment.len() as u64).to_be_bytes());
+            preimage.extend(segment);
+        }
+        SigningKey::from_slice(&keccak256(preimage)).expect("derived an invalid secret key")
+    }
+}
+
+impl Signer for LocalSigner {
+    async fn public_key(&self, derivation_path: &[Vec<u8>]) -> Vec<u8> {
+        self.signing_key(derivation_path)
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec()
+    }
+
+    async fn sign_hash(&self, hash: [u8; 32], derivation_path: &[Vec<u8>]) -> Vec<u8> {
+        let (signature, _) = self
+            .signing_key(derivation_path)
+            .sign_prehash_recoverable(&hash)
+            .expect("failed to sign the message hash");
+        signature.to_bytes().to_vec()
+    }
+}
+
 /// Signs an EIP-1559 transaction.
 ///
+/// This fetches the public key on every call, use a `ThresholdEcdsaSigner` to cache it.
+///
 /// # Arguments
 ///
 /// * `tx` - The EIP-1559 transaction to sign.
@@ -58,39 +307,9 @@ pub async fn sign_eip1559_transaction(
     key_id: EcdsaKeyId,
     derivation_path: Vec<Vec<u8>>,
 ) -> SignedTransaction {
-    const EIP1559_TX_ID: u8 = 2;
-
-    let ecdsa_pub_key =
-        get_canister_public_key(key_id.clone(), None, derivation_path.clone()).await;
-
-    let mut unsigned_tx_bytes = tx.rlp().to_vec();
-    unsigned_tx_bytes.insert(0, EIP1559_TX_ID);
-
-    let txhash = keccak256(&unsigned_tx_bytes);
-
-    let signature = sign_with_ecdsa(SignWithEcdsaArgument {
-        message_hash: txhash.to_vec(),
-        derivation_path,
-        key_id,
-    })
-    .await
-    .expect("failed to sign the transaction")
-    .0
-    .signature;
-
-    let signature = Signature {
-        v: y_parity(&txhash, &signature, &ecdsa_pub_key),
-        r: U256::from_big_endian(&signature[0..32]),
-        s: U256::from_big_endian(&signature[32..64]),
-    };
-
-    let mut signed_tx_bytes = tx.rlp_signed(&signature).to_vec();
-    signed_tx_bytes.insert(0, EIP1559_TX_ID);
-
-    SignedTransaction {
-        tx_hex: format!("0x{}", hex::encode(&signed_tx_bytes)),
-        tx_hash: format!("0x{}", hex::encode(keccak256(&signed_tx_bytes))),
-    }
+    ThresholdEcdsaSigner::new(key_id)
+        .sign_transaction(TypedTransaction::Eip1559(tx), derivation_path)
+        .await
 }
 
 /// Converts the public key bytes to an Ethereum address with a checksum.
@@ -149,3 +368,146 @@ fn y_parity(prehash: &[u8], sig: &[u8], pubkey: &[u8]) -> u64 {
         hex::encode(pubkey)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::transaction::eip712::TypedData;
+    use ethers_core::types::{Transaction, TransactionRequest, U64};
+    use ethers_core::utils::rlp::{Decodable, Rlp};
+    use ethers_core::utils::to_checksum;
+
+    use crate::test_fixtures::block_on;
+
+    fn address(signer: &LocalSigner, derivation_path: &[Vec<u8>]) -> String {
+        pubkey_bytes_to_address(&block_on(signer.public_key(derivation_path)))
+    }
+
+    #[test]
+    fn should_sign_legacy_transaction_as_specified_by_eip155() {
+        // the example of https://eips.ethereum.org/EIPS/eip-155
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let tx = TransactionRequest {
+            from: None,
+            to: Some(Address::from([0x35; 20]).into()),
+            gas: Some(U256::from(21000)),
+            gas_price: Some(U256::from(20_000_000_000u64)),
+            value: Some(U256::exp10(18)),
+            data: None,
+            nonce: Some(U256::from(9)),
+            chain_id: Some(U64::from(1)),
+        };
+
+        let signed = block_on(signer.sign_transaction(TypedTransaction::Legacy(tx), vec![]));
+
+        assert_eq!(
+            signed.tx_hex,
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn should_sign_eip1559_transaction_deterministically() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+        let tx = TypedTransaction::Eip1559(Eip1559TransactionRequest {
+            to: Some(Address::from([0x35; 20]).into()),
+            gas: Some(U256::from(21000)),
+            max_fee_per_gas: Some(U256::from(30_000_000_000u64)),
+            max_priority_fee_per_gas: Some(U256::from(1_500_000_000u64)),
+            value: Some(U256::exp10(18)),
+            nonce: Some(U256::from(9)),
+            chain_id: Some(U64::from(11155111)),
+            ..Default::default()
+        });
+
+        let signed = block_on(signer.sign_transaction(tx.clone(), vec![]));
+        assert_eq!(signed, block_on(signer.sign_transaction(tx, vec![])));
+
+        let bytes = hex::decode(signed.tx_hex.trim_start_matches("0x")).unwrap();
+        assert_eq!(bytes[0], 2);
+        let decoded = Transaction::decode(&Rlp::new(&bytes)).unwrap();
+        assert_eq!(format!("{:?}", decoded.hash), signed.tx_hash);
+        assert_eq!(
+            to_checksum(&decoded.recover_from().unwrap(), None),
+            address(&signer, &[])
+        );
+    }
+
+    #[test]
+    fn should_sign_personal_message() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+
+        let signature = block_on(signer.sign_personal_message(b"hello", vec![]));
+
+        assert!(signature.v == 27 || signature.v == 28);
+        assert_eq!(
+            to_checksum(&signature.recover("hello").unwrap(), None),
+            address(&signer, &[])
+        );
+    }
+
+    #[test]
+    fn should_sign_typed_data_as_specified_by_eip712() {
+        // the "Mail" example of https://eips.ethereum.org/EIPS/eip-712
+        let signer = LocalSigner::new(&keccak256("cow"));
+        let typed_data: TypedData = serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "Person": [
+                    {"name": "name", "type": "string"},
+                    {"name": "wallet", "type": "address"}
+                ],
+                "Mail": [
+                    {"name": "from", "type": "Person"},
+                    {"name": "to", "type": "Person"},
+                    {"name": "contents", "type": "string"}
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+                "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+                "contents": "Hello, Bob!"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            hex::encode(typed_data.encode_eip712().unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+
+        let signature = block_on(signer.sign_typed_data(&typed_data, vec![])).unwrap();
+
+        assert_eq!(
+            signature.to_string(),
+            "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c"
+        );
+        assert_eq!(
+            address(&signer, &[]),
+            "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"
+        );
+    }
+
+    #[test]
+    fn should_derive_different_keys_per_derivation_path() {
+        let signer = LocalSigner::new(&[0x46; 32]);
+
+        assert_ne!(address(&signer, &[]), address(&signer, &[b"tag".to_vec()]));
+        assert_eq!(
+            address(&signer, &[b"tag".to_vec()]),
+            address(&signer, &[b"tag".to_vec()])
+        );
+    }
+}
@@ -1,18 +1,63 @@
 //! This module provides functions for estimating transaction fees and getting the fee history.
+use std::fmt;
+
 use candid::Nat;
 use ethers_core::types::U256;
 use evm_rpc_canister_types::{
-    BlockTag, EvmRpcCanister, FeeHistory, FeeHistoryArgs, FeeHistoryResult, MultiFeeHistoryResult,
-    RpcServices,
+    BlockTag, FeeHistory, FeeHistoryArgs, FeeHistoryResult, MultiFeeHistoryResult, RequestResult,
+    RpcError, RpcService, RpcServices,
 };
+use ic_cdk::api::call::RejectionCode;
+use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
-use std::ops::Add;
 
 use crate::conversions::nat_to_u256;
+use crate::eth_call::EthCallError;
+use crate::evm_rpc_client::EvmRpcClient;
+use crate::request::{request, JsonRpcResult};
 
 /// The minimum suggested maximum priority fee per gas.
 const MIN_SUGGEST_MAX_PRIORITY_FEE_PER_GAS: u32 = 1_500_000_000;
 
+/// Represents a JSON-RPC request for the current gas price.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GasPriceJsonRpcRequest {
+    pub id: u64,
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: Vec<String>,
+}
+
+/// Represents the errors that can occur when estimating the transaction fees.
+#[derive(Clone, Debug)]
+pub enum FeeError {
+    /// The call to the EVM RPC canister failed.
+    CallFailed(RejectionCode, String),
+    /// The RPC providers returned an error, e.g. because the chain doesn't support
+    /// `eth_feeHistory`.
+    FeeHistory(RpcError),
+    /// The RPC providers returned different fee histories.
+    InconsistentFeeHistory,
+    /// The RPC providers returned no fee history.
+    MissingFeeHistory,
+    /// The `eth_gasPrice` request failed.
+    GasPrice(EthCallError),
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeError::CallFailed(code, message) => {
+                write!(f, "call failed with {code:?}: {message}")
+            }
+            FeeError::FeeHistory(e) => write!(f, "fee history error: {e:?}"),
+            FeeError::InconsistentFeeHistory => write!(f, "fee history is inconsistent"),
+            FeeError::MissingFeeHistory => write!(f, "no fee history was returned"),
+            FeeError::GasPrice(e) => write!(f, "gas price error: {e}"),
+        }
+    }
+}
+
 /// Gets the fee history.
 ///
 /// # Arguments
@@ -21,18 +66,19 @@ const MIN_SUGGEST_MAX_PRIORITY_FEE_PER_GAS: u32 = 1_500_000_000;
 /// * `newest_block` - The newest block to get the fee history for.
 /// * `reward_percentiles` - The reward percentiles to get the fee history for.
 /// * `rpc_services` - The RPC services used to interact with the EVM.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
 ///
 /// # Returns
 ///
-/// The fee history.
+/// The fee history, or a `FeeError` if the call failed, the providers disagree or the chain
+/// doesn't support `eth_feeHistory`.
 pub async fn fee_history(
     block_count: Nat,
     newest_block: BlockTag,
     reward_percentiles: Option<Vec<u8>>,
     rpc_services: RpcServices,
-    evm_rpc: EvmRpcCanister,
-) -> FeeHistory {
+    evm_rpc: impl EvmRpcClient,
+) -> Result<FeeHistory, FeeError> {
     let fee_history_args: FeeHistoryArgs = FeeHistoryArgs {
         blockCount: block_count,
         newestBlock: newest_block,
@@ -47,25 +93,264 @@ pub async fn fee_history(
     {
         Ok((res,)) => match res {
             MultiFeeHistoryResult::Consistent(fee_history) => match fee_history {
-                FeeHistoryResult::Ok(fee_history) => fee_history.unwrap(),
-                FeeHistoryResult::Err(e) => {
-                    ic_cdk::trap(format!("Error: {:?}", e).as_str());
-                }
+                FeeHistoryResult::Ok(fee_history) => fee_history.ok_or(FeeError::MissingFeeHistory),
+                FeeHistoryResult::Err(e) => Err(FeeError::FeeHistory(e)),
             },
-            MultiFeeHistoryResult::Inconsistent(_) => {
-                ic_cdk::trap("Fee history is inconsistent");
-            }
+            MultiFeeHistoryResult::Inconsistent(_) => Err(FeeError::InconsistentFeeHistory),
         },
-        Err(e) => ic_cdk::trap(format!("Error: {:?}", e).as_str()),
+        Err((code, message)) => Err(FeeError::CallFailed(code, message)),
     }
 }
 
+/// Gets the current gas price with `eth_gasPrice`, used to price legacy and EIP-2930
+/// transactions.
+///
+/// # Arguments
+///
+/// * `rpc_service` - The RPC service used to interact with the EVM.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The gas price in wei, or a `FeeError` if the request failed.
+pub async fn gas_price(
+    rpc_service: RpcService,
+    evm_rpc: impl EvmRpcClient,
+) -> Result<U256, FeeError> {
+    let max_response_bytes = 1024;
+    let json_rpc_payload = serde_json::to_string(&GasPriceJsonRpcRequest {
+        id: 1,
+        jsonrpc: "2.0".to_string(),
+        method: "eth_gasPrice".to_string(),
+        params: vec![],
+    })
+    .expect("Error while encoding JSON-RPC request");
+
+    let res = request(rpc_service, json_rpc_payload, max_response_bytes, evm_rpc).await;
+
+    let json: JsonRpcResult = match res {
+        RequestResult::Ok(ok) => serde_json::from_str(&ok)
+            .map_err(|e| FeeError::GasPrice(EthCallError::InvalidResponse(format!("{e}: {ok}"))))?,
+        RequestResult::Err(err) => return Err(FeeError::GasPrice(EthCallError::Transport(err))),
+    };
+
+    if let Some(error) = json.error {
+        return Err(FeeError::GasPrice(EthCallError::JsonRpc {
+            code: error.code,
+            message: error.message,
+        }));
+    }
+
+    json.result
+        .and_then(|result| U256::from_str_radix(result.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| {
+            FeeError::GasPrice(EthCallError::InvalidResponse(
+                "missing or invalid `result`".to_string(),
+            ))
+        })
+}
+
 /// Represents the fee estimates.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FeeEstimates {
     pub max_fee_per_gas: U256,
     pub max_priority_fee_per_gas: U256,
 }
 
+/// Represents the fee history a fee strategy bases its estimates on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeHistoryRequest {
+    /// The number of historical blocks to query.
+    pub block_count: u8,
+    /// The reward percentile to query for each block.
+    pub reward_percentile: u8,
+}
+
+/// A strategy for choosing the fees of an EIP-1559 transaction.
+pub trait FeeStrategy {
+    /// Returns the fee history the strategy needs, or `None` if it does not depend on it.
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest>;
+
+    /// Computes the fee estimates from the fee history requested by `fee_history_request`.
+    fn estimate(&self, fee_history: Option<&FeeHistory>) -> FeeEstimates;
+}
+
+/// Derives the tip from a percentile of the rewards paid in recent blocks and sets the
+/// maximum fee to a multiple of the current base fee plus the tip, leaving headroom for
+/// base fee increases while the transaction is pending.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoricalFeeStrategy {
+    /// The number of historical blocks to base the fee estimates on.
+    pub block_count: u8,
+    /// The percentile of the rewards paid in each block, the tip is the median over all blocks.
+    pub reward_percentile: u8,
+    /// The minimum tip, used if the rewards paid in recent blocks are lower.
+    pub min_max_priority_fee_per_gas: U256,
+    /// The percentage of the current base fee included in the maximum fee.
+    pub base_fee_multiplier_percent: u64,
+}
+
+impl HistoricalFeeStrategy {
+    /// Pays the median tip of recent blocks, trading inclusion speed for lower fees.
+    pub fn conservative() -> Self {
+        Self {
+            block_count: 20,
+            reward_percentile: 50,
+            min_max_priority_fee_per_gas: U256::zero(),
+            base_fee_multiplier_percent: 150,
+        }
+    }
+
+    /// Pays a tip in the 95th percentile of recent blocks to be included quickly.
+    pub fn fast() -> Self {
+        Self {
+            block_count: 9,
+            reward_percentile: 95,
+            min_max_priority_fee_per_gas: U256::from(MIN_SUGGEST_MAX_PRIORITY_FEE_PER_GAS),
+            base_fee_multiplier_percent: 200,
+        }
+    }
+}
+
+impl Default for HistoricalFeeStrategy {
+    fn default() -> Self {
+        Self::fast()
+    }
+}
+
+impl FeeStrategy for HistoricalFeeStrategy {
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest> {
+        Some(FeeHistoryRequest {
+            block_count: self.block_count,
+            reward_percentile: self.reward_percentile,
+        })
+    }
+
+    fn estimate(&self, fee_history: Option<&FeeHistory>) -> FeeEstimates {
+        let fee_history = fee_history.expect("fee history should be provided");
+        // we are setting the `max_priority_fee_per_gas` based on this article:
+        // https://docs.alchemy.com/docs/maxpriorityfeepergas-vs-maxfeepergas
+        // the tip is the median of the requested percentile of the tips paid in recent blocks.
+        let mut rewards: Vec<U256> = fee_history
+            .reward
+            .iter()
+            .flatten()
+            .map(nat_to_u256)
+            .collect();
+        // sort the tips in ascending order
+        rewards.sort_unstable();
+        // get the median by accessing the element in the middle
+        // set tip to 0 if there are not enough blocks in case of a local testnet
+        let median_reward = if rewards.is_empty() {
+            U256::zero()
+        } else {
+            rewards[median_index(rewards.len())]
+        };
+        let max_priority_fee_per_gas = median_reward.max(self.min_max_priority_fee_per_gas);
+
+        FeeEstimates {
+            max_fee_per_gas: with_base_fee(
+                fee_history,
+                self.base_fee_multiplier_percent,
+                max_priority_fee_per_gas,
+            ),
+            max_priority_fee_per_gas,
+        }
+    }
+}
+
+/// Pays a fixed tip and sets the maximum fee to a multiple of the current base fee plus the tip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BaseFeeMultiplierStrategy {
+    /// The percentage of the current base fee included in the maximum fee.
+    pub multiplier_percent: u64,
+    /// The tip paid to the block producer.
+    pub max_priority_fee_per_gas: U256,
+}
+
+impl FeeStrategy for BaseFeeMultiplierStrategy {
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest> {
+        // the latest block is enough to know the current base fee
+        Some(FeeHistoryRequest {
+            block_count: 1,
+            reward_percentile: 50,
+        })
+    }
+
+    fn estimate(&self, fee_history: Option<&FeeHistory>) -> FeeEstimates {
+        let fee_history = fee_history.expect("fee history should be provided");
+        FeeEstimates {
+            max_fee_per_gas: with_base_fee(
+                fee_history,
+                self.multiplier_percent,
+                self.max_priority_fee_per_gas,
+            ),
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+        }
+    }
+}
+
+/// Always uses the same fees, regardless of the network conditions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedFeeStrategy(pub FeeEstimates);
+
+impl FeeStrategy for FixedFeeStrategy {
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest> {
+        None
+    }
+
+    fn estimate(&self, _fee_history: Option<&FeeHistory>) -> FeeEstimates {
+        self.0.clone()
+    }
+}
+
+/// Limits the fees estimated by another strategy to a maximum fee ceiling.
+pub struct CappedFeeStrategy<S> {
+    pub strategy: S,
+    /// The ceiling for the maximum fee, the tip is lowered to not exceed it either.
+    pub max_fee_per_gas: U256,
+}
+
+impl<S: FeeStrategy> FeeStrategy for CappedFeeStrategy<S> {
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest> {
+        self.strategy.fee_history_request()
+    }
+
+    fn estimate(&self, fee_history: Option<&FeeHistory>) -> FeeEstimates {
+        let estimates = self.strategy.estimate(fee_history);
+        let max_fee_per_gas = estimates.max_fee_per_gas.min(self.max_fee_per_gas);
+        FeeEstimates {
+            max_fee_per_gas,
+            max_priority_fee_per_gas: estimates.max_priority_fee_per_gas.min(max_fee_per_gas),
+        }
+    }
+}
+
+impl FeeStrategy for Box<dyn FeeStrategy> {
+    fn fee_history_request(&self) -> Option<FeeHistoryRequest> {
+        self.as_ref().fee_history_request()
+    }
+
+    fn estimate(&self, fee_history: Option<&FeeHistory>) -> FeeEstimates {
+        self.as_ref().estimate(fee_history)
+    }
+}
+
+/// Computes `base_fee * multiplier_percent / 100 + max_priority_fee_per_gas` for the base fee
+/// of the next block.
+fn with_base_fee(
+    fee_history: &FeeHistory,
+    multiplier_percent: u64,
+    max_priority_fee_per_gas: U256,
+) -> U256 {
+    let base_fee_per_gas = fee_history
+        .baseFeePerGas
+        .last()
+        .map(nat_to_u256)
+        .unwrap_or_default();
+    base_fee_per_gas.saturating_mul(U256::from(multiplier_percent)) / 100 + max_priority_fee_per_gas
+}
+
 /// Gets the median index.
 ///
 /// # Arguments
@@ -86,56 +371,228 @@ fn median_index(length: usize) -> usize {
 ///
 /// # Arguments
 ///
-/// * `block_count` - The number of historical blocks to base the fee estimates on.
+/// * `fee_strategy` - The strategy used to choose the fees.
 /// * `rpc_services` - The RPC services used to interact with the EVM.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
+///
+/// # Returns
+///
+/// The fee estimates, or a `FeeError` if the fee history the strategy needs could not be
+/// fetched.
 pub async fn estimate_transaction_fees(
-    block_count: u8,
+    fee_strategy: &dyn FeeStrategy,
     rpc_services: RpcServices,
-    evm_rpc: EvmRpcCanister,
-) -> FeeEstimates {
-    // we are setting the `max_priority_fee_per_gas` based on this article:
-    // https://docs.alchemy.com/docs/maxpriorityfeepergas-vs-maxfeepergas
-    // following this logic, the base fee will be derived from the block history automatically
-    // and we only specify the maximum priority fee per gas (tip).
-    // the tip is derived from the fee history of the last 9 blocks, more specifically
-    // from the 95th percentile of the tip.
-    let fee_history = fee_history(
-        Nat::from(block_count),
-        BlockTag::Latest,
-        Some(vec![95]),
-        rpc_services,
-        evm_rpc,
-    )
-    .await;
-
-    let median_index = median_index(block_count.into());
-
-    // baseFeePerGas
-    let base_fee_per_gas = fee_history.baseFeePerGas.last().unwrap().clone();
-
-    // obtain the 95th percentile of the tips for the past 9 blocks
-    let mut percentile_95: Vec<Nat> = fee_history
-        .reward
-        .into_iter()
-        .flat_map(|x| x.into_iter())
-        .collect();
-    // sort the tips in ascending order
-    percentile_95.sort_unstable();
-    // get the median by accessing the element in the middle
-    // set tip to 0 if there are not enough blocks in case of a local testnet
-    let median_reward = percentile_95
-        .get(median_index)
-        .unwrap_or(&Nat::from(0_u8))
-        .clone();
-
-    let max_priority_fee_per_gas = median_reward
-        .clone()
-        .add(base_fee_per_gas)
-        .max(Nat::from(MIN_SUGGEST_MAX_PRIORITY_FEE_PER_GAS));
-
-    FeeEstimates {
-        max_fee_per_gas: nat_to_u256(&max_priority_fee_per_gas),
-        max_priority_fee_per_gas: nat_to_u256(&median_reward),
+    evm_rpc: impl EvmRpcClient,
+) -> Result<FeeEstimates, FeeError> {
+    let fee_history = match fee_strategy.fee_history_request() {
+        Some(FeeHistoryRequest {
+            block_count,
+            reward_percentile,
+        }) => Some(
+            fee_history(
+                Nat::from(block_count),
+                BlockTag::Latest,
+                Some(vec![reward_percentile]),
+                rpc_services,
+                evm_rpc,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    Ok(fee_strategy.estimate(fee_history.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fee history with one reward per block.
+    fn history(rewards: &[u64], base_fees: &[u64]) -> FeeHistory {
+        FeeHistory {
+            reward: rewards.iter().map(|&r| vec![Nat::from(r)]).collect(),
+            gasUsedRatio: vec![0.5; rewards.len()],
+            oldestBlock: Nat::from(100u32),
+            baseFeePerGas: base_fees.iter().map(|&b| Nat::from(b)).collect(),
+        }
+    }
+
+    fn estimates(max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> FeeEstimates {
+        FeeEstimates {
+            max_fee_per_gas: U256::from(max_fee_per_gas),
+            max_priority_fee_per_gas: U256::from(max_priority_fee_per_gas),
+        }
+    }
+
+    #[test]
+    fn should_select_median_of_rewards_for_historical_strategy() {
+        let gwei = 1_000_000_000;
+        // (strategy, rewards, base fees, expected estimates)
+        let cases = [
+            (
+                HistoricalFeeStrategy::conservative(),
+                vec![10, 30, 20],
+                vec![100],
+                estimates(170, 20),
+            ),
+            // the lower median is taken for an even number of blocks
+            (
+                HistoricalFeeStrategy::conservative(),
+                vec![40, 10, 30, 20],
+                vec![100],
+                estimates(170, 20),
+            ),
+            // the base fee of the next block is the last one
+            (
+                HistoricalFeeStrategy::conservative(),
+                vec![10],
+                vec![100, 200],
+                estimates(310, 10),
+            ),
+            (
+                HistoricalFeeStrategy::fast(),
+                vec![2 * gwei, 3 * gwei, gwei],
+                vec![10 * gwei],
+                estimates(22 * gwei, 2 * gwei),
+            ),
+            // the tip is raised to the minimum
+            (
+                HistoricalFeeStrategy::fast(),
+                vec![1, 2, 3],
+                vec![100],
+                estimates(1_500_000_200, 1_500_000_000),
+            ),
+        ];
+        for (strategy, rewards, base_fees, expected) in cases {
+            assert_eq!(
+                strategy.estimate(Some(&history(&rewards, &base_fees))),
+                expected,
+                "{strategy:?} with rewards {rewards:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_fall_back_to_minimum_tip_without_rewards() {
+        // (strategy, rewards, base fees, expected estimates)
+        let cases = [
+            (
+                HistoricalFeeStrategy::conservative(),
+                vec![],
+                vec![100],
+                estimates(150, 0),
+            ),
+            (
+                HistoricalFeeStrategy::conservative(),
+                vec![0, 0, 0],
+                vec![100],
+                estimates(150, 0),
+            ),
+            (
+                HistoricalFeeStrategy::fast(),
+                vec![],
+                vec![],
+                estimates(1_500_000_000, 1_500_000_000),
+            ),
+            (
+                HistoricalFeeStrategy::fast(),
+                vec![0, 0],
+                vec![0],
+                estimates(1_500_000_000, 1_500_000_000),
+            ),
+        ];
+        for (strategy, rewards, base_fees, expected) in cases {
+            assert_eq!(
+                strategy.estimate(Some(&history(&rewards, &base_fees))),
+                expected,
+                "{strategy:?} with rewards {rewards:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_request_fee_history_of_historical_strategy() {
+        assert_eq!(
+            HistoricalFeeStrategy::conservative().fee_history_request(),
+            Some(FeeHistoryRequest {
+                block_count: 20,
+                reward_percentile: 50,
+            })
+        );
+        assert_eq!(
+            HistoricalFeeStrategy::fast().fee_history_request(),
+            Some(FeeHistoryRequest {
+                block_count: 9,
+                reward_percentile: 95,
+            })
+        );
+    }
+
+    #[test]
+    fn should_multiply_latest_base_fee() {
+        let strategy = BaseFeeMultiplierStrategy {
+            multiplier_percent: 125,
+            max_priority_fee_per_gas: U256::from(2),
+        };
+        // (base fees, expected estimates)
+        let cases = [
+            (vec![100], estimates(127, 2)),
+            (vec![100, 200], estimates(252, 2)),
+            (vec![], estimates(2, 2)),
+        ];
+        for (base_fees, expected) in cases {
+            assert_eq!(
+                strategy.estimate(Some(&history(&[], &base_fees))),
+                expected,
+                "base fees {base_fees:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_use_fixed_fees_without_fee_history() {
+        let strategy = FixedFeeStrategy(estimates(100, 30));
+        assert_eq!(strategy.fee_history_request(), None);
+        assert_eq!(strategy.estimate(None), estimates(100, 30));
+    }
+
+    #[test]
+    fn should_clamp_estimates_to_cap() {
+        // (cap, expected estimates) for an estimated maximum fee of 100 and a tip of 30
+        let cases = [
+            (200, estimates(100, 30)),
+            (100, estimates(100, 30)),
+            (80, estimates(80, 30)),
+            // the tip never exceeds the maximum fee
+            (20, estimates(20, 20)),
+            (0, estimates(0, 0)),
+        ];
+        for (cap, expected) in cases {
+            let strategy = CappedFeeStrategy {
+                strategy: FixedFeeStrategy(estimates(100, 30)),
+                max_fee_per_gas: U256::from(cap),
+            };
+            assert_eq!(strategy.estimate(None), expected, "cap {cap}");
+        }
+    }
+
+    #[test]
+    fn should_delegate_fee_history_request_of_capped_strategy() {
+        let strategy = CappedFeeStrategy {
+            strategy: HistoricalFeeStrategy::fast(),
+            max_fee_per_gas: U256::from(1),
+        };
+        assert_eq!(
+            strategy.fee_history_request(),
+            HistoricalFeeStrategy::fast().fee_history_request()
+        );
+        let strategy = CappedFeeStrategy {
+            strategy: HistoricalFeeStrategy::conservative(),
+            max_fee_per_gas: U256::from(160),
+        };
+        assert_eq!(
+            strategy.estimate(Some(&history(&[10, 30, 20], &[100]))),
+            estimates(160, 20)
+        );
     }
 }
@@ -1,8 +1,15 @@
 pub mod conversions;
 pub mod eth_call;
+pub mod eth_create_access_list;
+pub mod eth_estimate_gas;
+pub mod eth_get_balance;
 pub mod eth_get_transaction_count;
 pub mod eth_send_raw_transaction;
+pub mod evm_rpc_client;
 pub mod evm_signer;
 pub mod fees;
 pub mod request;
 pub mod request_cost;
+pub mod revert;
+#[cfg(test)]
+mod test_fixtures;
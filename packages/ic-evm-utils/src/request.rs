@@ -1,7 +1,10 @@
 //! This module provides functions for making arbitrary requests to EVM RPC providers through the EVM RPC canister.
-use evm_rpc_canister_types::{EvmRpcCanister, RequestResult, RpcService};
+use ethers_core::utils::hex;
+use evm_rpc_canister_types::{RequestResult, RpcService, RpcServices};
 use serde::{Deserialize, Serialize};
 
+use crate::eth_send_raw_transaction::IntoChainId;
+use crate::evm_rpc_client::EvmRpcClient;
 use crate::request_cost::request_cost;
 
 /// Make a arbitrary request to EVM RPC provider through the EVM RPC canister.
@@ -11,7 +14,7 @@ use crate::request_cost::request_cost;
 /// * `rpc_service` - The RPC service used to interact with the EVM.
 /// * `json_rpc_payload` - The JSON-RPC payload to send.
 /// * `max_response_bytes` - The maximum number of response bytes to accept.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
 ///
 /// # Returns
 ///
@@ -20,7 +23,7 @@ pub async fn request(
     rpc_service: RpcService,
     json_rpc_payload: String,
     max_response_bytes: u64,
-    evm_rpc: EvmRpcCanister,
+    evm_rpc: impl EvmRpcClient,
 ) -> RequestResult {
     // estimate cycles costs
     let cycles = request_cost(
@@ -40,6 +43,35 @@ pub async fn request(
     }
 }
 
+/// Returns the first of the RPC services, used for the requests sent to a single provider.
+/// If the services use the default providers of their chain, the EVM RPC canister picks one.
+pub fn first_rpc_service(rpc_services: &RpcServices) -> RpcService {
+    let first = match rpc_services {
+        RpcServices::EthSepolia(services) => services
+            .as_ref()
+            .and_then(|services| services.first().cloned())
+            .map(RpcService::EthSepolia),
+        RpcServices::EthMainnet(services) => services
+            .as_ref()
+            .and_then(|services| services.first().cloned())
+            .map(RpcService::EthMainnet),
+        RpcServices::ArbitrumOne(services) => services
+            .as_ref()
+            .and_then(|services| services.first().cloned())
+            .map(RpcService::ArbitrumOne),
+        RpcServices::BaseMainnet(services) => services
+            .as_ref()
+            .and_then(|services| services.first().cloned())
+            .map(RpcService::BaseMainnet),
+        RpcServices::OptimismMainnet(services) => services
+            .as_ref()
+            .and_then(|services| services.first().cloned())
+            .map(RpcService::OptimismMainnet),
+        RpcServices::Custom { services, .. } => services.first().cloned().map(RpcService::Custom),
+    };
+    first.unwrap_or_else(|| RpcService::Chain(rpc_services.chain_id().as_u64()))
+}
+
 /// Represents a JSON-RPC result.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JsonRpcResult {
@@ -52,4 +84,22 @@ pub struct JsonRpcResult {
 pub struct JsonRpcError {
     pub code: isize,
     pub message: String,
+    /// Additional information about the error, e.g. the revert data of a failed call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl JsonRpcError {
+    /// Returns the revert data attached to the error, if any.
+    ///
+    /// Most providers return the revert data as a hex string in `data`, some nest it in
+    /// an object as `data.data`.
+    pub fn revert_data(&self) -> Option<Vec<u8>> {
+        let data = match self.data.as_ref()? {
+            serde_json::Value::String(data) => data.as_str(),
+            serde_json::Value::Object(object) => object.get("data")?.as_str()?,
+            _ => return None,
+        };
+        hex::decode(data.strip_prefix("0x").unwrap_or(data)).ok()
+    }
 }
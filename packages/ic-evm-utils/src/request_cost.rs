@@ -1,7 +1,8 @@
 //! This module provides functions for estimating the cycles cost of a call made to the EVM RPC canister's `request` method.
-use evm_rpc_canister_types::{EvmRpcCanister, RequestCostResult, RpcService};
+use evm_rpc_canister_types::{RequestCostResult, RpcService};
 
 use crate::conversions::nat_to_u128;
+use crate::evm_rpc_client::EvmRpcClient;
 
 /// Provides the cycles cost of a call made to the EVM RPC canister's `request` method.
 ///
@@ -10,7 +11,7 @@ use crate::conversions::nat_to_u128;
 /// * `rpc_service` - The RPC service used to interact with the EVM.
 /// * `json_rpc_payload` - The JSON-RPC payload to send.
 /// * `max_response_bytes` - The maximum number of response bytes to accept.
-/// * `evm_rpc` - The EVM RPC canister.
+/// * `evm_rpc` - The EVM RPC client, e.g. the EVM RPC canister.
 ///
 /// # Returns
 ///
@@ -19,7 +20,7 @@ pub async fn request_cost(
     rpc_service: RpcService,
     json_rpc_payload: String,
     max_response_bytes: u64,
-    evm_rpc: EvmRpcCanister,
+    evm_rpc: impl EvmRpcClient,
 ) -> u128 {
     // Get cycles cost
     let cycles_result = match evm_rpc
@@ -0,0 +1,194 @@
+//! This module provides functions for decoding the revert data of failed calls and transactions.
+//! It supports the built-in `Error(string)` and `Panic(uint256)` errors as well as custom errors
+//! defined in a contract's ABI.
+use std::fmt;
+
+use ethers_core::abi::{Contract, ParamType, Token};
+use ethers_core::types::U256;
+use ethers_core::utils::hex;
+
+/// The selector of `Error(string)`, emitted by `revert("reason")` and `require(cond, "reason")`.
+pub const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// The selector of `Panic(uint256)`, emitted by failing assertions, arithmetic overflows etc.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// The reason a call or transaction reverted, decoded from the revert data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RevertReason {
+    /// The call reverted with `Error(string)`.
+    Error(String),
+    /// The call reverted with `Panic(uint256)`.
+    Panic(PanicCode),
+    /// The call reverted with a custom error defined in the contract's ABI.
+    CustomError { name: String, args: Vec<Token> },
+    /// The revert data could not be decoded, or the provider did not return any.
+    Unknown(Vec<u8>),
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Error(reason) => write!(f, "{reason}"),
+            RevertReason::Panic(code) => write!(f, "panic: {code}"),
+            RevertReason::CustomError { name, args } => write!(f, "{name}({args:?})"),
+            RevertReason::Unknown(data) if data.is_empty() => write!(f, "unknown reason"),
+            RevertReason::Unknown(data) => write!(f, "unknown reason: 0x{}", hex::encode(data)),
+        }
+    }
+}
+
+/// The panic codes emitted by the Solidity compiler, see
+/// <https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require>.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PanicCode {
+    Generic,
+    AssertionFailed,
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidEnumValue,
+    InvalidStorageByteArray,
+    EmptyArrayPop,
+    ArrayIndexOutOfBounds,
+    OutOfMemory,
+    InvalidInternalFunction,
+    Other(U256),
+}
+
+impl From<U256> for PanicCode {
+    fn from(code: U256) -> Self {
+        if code > U256::from(u8::MAX) {
+            return PanicCode::Other(code);
+        }
+        match code.low_u32() {
+            0x00 => PanicCode::Generic,
+            0x01 => PanicCode::AssertionFailed,
+            0x11 => PanicCode::ArithmeticOverflow,
+            0x12 => PanicCode::DivisionByZero,
+            0x21 => PanicCode::InvalidEnumValue,
+            0x22 => PanicCode::InvalidStorageByteArray,
+            0x31 => PanicCode::EmptyArrayPop,
+            0x32 => PanicCode::ArrayIndexOutOfBounds,
+            0x41 => PanicCode::OutOfMemory,
+            0x51 => PanicCode::InvalidInternalFunction,
+            _ => PanicCode::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for PanicCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            PanicCode::Generic => "generic compiler panic",
+            PanicCode::AssertionFailed => "assertion failed",
+            PanicCode::ArithmeticOverflow => "arithmetic overflow or underflow",
+            PanicCode::DivisionByZero => "division or modulo by zero",
+            PanicCode::InvalidEnumValue => "conversion into an invalid enum value",
+            PanicCode::InvalidStorageByteArray => "incorrectly encoded storage byte array",
+            PanicCode::EmptyArrayPop => "pop on an empty array",
+            PanicCode::ArrayIndexOutOfBounds => "array index out of bounds",
+            PanicCode::OutOfMemory => "out of memory",
+            PanicCode::InvalidInternalFunction => "call to an invalid internal function",
+            PanicCode::Other(code) => return write!(f, "unknown panic code 0x{code:x}"),
+        };
+        write!(f, "{description}")
+    }
+}
+
+/// Decodes revert data into a `RevertReason`.
+///
+/// # Arguments
+///
+/// * `data` - The revert data, i.e. the 4 byte error selector followed by the ABI encoded arguments.
+/// * `abi` - The ABI of the reverting contract, used to decode custom errors.
+///
+/// # Returns
+///
+/// The decoded revert reason, or `RevertReason::Unknown` if the data does not match any known error.
+pub fn decode_revert_reason(data: &[u8], abi: Option<&Contract>) -> RevertReason {
+    if data.len() < 4 {
+        return RevertReason::Unknown(data.to_vec());
+    }
+    let (selector, args) = data.split_at(4);
+    if selector == ERROR_STRING_SELECTOR {
+        if let Ok(tokens) = ethers_core::abi::decode(&[ParamType::String], args) {
+            if let Some(Token::String(reason)) = tokens.into_iter().next() {
+                return RevertReason::Error(reason);
+            }
+        }
+    }
+    if selector == PANIC_SELECTOR {
+        if let Ok(tokens) = ethers_core::abi::decode(&[ParamType::Uint(256)], args) {
+            if let Some(Token::Uint(code)) = tokens.into_iter().next() {
+                return RevertReason::Panic(PanicCode::from(code));
+            }
+        }
+    }
+    for error in abi.into_iter().flat_map(Contract::errors) {
+        if error.signature()[..4] == *selector {
+            if let Ok(args) = error.decode(args) {
+                return RevertReason::CustomError {
+                    name: error.name.clone(),
+                    args,
+                };
+            }
+        }
+    }
+    RevertReason::Unknown(data.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_with_selector(selector: [u8; 4], args: &[Token]) -> Vec<u8> {
+        let mut data = selector.to_vec();
+        data.extend(ethers_core::abi::encode(args));
+        data
+    }
+
+    #[test]
+    fn should_decode_error_string() {
+        let data = encode_with_selector(
+            ERROR_STRING_SELECTOR,
+            &[Token::String(
+                "Only the coprocessor can call this function".to_string(),
+            )],
+        );
+        assert_eq!(
+            decode_revert_reason(&data, None),
+            RevertReason::Error("Only the coprocessor can call this function".to_string())
+        );
+    }
+
+    #[test]
+    fn should_decode_panic_code() {
+        let data = encode_with_selector(PANIC_SELECTOR, &[Token::Uint(U256::from(0x11))]);
+        assert_eq!(
+            decode_revert_reason(&data, None),
+            RevertReason::Panic(PanicCode::ArithmeticOverflow)
+        );
+    }
+
+    #[test]
+    fn should_decode_custom_error() {
+        let abi: Contract = serde_json::from_str(
+            r#"[{"type": "error", "name": "JobNotFound", "inputs": [{"name": "job_id", "type": "uint256"}]}]"#,
+        )
+        .unwrap();
+        let selector = ethers_core::utils::id("JobNotFound(uint256)");
+        let data = encode_with_selector(selector, &[Token::Uint(U256::from(7))]);
+
+        assert_eq!(
+            decode_revert_reason(&data, Some(&abi)),
+            RevertReason::CustomError {
+                name: "JobNotFound".to_string(),
+                args: vec![Token::Uint(U256::from(7))],
+            }
+        );
+        assert_eq!(
+            decode_revert_reason(&data, None),
+            RevertReason::Unknown(data.clone())
+        );
+    }
+}
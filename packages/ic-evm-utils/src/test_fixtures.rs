@@ -0,0 +1,16 @@
+//! Helpers shared by the unit tests of the crate.
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+
+/// Drives a future that never has to wait to completion, e.g. one of the `LocalSigner` or
+/// one using the `MockEvmRpcClient`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future should never be pending"),
+    }
+}
@@ -0,0 +1,26 @@
+#!/bin/bash
+set -e
+
+# the integration tests install the canister wasms in PocketIC
+cargo build --release --target wasm32-unknown-unknown --package chain_fusion --package evm_rpc_fake
+
+# download the PocketIC server matching the `pocket-ic` crate, unless one is provided
+if [ -z "$POCKET_IC_BIN" ]; then
+    export POCKET_IC_BIN="$(pwd)/target/pocket-ic"
+    if [ ! -f "$POCKET_IC_BIN" ]; then
+        if [ "$(uname)" == "Darwin" ]; then
+            platform="darwin"
+        else
+            platform="linux"
+        fi
+        curl -sSL "https://github.com/dfinity/pocketic/releases/download/4.0.0/pocket-ic-x86_64-$platform.gz" \
+            | gunzip > "$POCKET_IC_BIN"
+        chmod +x "$POCKET_IC_BIN"
+    fi
+fi
+
+# run the unit tests and the integration tests, which are ignored by a plain `cargo test`
+cargo test --workspace -- --include-ignored
+
+# run the tests of the contracts in `test/`
+forge test
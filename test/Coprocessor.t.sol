@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.13;
+
+import "forge-std/Test.sol";
+import "../contracts/Coprocessor.sol";
+
+contract CoprocessorTest is Test {
+    event NewJob(uint indexed job_id, uint payment);
+
+    address coprocessorAddress = makeAddr("coprocessor");
+    Coprocessor coprocessor;
+
+    function setUp() public {
+        coprocessor = new Coprocessor(coprocessorAddress);
+    }
+
+    function test_NewJobEmitsPayment() public {
+        vm.deal(address(this), 1 ether);
+
+        vm.expectEmit(true, false, false, true, address(coprocessor));
+        emit NewJob(0, 0.02 ether);
+        coprocessor.newJob{value: 0.02 ether}();
+
+        assertEq(coprocessorAddress.balance, 0.02 ether);
+    }
+
+    function test_BatchCallbackStoresEveryResult() public {
+        uint256[] memory jobIds = new uint256[](2);
+        jobIds[0] = 0;
+        jobIds[1] = 7;
+        bytes[] memory results = new bytes[](2);
+        results[0] = abi.encode("6765");
+        results[1] = abi.encode("10946");
+
+        vm.prank(coprocessorAddress);
+        coprocessor.batchCallback(jobIds, results);
+
+        assertEq(coprocessor.getResult(0), "6765");
+        assertEq(coprocessor.getResult(7), "10946");
+        assertEq(coprocessor.getResult(1), "");
+    }
+
+    function test_RevertWhen_BatchCallbackIsNotSentByCoprocessor() public {
+        uint256[] memory jobIds = new uint256[](1);
+        bytes[] memory results = new bytes[](1);
+        results[0] = abi.encode("6765");
+
+        vm.expectRevert("Only the coprocessor can call this function");
+        coprocessor.batchCallback(jobIds, results);
+    }
+
+    function test_RevertWhen_BatchCallbackLengthsDiffer() public {
+        uint256[] memory jobIds = new uint256[](2);
+        bytes[] memory results = new bytes[](1);
+        results[0] = abi.encode("6765");
+
+        vm.prank(coprocessorAddress);
+        vm.expectRevert("Job IDs and results must have the same length");
+        coprocessor.batchCallback(jobIds, results);
+    }
+}